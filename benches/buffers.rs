@@ -0,0 +1,82 @@
+//! Counts the allocations made while creating small thumbnails, with and without a buffer pool.
+//! Run it with `cargo bench --bench buffers`.
+
+use std::{alloc::{GlobalAlloc, Layout, System}, io::Cursor, sync::atomic::{AtomicUsize, Ordering}, time::Instant};
+
+use image::{DynamicImage, ImageOutputFormat, RgbImage};
+use thumbnails::{resize_image, resize_image_pooled, BufferPool, Chroma, Color, Error, FilterType, Frame, OutputFormat, ResizeMode, Shape, ThumbnailJob};
+
+/// Number of thumbnails created by each variant
+const ITERATIONS: usize = 500;
+
+/// The system allocator, counting the allocations and the allocated bytes
+struct Counting;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+static BYTES: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        BYTES.fetch_add(layout.size(), Ordering::Relaxed);
+        System.alloc(layout)
+    }
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        BYTES.fetch_add(new_size, Ordering::Relaxed);
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: Counting = Counting;
+
+/// Runs `f` ITERATIONS times and prints the number of allocations it took per thumbnail
+fn measure(name: &str, mut f: impl FnMut() -> Result<(), Error>) -> Result<(), Error> {
+    let allocations = ALLOCATIONS.load(Ordering::Relaxed);
+    let bytes = BYTES.load(Ordering::Relaxed);
+    let start = Instant::now();
+    for _ in 0..ITERATIONS {
+        f()?;
+    }
+    let elapsed = start.elapsed();
+    let allocations = ALLOCATIONS.load(Ordering::Relaxed) - allocations;
+    let bytes = BYTES.load(Ordering::Relaxed) - bytes;
+    println!("{name:<8} {:>8.1} allocations {:>10} bytes {:>8.1} µs per thumbnail",
+        allocations as f64 / ITERATIONS as f64, 
+        bytes / ITERATIONS, 
+        elapsed.as_micros() as f64 / ITERATIONS as f64);
+    Ok(())
+}
+
+fn main() -> Result<(), Error> {
+    let source = DynamicImage::ImageRgb8(RgbImage::from_fn(320, 240, |x, y| image::Rgb([x as u8, y as u8, (x ^ y) as u8])));
+    let mut input = Cursor::new(vec![]);
+    source.write_to(&mut input, ImageOutputFormat::Png)?;
+    let input = input.into_inner();
+    let job = ThumbnailJob { 
+        width: 32, height: 32, scale: None, 
+        filter: FilterType::Triangle, 
+        quality: 80, max_bytes: None, progressive: false, chroma: Chroma::Full, lossless: false, format: OutputFormat::Jpeg, 
+        mode: ResizeMode::Exact, allow_upscale: false, 
+        auto_orient: true, rotate: 0, flip_h: false, flip_v: false, 
+        grayscale: false, brightness: 0, contrast: 0.0, sharpen: 0.0,
+        preserve_metadata: false, to_srgb: false,
+        shape: Shape::default(), watermark: None, background: Color::default(), frame: Frame::default(), dpi: None,
+        max_pixels: None, passthrough: 0
+    };
+
+    measure("fresh", || {
+        let mut output = Cursor::new(vec![]);
+        resize_image(&input, &mut output, &job)
+    })?;
+    let pool = BufferPool::new(1);
+    measure("pooled", || {
+        let thumbnail = resize_image_pooled(&input, &job, &pool)?;
+        pool.give(thumbnail);
+        Ok(())
+    })
+}
@@ -0,0 +1,442 @@
+use std::{collections::HashMap, fs::File, io::{self, BufWriter, Read, Seek, SeekFrom, Write}, path::{Component, Path, PathBuf}, sync::Mutex, time::{SystemTime, UNIX_EPOCH}};
+
+use flate2::{read::DeflateDecoder, write::DeflateEncoder, Compression};
+
+use crate::{Error, error::WithPath};
+
+/// The kinds of archives into which the thumbnails can be written
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArchiveFormat {
+    /// A zip archive
+    Zip,
+    /// An (uncompressed) ustar archive
+    Tar,
+}
+impl ArchiveFormat {
+    /// The archive format designated by the extension of `path` ('zip' or 'tar', in any case), if any
+    pub fn from_path(path: &Path) -> Option<Self> {
+        match path.extension()?.to_str()?.to_ascii_lowercase().as_str() {
+            "zip" => Some(Self::Zip),
+            "tar" => Some(Self::Tar),
+            _     => None,
+        }
+    }
+}
+
+/// An archive into which the thumbnails are written instead of loose files. Its entries can be
+/// added from any thread: they are appended in the order in which they are added, which is the
+/// order of completion of the thumbnails (not the order of the scan). The archive is only valid
+/// once it has been `finish`ed.
+#[derive(Debug)]
+pub struct Archive {
+    /// The kind of archive
+    format: ArchiveFormat,
+    /// Should the entries of a zip archive be deflated ? (Otherwise they are stored as they are)
+    deflate: bool,
+    /// The file being written and what is known of its entries
+    state: Mutex<State>,
+}
+
+/// The part of an archive which changes as entries are added
+#[derive(Debug)]
+struct State {
+    /// The archive file
+    out: BufWriter<File>,
+    /// Number of bytes written so far
+    offset: u64,
+    /// The entries written so far (the zip central directory repeats them at the end)
+    entries: Vec<ZipEntry>,
+}
+
+/// What the central directory of a zip archive tells about one entry
+#[derive(Debug)]
+struct ZipEntry {
+    name: Vec<u8>,
+    method: u16,
+    time: u16,
+    date: u16,
+    crc: u32,
+    compressed: u32,
+    size: u32,
+    offset: u64,
+}
+
+impl Archive {
+    /// Creates the archive file at `path`. When `deflate` is set, the entries of a zip archive are
+    /// compressed (this is seldom worth it as the thumbnails are compressed already).
+    pub fn create(path: &Path, format: ArchiveFormat, deflate: bool) -> Result<Self, Error> {
+        let out = BufWriter::new(File::create(path).with_path(path)?);
+        Ok(Self { format, deflate, state: Mutex::new(State { out, offset: 0, entries: vec![] }) })
+    }
+    /// Adds the file `name` (a relative path, whose components become the folders of the archive)
+    /// holding `data` and last modified at `mtime`
+    pub fn add(&self, name: &Path, data: &[u8], mtime: SystemTime) -> Result<(), Error> {
+        let name = name.iter().map(|c| c.to_string_lossy()).collect::<Vec<_>>().join("/");
+        let mut state = self.state.lock().unwrap();
+        let written = match self.format {
+            ArchiveFormat::Zip => zip_entry(&mut state, &name, data, mtime, self.deflate)?,
+            ArchiveFormat::Tar => {
+                let header = tar_header(&name, data.len() as u64, mtime)?;
+                state.out.write_all(&header)?;
+                state.out.write_all(data)?;
+                let padding = (512 - data.len() % 512) % 512;
+                state.out.write_all(&[0; 512][..padding])?;
+                (header.len() + data.len() + padding) as u64
+            }
+        };
+        state.offset += written;
+        Ok(())
+    }
+    /// Completes the archive (with the central directory of a zip archive, or the end of archive
+    /// marker of a tar archive). Returns the size of the archive.
+    pub fn finish(&self) -> Result<u64, Error> {
+        let mut state = self.state.lock().unwrap();
+        match self.format {
+            ArchiveFormat::Zip => zip_end(&mut state)?,
+            ArchiveFormat::Tar => {
+                state.out.write_all(&[0; 1024])?;
+                state.offset += 1024;
+            }
+        }
+        state.out.flush()?;
+        Ok(state.offset)
+    }
+}
+
+/// Writes the local header and the data of one zip entry. Returns the number of bytes written.
+fn zip_entry(state: &mut State, name: &str, data: &[u8], mtime: SystemTime, deflate: bool) -> Result<u64, Error> {
+    let too_large = || io::Error::new(io::ErrorKind::InvalidInput, format!("{name} is too large for a zip archive"));
+    let crc = crc32fast::hash(data);
+    let deflated = if deflate {
+        let mut encoder = DeflateEncoder::new(vec![], Compression::default());
+        encoder.write_all(data)?;
+        Some(encoder.finish()?)
+    } else {
+        None
+    };
+    let (method, stored) = match &deflated {
+        Some(deflated) if deflated.len() < data.len() => (8, deflated.as_slice()),
+        _                                             => (0, data),
+    };
+    let (time, date) = dos_time(mtime);
+    let entry = ZipEntry {
+        name: name.as_bytes().to_vec(), method, time, date, crc,
+        compressed: u32::try_from(stored.len()).map_err(|_| too_large())?,
+        size: u32::try_from(data.len()).map_err(|_| too_large())?,
+        offset: state.offset,
+    };
+
+    let mut header = vec![];
+    header.extend(0x04034b50_u32.to_le_bytes());
+    header.extend(20_u16.to_le_bytes());          // version needed to extract
+    header.extend(0x0800_u16.to_le_bytes());      // the name is encoded in utf-8
+    header.extend(entry.method.to_le_bytes());
+    header.extend(entry.time.to_le_bytes());
+    header.extend(entry.date.to_le_bytes());
+    header.extend(entry.crc.to_le_bytes());
+    header.extend(entry.compressed.to_le_bytes());
+    header.extend(entry.size.to_le_bytes());
+    header.extend((entry.name.len() as u16).to_le_bytes());
+    header.extend(0_u16.to_le_bytes());           // no extra field
+    header.extend(&entry.name);
+    state.out.write_all(&header)?;
+    state.out.write_all(stored)?;
+    state.entries.push(entry);
+    Ok((header.len() + stored.len()) as u64)
+}
+
+/// Writes the central directory of a zip archive (in the zip64 format when the archive is too
+/// large or holds too many entries for the original one)
+fn zip_end(state: &mut State) -> Result<(), Error> {
+    let start = state.offset;
+    let mut directory = vec![];
+    for entry in state.entries.iter() {
+        let zip64 = entry.offset >= u32::MAX as u64;
+        directory.extend(0x02014b50_u32.to_le_bytes());
+        directory.extend(45_u16.to_le_bytes());   // version made by
+        directory.extend((if zip64 { 45_u16 } else { 20 }).to_le_bytes());
+        directory.extend(0x0800_u16.to_le_bytes());
+        directory.extend(entry.method.to_le_bytes());
+        directory.extend(entry.time.to_le_bytes());
+        directory.extend(entry.date.to_le_bytes());
+        directory.extend(entry.crc.to_le_bytes());
+        directory.extend(entry.compressed.to_le_bytes());
+        directory.extend(entry.size.to_le_bytes());
+        directory.extend((entry.name.len() as u16).to_le_bytes());
+        directory.extend((if zip64 { 12_u16 } else { 0 }).to_le_bytes());
+        directory.extend([0; 10]);                // comment, disk, internal and external attributes
+        directory.extend((if zip64 { u32::MAX } else { entry.offset as u32 }).to_le_bytes());
+        directory.extend(&entry.name);
+        if zip64 {
+            directory.extend(0x0001_u16.to_le_bytes());
+            directory.extend(8_u16.to_le_bytes());
+            directory.extend(entry.offset.to_le_bytes());
+        }
+    }
+    let count = state.entries.len() as u64;
+    let size  = directory.len() as u64;
+    let mut end = vec![];
+    if count >= u16::MAX as u64 || start >= u32::MAX as u64 || size >= u32::MAX as u64 {
+        let record = start + size;
+        end.extend(0x06064b50_u32.to_le_bytes());
+        end.extend(44_u64.to_le_bytes());         // size of the rest of the record
+        end.extend(45_u16.to_le_bytes());
+        end.extend(45_u16.to_le_bytes());
+        end.extend([0; 8]);                       // disk numbers
+        end.extend(count.to_le_bytes());
+        end.extend(count.to_le_bytes());
+        end.extend(size.to_le_bytes());
+        end.extend(start.to_le_bytes());
+        end.extend(0x07064b50_u32.to_le_bytes()); // the locator of the zip64 record
+        end.extend(0_u32.to_le_bytes());
+        end.extend(record.to_le_bytes());
+        end.extend(1_u32.to_le_bytes());
+    }
+    end.extend(0x06054b50_u32.to_le_bytes());
+    end.extend([0; 4]);                           // disk numbers
+    end.extend((count.min(u16::MAX as u64) as u16).to_le_bytes());
+    end.extend((count.min(u16::MAX as u64) as u16).to_le_bytes());
+    end.extend((size.min(u32::MAX as u64) as u32).to_le_bytes());
+    end.extend((start.min(u32::MAX as u64) as u32).to_le_bytes());
+    end.extend(0_u16.to_le_bytes());              // no comment
+    state.out.write_all(&directory)?;
+    state.out.write_all(&end)?;
+    state.offset += size + end.len() as u64;
+    Ok(())
+}
+
+/// The ustar header of a file called `name`, `size` bytes long and last modified at `mtime`.
+/// The names longer than 100 bytes are split between the prefix and the name fields (which fails
+/// for the names which cannot be split that way).
+fn tar_header(name: &str, size: u64, mtime: SystemTime) -> Result<[u8; 512], Error> {
+    let (prefix, name) = if name.len() <= 100 {
+        ("", name)
+    } else {
+        name.char_indices()
+            .filter(|(i, c)| *c == '/' && *i <= 155 && name.len() - i - 1 <= 100)
+            .map(|(i, _)| (&name[..i], &name[i + 1..]))
+            .next()
+            .ok_or_else(|| Error::ArchiveEntry(PathBuf::from(name)))?
+    };
+    let mtime = mtime.duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or_default();
+    let mut header = [0_u8; 512];
+    let mut field = |offset: usize, value: &[u8]| header[offset..offset + value.len()].copy_from_slice(value);
+    field(0, name.as_bytes());
+    field(100, b"0000644\0");
+    field(108, b"0000000\0");
+    field(116, b"0000000\0");
+    field(124, format!("{size:011o}\0").as_bytes());
+    field(136, format!("{:011o}\0", mtime.min(0o77777777777)).as_bytes());
+    field(148, b"        ");
+    field(156, b"0");
+    field(257, b"ustar\0");
+    field(263, b"00");
+    field(345, prefix.as_bytes());
+    let checksum = header.iter().map(|&b| b as u32).sum::<u32>();
+    header[148..156].copy_from_slice(format!("{checksum:06o}\0 ").as_bytes());
+    Ok(header)
+}
+
+/// The MS-DOS time and date (in UTC) used by the zip archives. The moments before 1980 (which
+/// these cannot represent) are clamped to 1980-01-01.
+fn dos_time(mtime: SystemTime) -> (u16, u16) {
+    let secs = mtime.duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or_default().max(315532800);
+    let (days, secs) = (secs / 86400, secs % 86400);
+    // converts the number of days since 1970-01-01 to a civil date (see H. Hinnant's algorithms)
+    let z = days as i64 + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    let time = ((secs / 3600) << 11) | ((secs % 3600 / 60) << 5) | ((secs % 60) / 2);
+    let date = (((year - 1980).min(127) as u64) << 9) | ((month as u64) << 5) | day as u64;
+    (time as u16, date as u16)
+}
+
+/// A zip archive from which the source images are read (rather than from a folder). The images
+/// are named after the path of the archive followed by their path inside the archive (e.g. 
+/// 'scans.zip/2023/a.tif'). Their entries can be read from any thread.
+#[derive(Debug)]
+pub struct ZipSource {
+    /// Path to the archive
+    path: PathBuf,
+    /// The archive file
+    file: Mutex<File>,
+    /// The file entries of the archive, in the order of its central directory
+    entries: Vec<SourceEntry>,
+    /// The index of each entry, by name
+    index: HashMap<PathBuf, usize>,
+}
+
+/// What the central directory of a zip archive tells about one of the entries to read
+#[derive(Debug)]
+struct SourceEntry {
+    /// Path of the entry inside the archive
+    name: PathBuf,
+    /// Is the entry deflated ? (Otherwise it is stored)
+    deflated: bool,
+    /// Size of the entry in the archive
+    compressed: u64,
+    /// Size of the entry once decompressed
+    size: u64,
+    /// Offset of the local header of the entry
+    offset: u64,
+}
+
+impl ZipSource {
+    /// Opens the zip archive at `path` and reads its central directory. The folders, the
+    /// encrypted entries and those compressed otherwise than deflated are left out, just like
+    /// the entries whose path would lead out of the destination folder (e.g. '../a.jpg' or
+    /// '/a.jpg').
+    pub fn open(path: &Path) -> Result<Self, Error> {
+        let invalid = || Error::Decode(format!("{} is not a valid zip archive", path.display()));
+        let mut file = File::open(path).with_path(path)?;
+        let length = file.metadata()?.len();
+        // the end of central directory record lies in the last 64KiB (it ends with a comment)
+        let tail_start = length.saturating_sub(65557);
+        let mut tail = vec![];
+        file.seek(SeekFrom::Start(tail_start))?;
+        file.read_to_end(&mut tail)?;
+        let eocd = (0..tail.len().saturating_sub(21)).rev()
+            .find(|&i| tail[i..i + 4] == 0x06054b50_u32.to_le_bytes())
+            .ok_or_else(invalid)?;
+        let mut count  = le(&tail[eocd + 10..eocd + 12]);
+        let mut size   = le(&tail[eocd + 12..eocd + 16]);
+        let mut start  = le(&tail[eocd + 16..eocd + 20]);
+        // the zip64 locator precedes the end of central directory record
+        if eocd >= 20 && tail[eocd - 20..eocd - 16] == 0x07064b50_u32.to_le_bytes() {
+            let record = le(&tail[eocd - 12..eocd - 4]);
+            let mut zip64 = [0; 56];
+            file.seek(SeekFrom::Start(record))?;
+            file.read_exact(&mut zip64)?;
+            if zip64[..4] != 0x06064b50_u32.to_le_bytes() {
+                return Err(invalid());
+            }
+            count = le(&zip64[32..40]);
+            size  = le(&zip64[40..48]);
+            start = le(&zip64[48..56]);
+        }
+
+        let mut directory = vec![0; usize::try_from(size).map_err(|_| invalid())?];
+        file.seek(SeekFrom::Start(start))?;
+        file.read_exact(&mut directory)?;
+        let mut entries = vec![];
+        let mut at = 0;
+        for _ in 0..count {
+            let header = directory.get(at..at + 46).ok_or_else(invalid)?;
+            if header[..4] != 0x02014b50_u32.to_le_bytes() {
+                return Err(invalid());
+            }
+            let flags  = le(&header[8..10]);
+            let method = le(&header[10..12]);
+            let mut compressed = le(&header[20..24]);
+            let mut size       = le(&header[24..28]);
+            let name_len  = le(&header[28..30]) as usize;
+            let extra_len = le(&header[30..32]) as usize;
+            let comment   = le(&header[32..34]) as usize;
+            let mut offset = le(&header[42..46]);
+            let name  = directory.get(at + 46..at + 46 + name_len).ok_or_else(invalid)?;
+            let mut extra = directory.get(at + 46 + name_len..at + 46 + name_len + extra_len).ok_or_else(invalid)?;
+            at += 46 + name_len + extra_len + comment;
+            // the zip64 extra field holds the values which do not fit in the header, in this order
+            while extra.len() >= 4 {
+                let (id, len) = (le(&extra[..2]), le(&extra[2..4]) as usize);
+                let mut values = extra.get(4..4 + len).ok_or_else(invalid)?;
+                if id == 1 {
+                    for value in [&mut size, &mut compressed, &mut offset] {
+                        if *value == u32::MAX as u64 && values.len() >= 8 {
+                            *value = le(&values[..8]);
+                            values = &values[8..];
+                        }
+                    }
+                }
+                extra = &extra[4 + len..];
+            }
+            let name = String::from_utf8_lossy(name);
+            match () {
+                _ if name.ends_with('/') => continue,
+                _ if escapes(&name)      => log::warn!("ignoring {}/{name}: its path leads out of the archive", path.display()),
+                _ if flags & 1 != 0      => log::warn!("ignoring {}/{name}: it is encrypted", path.display()),
+                _ if method != 0 && method != 8 => log::warn!("ignoring {}/{name}: unsupported compression method {method}", path.display()),
+                _ => entries.push(SourceEntry { name: PathBuf::from(name.as_ref()), deflated: method == 8, compressed, size, offset }),
+            }
+        }
+        let index = entries.iter().enumerate().map(|(i, e)| (e.name.clone(), i)).collect();
+        Ok(Self { path: path.to_path_buf(), file: Mutex::new(file), entries, index })
+    }
+    /// Path to the archive
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+    /// The names of the images of the archive (i.e. the path of the archive followed by their
+    /// path inside the archive), in the order of the archive
+    pub fn files(&self) -> impl Iterator<Item = PathBuf> + '_ {
+        self.entries.iter().map(|e| self.path.join(&e.name))
+    }
+    /// The (decompressed) size of the image called `name` (see `files`)
+    pub fn size(&self, name: &Path) -> Option<u64> {
+        let inner = name.strip_prefix(&self.path).unwrap_or(name);
+        self.index.get(inner).map(|&i| self.entries[i].size)
+    }
+    /// Reads the image called `name` (see `files`) into the (empty) buffer `content`
+    pub fn read(&self, name: &Path, content: &mut Vec<u8>) -> Result<(), Error> {
+        let inner = name.strip_prefix(&self.path).unwrap_or(name);
+        let entry = self.index.get(inner).map(|&i| &self.entries[i])
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, format!("{} is not in the archive", name.display())))?;
+        let mut stored = vec![0; entry.compressed as usize];
+        {
+            let mut file = self.file.lock().unwrap();
+            let mut header = [0; 30];
+            file.seek(SeekFrom::Start(entry.offset))?;
+            file.read_exact(&mut header)?;
+            let skip = le(&header[26..28]) + le(&header[28..30]);
+            file.seek(SeekFrom::Current(skip as i64))?;
+            file.read_exact(&mut stored)?;
+        }
+        if entry.deflated {
+            DeflateDecoder::new(stored.as_slice()).read_to_end(content)?;
+        } else {
+            content.extend_from_slice(&stored);
+        }
+        Ok(())
+    }
+}
+
+/// Tells whether the entry `name` of a zip archive would be written outside of the folder in
+/// which the archive is extracted
+fn escapes(name: &str) -> bool {
+    Path::new(name).components().any(|c| matches!(c, Component::ParentDir | Component::RootDir | Component::Prefix(_)))
+}
+
+/// Reads the little endian integer held in `bytes`
+fn le(bytes: &[u8]) -> u64 {
+    bytes.iter().rev().fold(0, |value, &b| value << 8 | b as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Scan, Stats, prepare_archive, testing::{TempDir, job}};
+
+    #[test]
+    fn the_entries_leading_out_of_the_archive_are_left_out() {
+        let dir = TempDir::new("zipslip");
+        let path = dir.path().join("in.zip");
+        let archive = Archive::create(&path, ArchiveFormat::Zip, false).unwrap();
+        for name in ["a.bmp", "sub/b.bmp", "../../escaped.bmp", "/x/y.bmp", "sub/../../c.bmp"] {
+            archive.add(Path::new(name), b"BM", SystemTime::now()).unwrap();
+        }
+        archive.finish().unwrap();
+
+        let source = ZipSource::open(&path).unwrap();
+        assert_eq!(source.files().collect::<Vec<_>>(), [path.join("a.bmp"), path.join("sub").join("b.bmp")]);
+        let out = dir.path().join("out");
+        let list = prepare_archive(&source, out.to_str().unwrap(), &Scan::default(), &[job(4, 4)], &Stats::new()).unwrap();
+        assert_eq!(list.iter().map(|task| task.dst[0].clone()).collect::<Vec<_>>(), [out.join("a.jpg"), out.join("sub").join("b.jpg")]);
+    }
+}
@@ -0,0 +1,137 @@
+//! A minimal color management module converting the thumbnails to sRGB. Only the matrix/TRC ICC
+//! profiles (the three primaries and the tone curve of each channel) are understood, which is
+//! what the RGB working spaces (Adobe RGB, Display P3, ProPhoto, ...) are made of. The profiles
+//! based on lookup tables are left to a full blown color management library.
+
+use image::RgbaImage;
+
+/// The conversion from the profile connection space (XYZ, D50) to linear sRGB: the inverse of
+/// the Bradford-adapted sRGB primaries
+const XYZ_TO_SRGB: [[f32; 3]; 3] = [
+    [ 3.133_856, -1.616_867, -0.490_615],
+    [-0.978_768,  1.916_142,  0.033_454],
+    [ 0.071_945, -0.228_991,  1.405_243],
+];
+
+/// The number of entries of the table encoding linear values to sRGB
+const ENCODING_STEPS: usize = 4096;
+
+/// The conversion of the pixels of one color profile to sRGB
+#[derive(Debug, Clone)]
+pub(crate) struct ToSrgb {
+    /// The linear value of each 8-bit value of each channel (as per the tone curves)
+    curves: [[f32; 256]; 3],
+    /// The conversion of linear values of the profile to linear sRGB values
+    matrix: [[f32; 3]; 3],
+    /// The 8-bit sRGB encoding of `ENCODING_STEPS` evenly spaced linear values
+    encoding: Vec<u8>,
+}
+impl ToSrgb {
+    /// Builds the conversion from the RGB color space described by the ICC profile `icc`. Returns
+    /// `None` when the profile is not a matrix/TRC RGB profile (or cannot be read).
+    pub(crate) fn new(icc: &[u8]) -> Option<Self> {
+        if icc.get(16..20)? != b"RGB " || icc.get(20..24)? != b"XYZ " {
+            return None;
+        }
+        let primaries = [b"rXYZ", b"gXYZ", b"bXYZ"].map(|sig| tag(icc, sig).and_then(xyz));
+        let curves    = [b"rTRC", b"gTRC", b"bTRC"].map(|sig| tag(icc, sig).and_then(curve));
+        let [Some(r), Some(g), Some(b)] = primaries else { return None };
+        let [Some(rc), Some(gc), Some(bc)] = curves else { return None };
+
+        let to_xyz = [[r[0], g[0], b[0]], [r[1], g[1], b[1]], [r[2], g[2], b[2]]];
+        let mut matrix = [[0.0; 3]; 3];
+        for (i, row) in matrix.iter_mut().enumerate() {
+            for (j, m) in row.iter_mut().enumerate() {
+                *m = (0..3).map(|k| XYZ_TO_SRGB[i][k] * to_xyz[k][j]).sum();
+            }
+        }
+        let encoding = (0..ENCODING_STEPS).map(|i| {
+            let v = i as f32 / (ENCODING_STEPS - 1) as f32;
+            let c = if v <= 0.003_130_8 { v * 12.92 } else { 1.055 * v.powf(1.0 / 2.4) - 0.055 };
+            (c * 255.0).round().clamp(0.0, 255.0) as u8
+        }).collect();
+        Some(Self { curves: [rc, gc, bc], matrix, encoding })
+    }
+    /// Converts the pixels of `im` (in the color space of the profile) to sRGB, in place. The
+    /// colors lying out of the sRGB gamut are clipped and the alpha channel is left untouched.
+    pub(crate) fn apply(&self, im: &mut RgbaImage) {
+        let scale = (ENCODING_STEPS - 1) as f32;
+        for pixel in im.pixels_mut() {
+            let linear = [0, 1, 2].map(|c| self.curves[c][pixel[c] as usize]);
+            for (c, row) in self.matrix.iter().enumerate() {
+                let v = row[0] * linear[0] + row[1] * linear[1] + row[2] * linear[2];
+                pixel[c] = self.encoding[(v.clamp(0.0, 1.0) * scale).round() as usize];
+            }
+        }
+    }
+}
+
+/// The data of the tag `sig` of the ICC profile `icc`
+fn tag<'a>(icc: &'a [u8], sig: &[u8; 4]) -> Option<&'a [u8]> {
+    let count = u32::from_be_bytes(icc.get(128..132)?.try_into().ok()?) as usize;
+    (0..count).find_map(|i| {
+        let entry = icc.get(132 + 12 * i..144 + 12 * i)?;
+        if &entry[..4] != sig {
+            return None;
+        }
+        let offset = u32::from_be_bytes(entry[4..8].try_into().ok()?) as usize;
+        let size   = u32::from_be_bytes(entry[8..12].try_into().ok()?) as usize;
+        icc.get(offset..offset.checked_add(size)?)
+    })
+}
+
+/// Reads the s15Fixed16 number at `at` in `data`
+fn fixed(data: &[u8], at: usize) -> Option<f32> {
+    Some(i32::from_be_bytes(data.get(at..at + 4)?.try_into().ok()?) as f32 / 65536.0)
+}
+
+/// Reads the color of an `XYZ ` tag
+fn xyz(data: &[u8]) -> Option<[f32; 3]> {
+    if data.get(..4)? != b"XYZ " {
+        return None;
+    }
+    Some([fixed(data, 8)?, fixed(data, 12)?, fixed(data, 16)?])
+}
+
+/// Reads a tone curve (a `curv` or `para` tag) and tabulates the linear value of each 8-bit value
+fn curve(data: &[u8]) -> Option<[f32; 256]> {
+    let eval: Box<dyn Fn(f32) -> f32> = match data.get(..4)? {
+        b"curv" => {
+            let count = u32::from_be_bytes(data.get(8..12)?.try_into().ok()?) as usize;
+            let entries = data.get(12..12 + 2 * count)?.chunks(2)
+                .map(|e| u16::from_be_bytes([e[0], e[1]]) as f32 / 65535.0)
+                .collect::<Vec<_>>();
+            match count {
+                0 => Box::new(|x| x),
+                1 => {
+                    let gamma = u16::from_be_bytes(data.get(12..14)?.try_into().ok()?) as f32 / 256.0;
+                    Box::new(move |x| x.powf(gamma))
+                },
+                n => Box::new(move |x| {
+                    let at = x * (n - 1) as f32;
+                    let i  = (at as usize).min(n - 2);
+                    entries[i] + (entries[i + 1] - entries[i]) * (at - i as f32)
+                }),
+            }
+        },
+        b"para" => {
+            let kind = u16::from_be_bytes(data.get(8..10)?.try_into().ok()?);
+            let n = match kind { 0 => 1, 1 => 3, 2 => 4, 3 => 5, 4 => 7, _ => return None };
+            let p = (0..n).map(|i| fixed(data, 12 + 4 * i)).collect::<Option<Vec<_>>>()?;
+            let g = p[0];
+            match kind {
+                0 => Box::new(move |x| x.powf(g)),
+                1 => Box::new(move |x| if x >= -p[2] / p[1] { (p[1] * x + p[2]).powf(g) } else { 0.0 }),
+                2 => Box::new(move |x| if x >= -p[2] / p[1] { (p[1] * x + p[2]).powf(g) + p[3] } else { p[3] }),
+                3 => Box::new(move |x| if x >= p[4] { (p[1] * x + p[2]).powf(g) } else { p[3] * x }),
+                _ => Box::new(move |x| if x >= p[4] { (p[1] * x + p[2]).powf(g) + p[5] } else { p[3] * x + p[6] }),
+            }
+        },
+        _ => return None,
+    };
+    let mut table = [0.0; 256];
+    for (v, t) in table.iter_mut().enumerate() {
+        *t = eval(v as f32 / 255.0).clamp(0.0, 1.0);
+    }
+    Some(table)
+}
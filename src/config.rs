@@ -0,0 +1,142 @@
+//! The configuration files: the options which would otherwise be repeated on every run, kept in
+//! a TOML file.
+
+use std::{fmt, fs, path::{Path, PathBuf}};
+
+use crate::{Error, error::WithPath};
+
+/// A value of a configuration file
+#[derive(Debug, Clone, PartialEq)]
+pub enum ConfigValue {
+    String(String),
+    Integer(i64),
+    Float(f64),
+    Boolean(bool),
+    Array(Vec<ConfigValue>),
+}
+impl fmt::Display for ConfigValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::String(s)  => write!(f, "{s}"),
+            Self::Integer(i) => write!(f, "{i}"),
+            Self::Float(x)   => write!(f, "{x}"),
+            Self::Boolean(b) => write!(f, "{b}"),
+            Self::Array(vs)  => {
+                let vs = vs.iter().map(|v| v.to_string()).collect::<Vec<_>>();
+                write!(f, "{}", vs.join(","))
+            },
+        }
+    }
+}
+
+/// Reads the configuration file at `path` and returns its `key = value` pairs, in the order of
+/// the file. The file is written in TOML, but only its simplest form is understood: one pair per
+/// line, bare keys, and values which are strings (basic or literal), integers, floats, booleans
+/// or arrays of those written on one single line. The tables, dotted keys, dates and multi-line
+/// values are rejected with an `InvalidConfig` error, as are the keys given more than once.
+pub fn read_config(path: &Path) -> Result<Vec<(String, ConfigValue)>, Error> {
+    let text = fs::read_to_string(path).with_path(path)?;
+    let mut pairs = vec![];
+    for (i, line) in text.lines().enumerate() {
+        let invalid = |reason: &str| Error::InvalidConfig(PathBuf::from(path), i + 1, reason.to_string());
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        if line.starts_with('[') {
+            return Err(invalid("tables are not supported"));
+        }
+        let (key, rest) = line.split_once('=').ok_or_else(|| invalid("expected 'key = value'"))?;
+        let key = key.trim();
+        if key.is_empty() || !key.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-') {
+            return Err(invalid(&format!("invalid key '{key}' (only bare keys are supported)")));
+        }
+        if pairs.iter().any(|(k, _)| k == key) {
+            return Err(invalid(&format!("the key '{key}' is given more than once")));
+        }
+        let (value, rest) = value(rest.trim_start()).map_err(|reason| invalid(&reason))?;
+        let rest = rest.trim_start();
+        if !rest.is_empty() && !rest.starts_with('#') {
+            return Err(invalid(&format!("unexpected '{rest}' after the value")));
+        }
+        pairs.push((key.to_string(), value));
+    }
+    Ok(pairs)
+}
+
+/// Parses the value at the start of `s` and returns it along with what follows it
+fn value(s: &str) -> Result<(ConfigValue, &str), String> {
+    if let Some(rest) = s.strip_prefix('"') {
+        basic_string(rest)
+    } else if let Some(rest) = s.strip_prefix('\'') {
+        let end = rest.find('\'').ok_or("unterminated string")?;
+        Ok((ConfigValue::String(rest[..end].to_string()), &rest[end + 1..]))
+    } else if let Some(mut rest) = s.strip_prefix('[') {
+        let mut values = vec![];
+        loop {
+            rest = rest.trim_start();
+            if let Some(rest) = rest.strip_prefix(']') {
+                return Ok((ConfigValue::Array(values), rest));
+            }
+            let (v, after) = value(rest)?;
+            values.push(v);
+            rest = after.trim_start();
+            match rest.chars().next() {
+                Some(',') => rest = &rest[1..],
+                Some(']') => {},
+                _         => return Err("expected ',' or ']' in the array".to_string()),
+            }
+        }
+    } else {
+        let end = s.find(|c: char| c.is_whitespace() || c == ',' || c == ']' || c == '#').unwrap_or(s.len());
+        let (word, rest) = s.split_at(end);
+        let value = match word {
+            "true"  => ConfigValue::Boolean(true),
+            "false" => ConfigValue::Boolean(false),
+            _ => {
+                let number = word.replace('_', "");
+                if let Ok(i) = number.parse() {
+                    ConfigValue::Integer(i)
+                } else if let Ok(x) = number.parse() {
+                    ConfigValue::Float(x)
+                } else {
+                    return Err(format!("invalid value '{word}'"));
+                }
+            },
+        };
+        Ok((value, rest))
+    }
+}
+
+/// Parses the basic string at the start of `s` (past its opening quote) and returns it along
+/// with what follows its closing quote
+fn basic_string(s: &str) -> Result<(ConfigValue, &str), String> {
+    let mut string = String::new();
+    let mut chars  = s.char_indices();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '"'  => return Ok((ConfigValue::String(string), &s[i + 1..])),
+            '\\' => {
+                let escaped = match chars.next().map(|(_, c)| c) {
+                    Some('b')  => '\u{8}',
+                    Some('t')  => '\t',
+                    Some('n')  => '\n',
+                    Some('f')  => '\u{c}',
+                    Some('r')  => '\r',
+                    Some('"')  => '"',
+                    Some('\\') => '\\',
+                    Some(u @ ('u' | 'U')) => {
+                        let digits = (0..if u == 'u' { 4 } else { 8 }).filter_map(|_| chars.next().map(|(_, c)| c)).collect::<String>();
+                        u32::from_str_radix(&digits, 16).ok().and_then(char::from_u32)
+                            .ok_or_else(|| format!("invalid unicode escape '\\{u}{digits}'"))?
+                    },
+                    Some(c) => return Err(format!("invalid escape '\\{c}'")),
+                    None    => break,
+                };
+                string.push(escaped);
+            },
+            c => string.push(c),
+        }
+    }
+    Err("unterminated string".to_string())
+}
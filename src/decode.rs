@@ -0,0 +1,337 @@
+//! Decoding the source images into an 8-bit representation which can be resized and encoded 
+//! by any of the output formats.
+
+use std::{io::Cursor, path::Path};
+
+use image::{codecs::{gif::GifDecoder, png::PngDecoder, webp::WebPDecoder}, AnimationDecoder, DynamicImage, ImageBuffer, ImageError, ImageFormat, Rgb};
+use tiff::{decoder::{Decoder, DecodingResult}, ColorType, TiffError};
+
+use crate::{Error, Frame};
+
+/// Reads the dimensions of the image `input` from its header, without decoding it
+pub(crate) fn dimensions(input: &[u8]) -> Option<(u32, u32)> {
+    let reader = image::io::Reader::new(Cursor::new(input)).with_guessed_format().ok()?;
+    match reader.into_dimensions() {
+        Ok(dimensions) => Some(dimensions),
+        // typically a CMYK image, which only the tiff library can decode
+        Err(_) => Decoder::new(Cursor::new(input)).ok()?.dimensions().ok(),
+    }
+}
+
+/// Makes sure the image `input` has no more than `max_pixels` pixels before it gets decoded. 
+/// This protects against the huge images (and decompression bombs) which would exhaust the 
+/// memory once decoded. The images whose dimensions cannot be read are left to the decoder.
+pub(crate) fn check_size(input: &[u8], max_pixels: u64) -> Result<(), Error> {
+    match dimensions(input) {
+        Some((w, h)) if w as u64 * h as u64 > max_pixels => Err(Error::TooManyPixels(w, h, max_pixels)),
+        _ => Ok(()),
+    }
+}
+
+/// Decodes the image `input` and converts it to 8 bits per channel.
+/// 
+/// * CMYK TIFF images (which the image library cannot decode) are converted to RGB,
+/// * 16-bit (and floating point) images are converted to 8 bits, keeping their channels: 
+///   gray stays gray, and the alpha channel is kept if there is one.
+/// 
+/// The documents which this build cannot render (see `unsupported`) are rejected with an 
+/// explicit error.
+pub(crate) fn decode(input: &[u8]) -> Result<DynamicImage, Error> {
+    if let Some((format, dependency)) = unsupported(input) {
+        return Err(Error::UnsupportedInputFormat(format, dependency));
+    }
+    if image::guess_format(input).ok() == Some(ImageFormat::Tiff) {
+        if let Some(im) = decode_cmyk_tiff(input)? {
+            return Ok(im);
+        }
+    }
+    let im = match image::guess_format(input) {
+        Ok(_)    => image::load_from_memory(input)?,
+        // tga images have no signature: they are only recognized by their decoder
+        Err(err) => image::load_from_memory_with_format(input, ImageFormat::Tga).map_err(|_| err)?,
+    };
+    Ok(match im {
+        DynamicImage::ImageLuma8(_) | DynamicImage::ImageLumaA8(_) | DynamicImage::ImageRgb8(_) | DynamicImage::ImageRgba8(_) => im,
+        DynamicImage::ImageLuma16(_)  => DynamicImage::ImageLuma8(im.to_luma8()),
+        DynamicImage::ImageLumaA16(_) => DynamicImage::ImageLumaA8(im.to_luma_alpha8()),
+        DynamicImage::ImageRgb16(_) | DynamicImage::ImageRgb32F(_) => DynamicImage::ImageRgb8(im.to_rgb8()),
+        _ => DynamicImage::ImageRgba8(im.to_rgba8()),
+    })
+}
+
+/// The format of `input` when it is a document this build cannot render, along with the 
+/// dependency it would take to render it: 
+/// 
+/// * HEIC photos would be decoded by libheif (through the libheif-rs crate), which requires the
+///   native libheif library, and AVIF images by the dav1d decoder (which the `avif-decoder`
+///   feature of the image crate pulls),
+/// * SVG documents would be rasterized by the resvg crate,
+/// * the first page of PDF documents would be rendered by pdfium (through the pdfium-render 
+///   crate), which requires the native pdfium library,
+/// * a frame of the videos (mp4, mov, mkv, webm, avi) would be decoded by ffmpeg (through the 
+///   ffmpeg-next crate), which requires the native ffmpeg libraries.
+fn unsupported(input: &[u8]) -> Option<(&'static str, &'static str)> {
+    if let Some(format) = heif(input) {
+        Some((format, if format == "avif" { "dav1d" } else { "libheif-rs" }))
+    } else if is_svg(input) {
+        Some(("svg", "resvg"))
+    } else if input.starts_with(b"%PDF-") {
+        Some(("pdf", "pdfium-render"))
+    } else if is_video(input) {
+        Some(("video", "ffmpeg-next"))
+    } else {
+        None
+    }
+}
+
+/// Tells whether `input` is a HEIF image, either a HEIC photo or an AVIF image, as per the brands
+/// of its `ftyp` box
+fn heif(input: &[u8]) -> Option<&'static str> {
+    const HEIC_BRANDS: [&[u8]; 10] = [b"heic", b"heix", b"hevc", b"hevx", b"heim", b"heis", b"hevm", b"hevs", b"mif1", b"msf1"];
+    if input.get(4..8)? != b"ftyp" {
+        return None;
+    }
+    let size  = u32::from_be_bytes(input.get(..4)?.try_into().ok()?) as usize;
+    let ftyp   = input.get(8..size.max(12).min(input.len()))?;
+    // the major brand, then the minor version and the compatible brands
+    let brands = ftyp.chunks_exact(4).enumerate().filter(|(i, _)| *i != 1).map(|(_, brand)| brand).collect::<Vec<_>>();
+    match () {
+        _ if brands.iter().any(|b| *b == b"avif" || *b == b"avis") => Some("avif"),
+        _ if brands.iter().any(|b| HEIC_BRANDS.contains(b))         => Some("heic"),
+        _                                                          => None,
+    }
+}
+
+/// Tells whether `input` is a video: an ISO base media file (mp4, mov) which is not an image 
+/// (heic, avif), a matroska (mkv, webm) or an avi file
+fn is_video(input: &[u8]) -> bool {
+    const IMAGE_BRANDS: [&[u8]; 6] = [b"heic", b"heix", b"mif1", b"msf1", b"avif", b"avis"];
+    match input {
+        [_, _, _, _, b'f', b't', b'y', b'p', brand @ ..] if brand.len() >= 4 => !IMAGE_BRANDS.contains(&&brand[..4]),
+        [0x1a, 0x45, 0xdf, 0xa3, ..] => true,
+        [b'R', b'I', b'F', b'F', _, _, _, _, b'A', b'V', b'I', b' ', ..] => true,
+        _ => false,
+    }
+}
+
+/// Tells whether `input` is an SVG document (possibly preceded by an xml declaration, comments
+/// or a doctype)
+fn is_svg(input: &[u8]) -> bool {
+    let head = &input[..input.len().min(1024)];
+    let head = String::from_utf8_lossy(head);
+    let head = head.trim_start_matches('\u{feff}').trim_start();
+    (head.starts_with("<?xml") || head.starts_with("<!") || head.starts_with("<svg")) && head.contains("<svg")
+}
+
+/// Decodes the requested `frame` of the image `input` (or all of them), each converted as per
+/// `decode`. 
+/// 
+/// The frames of animated GIF, PNG (APNG) and WebP images are fully composited (that is, each of
+/// them is what a viewer would display at that point of the animation). The frames of a TIFF 
+/// image are its pages. Any other image has a single frame: the whole image. The first frame is
+/// always decoded with `decode`, as if the image was not animated. An animation without any frame
+/// is rejected with a `Decode` error.
+pub(crate) fn decode_frames(input: &[u8], frame: Frame) -> Result<Vec<DynamicImage>, Error> {
+    if frame == Frame::Index(0) {
+        return Ok(vec![decode(input)?]);
+    }
+    let missing = |index| Error::Decode(format!("the image has no frame {index}"));
+    let frames = match image::guess_format(input).ok() {
+        Some(ImageFormat::Gif)  => GifDecoder::new(Cursor::new(input))?.into_frames(),
+        Some(ImageFormat::WebP) => WebPDecoder::new(Cursor::new(input))?.into_frames(),
+        Some(ImageFormat::Png)  => {
+            let decoder = PngDecoder::new(Cursor::new(input))?;
+            if !decoder.is_apng() {
+                return single_frame(input, frame);
+            }
+            decoder.apng().into_frames()
+        },
+        Some(ImageFormat::Tiff) => return decode_tiff_pages(input, frame),
+        _ => return single_frame(input, frame),
+    };
+    match frame {
+        Frame::All => {
+            let frames = frames
+                .map(|f| f.map(|f| DynamicImage::ImageRgba8(f.into_buffer())).map_err(Error::from))
+                .collect::<Result<Vec<_>, _>>()?;
+            if frames.is_empty() {
+                return Err(Error::Decode("the image has no frame".to_string()));
+            }
+            Ok(frames)
+        },
+        Frame::Index(index) => {
+            let f = frames.into_iter().nth(index).ok_or_else(|| missing(index))??;
+            Ok(vec![DynamicImage::ImageRgba8(f.into_buffer())])
+        }
+    }
+}
+
+/// Tells apart the errors which are due to an image being cut short (e.g. a file whose copy or
+/// download did not complete) from the other decoding errors: they are turned into `Truncated`
+/// errors naming the source image `src`.
+pub(crate) fn classify(error: Error, src: &Path) -> Error {
+    let eof = |e: &std::io::Error| e.kind() == std::io::ErrorKind::UnexpectedEof;
+    match &error {
+        Error::Image(ImageError::IoError(e)) | Error::Tiff(TiffError::IoError(e)) if eof(e) => Error::Truncated(src.to_path_buf(), e.to_string()),
+        // the png decoder reports a missing end of file as a format error
+        Error::Image(ImageError::Decoding(e)) if e.to_string().contains("end of data") => Error::Truncated(src.to_path_buf(), e.to_string()),
+        _ => error,
+    }
+}
+
+/// Decodes the requested `frame` of an image which has only one
+fn single_frame(input: &[u8], frame: Frame) -> Result<Vec<DynamicImage>, Error> {
+    match frame {
+        Frame::All | Frame::Index(0) => Ok(vec![decode(input)?]),
+        Frame::Index(index)          => Err(Error::Decode(format!("the image has no frame {index}"))),
+    }
+}
+
+/// Decodes the requested page of a TIFF image (or all of them). The first page is decoded as
+/// per `decode`. The other ones are decoded directly with the tiff library, which supports 8 and
+/// 16 bits gray, RGB and CMYK samples (with or without alpha).
+fn decode_tiff_pages(input: &[u8], frame: Frame) -> Result<Vec<DynamicImage>, Error> {
+    let mut decoder = Decoder::new(Cursor::new(input))?;
+    match frame {
+        Frame::Index(0)     => Ok(vec![decode(input)?]),
+        Frame::Index(index) => {
+            decoder.seek_to_image(index).map_err(|_| Error::Decode(format!("the image has no frame {index}")))?;
+            Ok(vec![decode_tiff_page(&mut decoder)?])
+        },
+        Frame::All => {
+            let mut pages = vec![decode(input)?];
+            while decoder.more_images() {
+                decoder.next_image()?;
+                pages.push(decode_tiff_page(&mut decoder)?);
+            }
+            Ok(pages)
+        }
+    }
+}
+
+/// Decodes the current page of the TIFF `decoder` into an 8-bit image
+fn decode_tiff_page(decoder: &mut Decoder<Cursor<&[u8]>>) -> Result<DynamicImage, Error> {
+    let colortype = decoder.colortype()?;
+    let (w, h)    = decoder.dimensions()?;
+    let samples   = match decoder.read_image()? {
+        DecodingResult::U8(s)  => s,
+        DecodingResult::U16(s) => s.into_iter().map(|x| (x >> 8) as u8).collect(),
+        _ => return Err(Error::Decode(format!("unsupported tiff samples {colortype:?}"))),
+    };
+    let im = match colortype {
+        ColorType::Gray(8 | 16)  => ImageBuffer::from_raw(w, h, samples).map(DynamicImage::ImageLuma8),
+        ColorType::GrayA(8 | 16) => ImageBuffer::from_raw(w, h, samples).map(DynamicImage::ImageLumaA8),
+        ColorType::RGB(8 | 16)   => ImageBuffer::from_raw(w, h, samples).map(DynamicImage::ImageRgb8),
+        ColorType::RGBA(8 | 16)  => ImageBuffer::from_raw(w, h, samples).map(DynamicImage::ImageRgba8),
+        ColorType::CMYK(8 | 16)  => Some(cmyk_to_rgb(w, h, samples)?),
+        _ => None,
+    };
+    im.ok_or_else(|| Error::Decode(format!("unsupported tiff samples {colortype:?}")))
+}
+
+/// Decodes a TIFF image with CMYK samples (8 or 16 bits) into an RGB image. This returns `None`
+/// when the TIFF image is not a CMYK one. 
+fn decode_cmyk_tiff(input: &[u8]) -> Result<Option<DynamicImage>, Error> {
+    let mut decoder = Decoder::new(Cursor::new(input))?;
+    if !matches!(decoder.colortype()?, ColorType::CMYK(8) | ColorType::CMYK(16)) {
+        return Ok(None);
+    }
+    let (w, h) = decoder.dimensions()?;
+    // brings all samples to 8 bits: 0 means no ink and 255 means full ink
+    let samples = match decoder.read_image()? {
+        DecodingResult::U8(s)  => s,
+        DecodingResult::U16(s) => s.into_iter().map(|x| (x >> 8) as u8).collect(),
+        _ => return Ok(None),
+    };
+    cmyk_to_rgb(w, h, samples).map(Some)
+}
+
+/// Converts 8-bit CMYK `samples` (0 means no ink and 255 means full ink) into an RGB image.
+/// 
+/// The conversion is the naive one (R = (1 - C) * (1 - K), ...), without any color management.
+fn cmyk_to_rgb(w: u32, h: u32, samples: Vec<u8>) -> Result<DynamicImage, Error> {
+    let pixels = samples.chunks_exact(4)
+        .flat_map(|cmyk| {
+            let k = 255 - cmyk[3] as u32;
+            let channel = |ink: u8| ((255 - ink as u32) * k / 255) as u8;
+            [channel(cmyk[0]), channel(cmyk[1]), channel(cmyk[2])]
+        })
+        .collect::<Vec<_>>();
+    let im = ImageBuffer::<Rgb<u8>, _>::from_raw(w, h, pixels)
+        .ok_or_else(|| Error::Decode("truncated CMYK samples".to_string()))?;
+    Ok(DynamicImage::ImageRgb8(im))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tiff::encoder::{colortype, TiffEncoder};
+
+    /// The CMYK samples of a cyan, a black and a half-black pixel
+    const CMYK: [u8; 12] = [255, 0, 0, 0, 0, 0, 0, 255, 0, 0, 0, 128];
+    /// The RGB pixels they are converted to
+    const RGB: [u8; 9] = [0, 255, 255, 0, 0, 0, 127, 127, 127];
+    /// The 16-bit gray samples of a black, a mid-gray and a white pixel
+    const GRAY: [u16; 3] = [0, 0x8000, 0xFFFF];
+
+    #[test]
+    fn an_animation_without_any_frame_is_an_error() {
+        // a gif holding a 1x1 logical screen and a graphic control extension, but no image
+        let gif = b"GIF89a\x01\x00\x01\x00\x00\x00\x00\x21\xf9\x04\x00\x00\x00\x00\x00\x3b";
+        let result = decode_frames(gif, Frame::All);
+        assert!(matches!(result, Err(Error::Decode(_))), "{:?}", result.map(|f| f.len()));
+        let job = crate::ThumbnailJob { frame: Frame::All, ..crate::testing::job(4, 4) };
+        let mut output = Cursor::new(vec![]);
+        assert!(crate::resize_image_into(gif, &mut output, &job).is_err());
+        assert!(output.get_ref().is_empty());
+    }
+
+    #[test]
+    fn a_short_ftyp_box_is_an_error_rather_than_a_panic() {
+        for len in 4..=12 {
+            let input = &b"\x00\x00\x00\x0cftypABCD"[..len];
+            assert_eq!(heif(input), None);
+            assert!(decode(input).is_err());
+        }
+        assert_eq!(heif(b"\x00\x00\x00\x0cftypheic"), Some("heic"));
+    }
+
+    #[test]
+    fn cmyk_tiffs_are_converted_to_rgb() {
+        let mut cmyk8 = Cursor::new(vec![]);
+        TiffEncoder::new(&mut cmyk8).unwrap().write_image::<colortype::CMYK8>(3, 1, &CMYK).unwrap();
+        let mut cmyk16 = Cursor::new(vec![]);
+        let samples = CMYK.map(|s| s as u16 * 257);
+        TiffEncoder::new(&mut cmyk16).unwrap().write_image::<colortype::CMYK16>(3, 1, &samples).unwrap();
+
+        for tiff in [cmyk8, cmyk16] {
+            let im = decode(tiff.get_ref()).unwrap();
+            assert!(matches!(im, DynamicImage::ImageRgb8(_)));
+            assert_eq!(im.as_bytes(), RGB);
+        }
+    }
+
+    #[test]
+    fn gray16_tiffs_stay_gray() {
+        let mut gray = Cursor::new(vec![]);
+        TiffEncoder::new(&mut gray).unwrap().write_image::<colortype::Gray16>(3, 1, &GRAY).unwrap();
+        let im = decode(gray.get_ref()).unwrap();
+        assert!(matches!(im, DynamicImage::ImageLuma8(_)));
+        assert_eq!(im.as_bytes(), [0, 128, 255]);
+    }
+
+    #[test]
+    fn all_the_pages_of_a_tiff_are_converted() {
+        let mut pages = Cursor::new(vec![]);
+        let mut encoder = TiffEncoder::new(&mut pages).unwrap();
+        encoder.write_image::<colortype::CMYK8>(3, 1, &CMYK).unwrap();
+        encoder.write_image::<colortype::Gray16>(3, 1, &GRAY).unwrap();
+
+        let frames = decode_frames(pages.get_ref(), Frame::All).unwrap();
+        assert_eq!(frames.len(), 2);
+        assert!(matches!(frames[0], DynamicImage::ImageRgb8(_)));
+        assert_eq!(frames[0].as_bytes(), RGB);
+        assert!(matches!(frames[1], DynamicImage::ImageLuma8(_)));
+        assert_eq!(frames[1].as_bytes(), [0, 128, 255]);
+    }
+}
@@ -0,0 +1,119 @@
+use std::path::{Path, PathBuf};
+
+/// The kind of errors that could potentially happen
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("Cannot parse filter type. The only authorized values are 'nearest' (or 'nn'), 'box' (or 'area'), 'triangle', 'gaussian', 'catmull-rom' (or 'cubic'), 'lanczos3' (or 'lanczos'), in any case")]
+    CannotParseFilterType,
+    #[error("Invalid quality '{0}'. The quality must be an integer in the range 1..=100")]
+    InvalidQuality(String),
+    #[error("Invalid rotation '{0}'. The only authorized values are 0, 90, 180 and 270")]
+    InvalidRotation(String),
+    #[error("Invalid brightness '{0}'. The brightness must be an integer in the range -255..=255")]
+    InvalidBrightness(String),
+    #[error("Invalid contrast '{0}'. The contrast must be a number in the range -100..=100")]
+    InvalidContrast(String),
+    #[error("Invalid sharpening amount '{0}'. The amount must be a number in the range 0..=5")]
+    InvalidSharpen(String),
+    #[error("Invalid opacity '{0}'. The opacity must be a number in the range 0..=1")]
+    InvalidOpacity(String),
+    #[error("Invalid watermark size '{0}'. The size must be a fraction of the thumbnail in the range 0..=1 (e.g. 0.25)")]
+    InvalidWatermarkSize(String),
+    #[error("Cannot parse watermark position. The only authorized values are 'tl', 'tr', 'bl', 'br', 'center'")]
+    CannotParseWatermarkPosition,
+    #[error("Cannot read the watermark {0}: {1}")]
+    InvalidWatermark(PathBuf, String),
+    #[error("Invalid scale '{0}'. The scale must be a positive number no larger than 10 (e.g. 0.5)")]
+    InvalidScale(String),
+    #[error("Cannot parse output format. The only authorized values are 'jpeg', 'png', 'webp', 'avif', 'bmp', 'gif'")]
+    CannotParseOutputFormat,
+    #[error("Cannot encode {0} thumbnails: this build lacks the '{1}' feature of the image crate")]
+    UnsupportedOutputFormat(&'static str, &'static str),
+    #[error("Cannot encode {0} thumbnails losslessly: the format has no lossless mode (see --lossless)")]
+    NoLosslessMode(&'static str),
+    #[error("Cannot decode {0} files: this build lacks the '{1}' crate")]
+    UnsupportedInputFormat(&'static str, &'static str),
+    #[error("Cannot parse resize mode. The only authorized values are 'exact', 'fit', 'fill'")]
+    CannotParseResizeMode,
+    #[error("Cannot parse shape '{0}'. The only authorized values are 'rectangle', 'circle' and 'rounded:R' (R being the radius of the corners in pixels, e.g. 'rounded:12')")]
+    CannotParseShape(String),
+    #[error("Cannot parse sizes '{0}'. Sizes must be given as comma separated WIDTHxHEIGHT pairs (e.g. '120x150,240x300')")]
+    CannotParseSizes(String),
+    #[error("Invalid blurhash components '{0}'. They must be given as XxY, with X and Y in the range 1..=9 (e.g. '4x3')")]
+    InvalidBlurhashComponents(String),
+    #[error("Cannot parse color '{0}'. Colors must be given as RRGGBB hexadecimal digits (e.g. 'ffffff')")]
+    CannotParseColor(String),
+    #[error("Invalid name template '{0}'. The only authorized tokens are {}", crate::naming::tokens())]
+    InvalidNameTemplate(String),
+    #[error("Cannot parse collision policy. The only authorized values are 'error', 'skip', 'rename'")]
+    CannotParseCollisionPolicy,
+    #[error("Cannot parse overwrite policy. The only authorized values are 'always', 'never', 'if-newer', 'if-changed'")]
+    CannotParseOverwritePolicy,
+    #[error("Cannot parse incremental mode. The only authorized values are 'mtime', 'hash'")]
+    CannotParseIncremental,
+    #[error("Cannot parse chroma subsampling. The only authorized values are '444', '422', '420'")]
+    CannotParseChroma,
+    #[error("Invalid glob pattern '{0}'")]
+    InvalidGlob(String),
+    #[error("Invalid configuration file {0} (line {1}): {2}")]
+    InvalidConfig(PathBuf, usize, String),
+    #[error("Invalid filter file {0}: '{1}' is not a filter (see --list-filters)")]
+    InvalidFilterFile(PathBuf, String),
+    #[error("Cannot store {0} in a tar archive: its name is too long")]
+    ArchiveEntry(PathBuf),
+    #[error("Cannot read the source {0}: {1}")]
+    InvalidSource(PathBuf, String),
+    #[error("Cannot fetch {0}: {1}")]
+    Http(PathBuf, String),
+    #[error("Cannot write to the destination {0}: {1}")]
+    InvalidDestination(PathBuf, String),
+    #[error("Several thumbnails would be written to {0}")]
+    NameCollision(PathBuf),
+    #[error("{0} would be overwritten by its own thumbnail (see --suffix)")]
+    OverwritesSource(PathBuf),
+    #[error("image too large ({0}x{1} pixels, the limit is {2} pixels)")]
+    TooManyPixels(u32, u32, u64),
+    #[error("{0} could not be processed: {1}")]
+    Failed(PathBuf, String),
+    #[error("the file is empty")]
+    EmptyFile(PathBuf),
+    #[error("truncated image ({1})")]
+    Truncated(PathBuf, String),
+    #[error("problem while processing image {0}")]
+    Image(#[from] image::error::ImageError),
+    #[error("problem while decoding tiff image {0}")]
+    Tiff(#[from] tiff::TiffError),
+    #[error("cannot decode image: {0}")]
+    Decode(String),
+    #[error("io error {0}")]
+    Io(#[from] std::io::Error),
+    #[error("io error on {path}: {source}")]
+    IoWithPath { path: PathBuf, source: std::io::Error },
+    #[error("cannot create the thread pool {0}")]
+    ThreadPool(#[from] rayon::ThreadPoolBuildError),
+    #[error("smol send error {0}")]
+    SendError(String),
+    #[error("error joining scoped threads {0}")]
+    JoinError(String),
+}
+
+impl Error {
+    /// Turns the error into a `Failed` error naming the source image `src` it happened on
+    pub(crate) fn failed(self, src: &Path) -> Self {
+        match self {
+            Error::Failed(..) => self,
+            error             => Error::Failed(src.to_path_buf(), error.to_string()),
+        }
+    }
+}
+
+/// Attaches to the error of an io operation the path of the file (or folder) it failed on
+pub(crate) trait WithPath<T> {
+    /// Turns the error into an `IoWithPath` error naming `path`
+    fn with_path(self, path: &Path) -> Result<T, Error>;
+}
+impl<T> WithPath<T> for std::io::Result<T> {
+    fn with_path(self, path: &Path) -> Result<T, Error> {
+        self.map_err(|source| Error::IoWithPath { path: path.to_path_buf(), source })
+    }
+}
@@ -0,0 +1,84 @@
+//! A minimal reader (and writer) for the few bits of EXIF metadata this crate cares about. Only
+//! the orientation tag of the first IFD is ever looked at, which is why a full blown EXIF 
+//! library is not warranted.
+
+use image::DynamicImage;
+
+/// The EXIF tag holding the orientation of the image
+const ORIENTATION: u16 = 0x0112;
+
+/// Returns the EXIF orientation (1..=8) of the encoded image `input`, if it has one. Both JPEG
+/// (APP1 segment) and TIFF (first IFD) inputs are supported.
+pub(crate) fn orientation(input: &[u8]) -> Option<u16> {
+    let tiff = if input.starts_with(b"II*\0") || input.starts_with(b"MM\0*") {
+        input
+    } else if input.starts_with(&[0xFF, 0xD8]) {
+        jpeg_exif(input)?
+    } else {
+        return None;
+    };
+    let o = ifd0_short(tiff, ORIENTATION)?;
+    (1..=8).contains(&o).then_some(o)
+}
+
+/// Applies the given EXIF orientation to `im` so that it is displayed upright
+pub(crate) fn apply_orientation(im: &DynamicImage, orientation: u16) -> DynamicImage {
+    match orientation {
+        2 => im.fliph(),
+        3 => im.rotate180(),
+        4 => im.flipv(),
+        5 => im.rotate90().fliph(),
+        6 => im.rotate90(),
+        7 => im.rotate270().fliph(),
+        8 => im.rotate270(),
+        _ => im.clone(),
+    }
+}
+
+/// Builds a (big endian) TIFF structure whose first IFD only holds the given orientation
+pub(crate) fn orientation_tiff(orientation: u16) -> Vec<u8> {
+    let mut tiff = b"MM\0*".to_vec();
+    tiff.extend(8_u32.to_be_bytes());           // offset of the first IFD
+    tiff.extend(1_u16.to_be_bytes());           // number of entries
+    tiff.extend(ORIENTATION.to_be_bytes());     // tag
+    tiff.extend(3_u16.to_be_bytes());           // type: SHORT
+    tiff.extend(1_u32.to_be_bytes());           // count
+    tiff.extend(orientation.to_be_bytes());     // value (padded to 4 bytes)
+    tiff.extend(0_u16.to_be_bytes());
+    tiff.extend(0_u32.to_be_bytes());           // no next IFD
+    tiff
+}
+
+/// Returns the TIFF structure embedded in the EXIF (APP1) segment of a JPEG image
+fn jpeg_exif(input: &[u8]) -> Option<&[u8]> {
+    let mut pos = 2;
+    while pos + 4 <= input.len() && input[pos] == 0xFF {
+        let marker = input[pos + 1];
+        // start of scan: there are no more metadata segments after that point
+        if marker == 0xDA {
+            return None;
+        }
+        let len = u16::from_be_bytes([input[pos + 2], input[pos + 3]]) as usize;
+        let segment = input.get(pos + 4..pos + 2 + len)?;
+        if marker == 0xE1 && segment.starts_with(b"Exif\0\0") {
+            return Some(&segment[6..]);
+        }
+        pos += 2 + len;
+    }
+    None
+}
+
+/// Reads the value of a SHORT tag from the first IFD of a TIFF structure
+fn ifd0_short(tiff: &[u8], tag: u16) -> Option<u16> {
+    let little = tiff.starts_with(b"II");
+    let u16_at = |at: usize| tiff.get(at..at + 2).map(|b| if little { u16::from_le_bytes([b[0], b[1]]) } else { u16::from_be_bytes([b[0], b[1]]) });
+    let u32_at = |at: usize| tiff.get(at..at + 4).map(|b| if little { u32::from_le_bytes([b[0], b[1], b[2], b[3]]) } else { u32::from_be_bytes([b[0], b[1], b[2], b[3]]) });
+
+    let ifd = u32_at(4)? as usize;
+    let count = u16_at(ifd)? as usize;
+    (0..count)
+        .map(|i| ifd + 2 + 12 * i)
+        .find(|&entry| u16_at(entry) == Some(tag))
+        // the value of a single SHORT is stored inline, right after the tag, type and count
+        .and_then(|entry| u16_at(entry + 8))
+}
@@ -0,0 +1,109 @@
+use std::str::FromStr;
+
+use crate::Error;
+
+/// A shell-like glob pattern matched against file names. The supported syntax is:
+/// 
+/// * `*` matches any (possibly empty) sequence of characters,
+/// * `?` matches exactly one character,
+/// * `[abc]`, `[a-z]` match one character of the set (`[!abc]` one character out of the set).
+/// 
+/// Any other character matches itself, the comparison is case sensitive.
+#[derive(Debug, Clone)]
+pub struct Glob(Vec<Token>);
+
+/// One element of a glob pattern
+#[derive(Debug, Clone)]
+enum Token {
+    /// A literal character
+    Char(char),
+    /// `?`
+    Any,
+    /// `*`
+    Star,
+    /// `[...]`: the ranges of the set and whether it is negated
+    Set(Vec<(char, char)>, bool),
+}
+
+impl Glob {
+    /// Returns true iff the whole `name` matches this pattern
+    pub fn matches(&self, name: &str) -> bool {
+        let name = name.chars().collect::<Vec<_>>();
+        let (mut p, mut n) = (0, 0);
+        // position of the last star in the pattern, and of the name when it was met
+        let mut backtrack = None;
+        while n < name.len() {
+            match self.0.get(p) {
+                Some(Token::Star) => {
+                    backtrack = Some((p, n));
+                    p += 1;
+                    continue;
+                },
+                Some(t) if t.matches(name[n]) => {
+                    p += 1;
+                    n += 1;
+                    continue;
+                },
+                _ => {}
+            }
+            // mismatch: let the last star swallow one more character
+            match backtrack {
+                Some((bp, bn)) => {
+                    backtrack = Some((bp, bn + 1));
+                    p = bp + 1;
+                    n = bn + 1;
+                },
+                None => return false,
+            }
+        }
+        self.0[p..].iter().all(|t| matches!(t, Token::Star))
+    }
+}
+impl Token {
+    /// Returns true iff this (non star) token matches the character `c`
+    fn matches(&self, c: char) -> bool {
+        match self {
+            Token::Char(x)      => *x == c,
+            Token::Any          => true,
+            Token::Star         => false,
+            Token::Set(set, negated) => set.iter().any(|&(lo, hi)| lo <= c && c <= hi) != *negated,
+        }
+    }
+}
+impl FromStr for Glob {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut tokens = vec![];
+        let mut chars = s.chars().peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                '*' => tokens.push(Token::Star),
+                '?' => tokens.push(Token::Any),
+                '[' => {
+                    let negated = chars.next_if(|&c| c == '!').is_some();
+                    let mut set = vec![];
+                    loop {
+                        match chars.next() {
+                            None      => return Err(Error::InvalidGlob(s.to_string())),
+                            Some(']') if !set.is_empty() => break,
+                            Some(lo)  => {
+                                if chars.next_if(|&c| c == '-').is_some() {
+                                    match chars.next() {
+                                        Some(hi) if hi != ']' => set.push((lo, hi)),
+                                        _ => return Err(Error::InvalidGlob(s.to_string())),
+                                    }
+                                } else {
+                                    set.push((lo, lo));
+                                }
+                            }
+                        }
+                    }
+                    tokens.push(Token::Set(set, negated));
+                },
+                c => tokens.push(Token::Char(c)),
+            }
+        }
+        Ok(Self(tokens))
+    }
+}
@@ -0,0 +1,139 @@
+//! Fetching the source images which are given as `http://` URLs (e.g. in a `--from-file` list)
+//! rather than as paths. This is a minimal HTTP/1.1 client over a plain TCP connection: it
+//! follows the redirections and understands the chunked responses, but it does not speak TLS,
+//! which is why `https://` URLs are rejected.
+
+use std::{io::{self, Read, Write}, net::{TcpStream, ToSocketAddrs}, path::Path, sync::{Condvar, Mutex}, time::Duration};
+
+use crate::{Error, error::WithPath};
+
+/// The largest number of redirections followed to fetch one image
+const MAX_REDIRECTS: usize = 5;
+
+/// Tells whether the source image `path` is a URL (`http://` or `https://`) rather than a file
+pub fn is_url(path: &Path) -> bool {
+    path.to_str().is_some_and(|s| s.starts_with("http://") || s.starts_with("https://"))
+}
+
+/// Where the source images given as URLs are fetched from. The images can be fetched from any
+/// thread, but no more than `limit` of them are fetched at the same time: the other threads wait
+/// for their turn, which keeps a run from flooding the servers.
+#[derive(Debug)]
+pub struct HttpSource {
+    /// How long connecting, sending the request or receiving each part of the response may take
+    timeout: Duration,
+    /// The largest number of images fetched at the same time
+    limit: usize,
+    /// The number of images being fetched
+    fetching: Mutex<usize>,
+    /// Signaled each time an image has been fetched
+    done: Condvar,
+}
+
+impl HttpSource {
+    /// Creates a source fetching at most `limit` images at the same time (at least one), each of
+    /// the steps of a fetch taking no longer than `timeout`
+    pub fn new(timeout: Duration, limit: usize) -> Self {
+        Self { timeout, limit: limit.max(1), fetching: Mutex::new(0), done: Condvar::new() }
+    }
+    /// Fetches the image at `url` into the (empty) buffer `content`. A timeout is reported as an
+    /// io error of kind `TimedOut`, which the pipeline deems transient (see `Pipeline::retries`).
+    pub fn read(&self, url: &Path, content: &mut Vec<u8>) -> Result<(), Error> {
+        {
+            let mut fetching = self.done.wait_while(self.fetching.lock().unwrap(), |n| *n >= self.limit).unwrap();
+            *fetching += 1;
+        }
+        let outcome = self.fetch(url, content);
+        *self.fetching.lock().unwrap() -= 1;
+        self.done.notify_one();
+        outcome
+    }
+    /// Fetches the image at `url`, following the redirections
+    fn fetch(&self, url: &Path, content: &mut Vec<u8>) -> Result<(), Error> {
+        let failed = |reason: String| Error::Http(url.to_path_buf(), reason);
+        let mut location = url.to_str().unwrap_or_default().to_string();
+        for _ in 0..=MAX_REDIRECTS {
+            let Some(rest) = location.strip_prefix("http://") else {
+                return Err(failed("only the http:// URLs are supported (this build has no TLS implementation)".to_string()));
+            };
+            let (host, path) = rest.split_at(rest.find('/').unwrap_or(rest.len()));
+            let path = if path.is_empty() { "/" } else { path };
+
+            let response = self.get(host, path).with_path(url)?;
+            let Some(end) = response.windows(4).position(|w| w == b"\r\n\r\n") else {
+                return Err(failed("malformed response".to_string()));
+            };
+            let head = String::from_utf8_lossy(&response[..end]);
+            let mut lines = head.split("\r\n");
+            let status = lines.next().unwrap_or_default();
+            let code = status.split(' ').nth(1).and_then(|code| code.parse::<u16>().ok());
+            let headers = lines
+                .filter_map(|line| line.split_once(':'))
+                .map(|(name, value)| (name.trim().to_ascii_lowercase(), value.trim().to_string()))
+                .collect::<Vec<_>>();
+            let header = |name: &str| headers.iter().find(|(n, _)| n == name).map(|(_, v)| v.as_str());
+            let body = &response[end + 4..];
+
+            match code {
+                Some(200) => {
+                    if header("transfer-encoding").is_some_and(|te| te.eq_ignore_ascii_case("chunked")) {
+                        dechunk(body, content).ok_or_else(|| failed("malformed chunked response".to_string()))?;
+                    } else {
+                        let length = header("content-length").and_then(|l| l.parse::<usize>().ok()).unwrap_or(body.len());
+                        if body.len() < length {
+                            return Err(failed(format!("the response was cut short ({} bytes out of {length})", body.len())));
+                        }
+                        content.extend_from_slice(&body[..length]);
+                    }
+                    return Ok(());
+                },
+                Some(301 | 302 | 303 | 307 | 308) => {
+                    let Some(next) = header("location") else {
+                        return Err(failed(format!("the server answered '{status}' without a location")));
+                    };
+                    log::debug!("{} redirects to {next}", url.display());
+                    location = if next.starts_with('/') { format!("http://{host}{next}") } else { next.to_string() };
+                },
+                _ => return Err(failed(format!("the server answered '{status}'"))),
+            }
+        }
+        Err(failed(format!("more than {MAX_REDIRECTS} redirections")))
+    }
+    /// Sends a GET request for `path` to `host` (which may name a port) and returns the whole
+    /// response, headers included
+    fn get(&self, host: &str, path: &str) -> io::Result<Vec<u8>> {
+        let address = if host.contains(':') { host.to_string() } else { format!("{host}:80") };
+        let mut last = io::Error::new(io::ErrorKind::NotFound, format!("cannot resolve {host}"));
+        for address in address.to_socket_addrs()? {
+            match TcpStream::connect_timeout(&address, self.timeout) {
+                Ok(mut stream) => {
+                    stream.set_read_timeout(Some(self.timeout))?;
+                    stream.set_write_timeout(Some(self.timeout))?;
+                    let version = env!("CARGO_PKG_VERSION");
+                    write!(stream, "GET {path} HTTP/1.1\r\nHost: {host}\r\nUser-Agent: thumbnails/{version}\r\nAccept: */*\r\nConnection: close\r\n\r\n")?;
+                    let mut response = vec![];
+                    stream.read_to_end(&mut response)?;
+                    return Ok(response);
+                },
+                Err(error) => last = error,
+            }
+        }
+        Err(last)
+    }
+}
+
+/// Decodes the chunked `body` of a response into `content`. Returns `None` when the body is
+/// malformed or cut short.
+fn dechunk(mut body: &[u8], content: &mut Vec<u8>) -> Option<()> {
+    loop {
+        let eol  = body.windows(2).position(|w| w == b"\r\n")?;
+        let size = std::str::from_utf8(&body[..eol]).ok()?;
+        let size = usize::from_str_radix(size.split(';').next()?.trim(), 16).ok()?;
+        if size == 0 {
+            return Some(());
+        }
+        let chunk = body.get(eol + 2..eol + 2 + size)?;
+        content.extend_from_slice(chunk);
+        body = body.get(eol + 4 + size..)?;
+    }
+}
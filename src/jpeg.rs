@@ -0,0 +1,407 @@
+//! A JPEG encoder writing the progressive JPEG and the JPEG with subsampled chroma (the encoder
+//! of the image library only writes baseline JPEG, with full resolution chroma).
+//!
+//! A progressive JPEG is made of several scans, each of which refines the whole image: a viewer
+//! can show a blurry version of the thumbnail as soon as the first scan is received. The
+//! progression is achieved by spectral selection only: the first scan holds the DC coefficients
+//! of all the blocks, the next ones hold bands of their AC coefficients. A baseline JPEG is
+//! made of a single scan holding all the coefficients. The chroma may be subsampled (see
+//! `Chroma`) and the standard quantization (scaled by the quality) and Huffman tables of the
+//! JPEG specification (annex K) are used.
+
+use std::io::{self, Write};
+
+use image::RgbImage;
+
+use crate::Chroma;
+
+/// The luminance quantization table (table K.1, in natural order)
+#[rustfmt::skip]
+const LUMA_QUANTIZATION: [u8; 64] = [
+    16, 11, 10, 16,  24,  40,  51,  61,
+    12, 12, 14, 19,  26,  58,  60,  55,
+    14, 13, 16, 24,  40,  57,  69,  56,
+    14, 17, 22, 29,  51,  87,  80,  62,
+    18, 22, 37, 56,  68, 109, 103,  77,
+    24, 35, 55, 64,  81, 104, 113,  92,
+    49, 64, 78, 87, 103, 121, 120, 101,
+    72, 92, 95, 98, 112, 100, 103,  99,
+];
+/// The chrominance quantization table (table K.2, in natural order)
+#[rustfmt::skip]
+const CHROMA_QUANTIZATION: [u8; 64] = [
+    17, 18, 24, 47, 99, 99, 99, 99,
+    18, 21, 26, 66, 99, 99, 99, 99,
+    24, 26, 56, 99, 99, 99, 99, 99,
+    47, 66, 99, 99, 99, 99, 99, 99,
+    99, 99, 99, 99, 99, 99, 99, 99,
+    99, 99, 99, 99, 99, 99, 99, 99,
+    99, 99, 99, 99, 99, 99, 99, 99,
+    99, 99, 99, 99, 99, 99, 99, 99,
+];
+
+/// The position (in natural order) of each coefficient in zigzag order
+#[rustfmt::skip]
+const ZIGZAG: [usize; 64] = [
+     0,  1,  8, 16,  9,  2,  3, 10,
+    17, 24, 32, 25, 18, 11,  4,  5,
+    12, 19, 26, 33, 40, 48, 41, 34,
+    27, 20, 13,  6,  7, 14, 21, 28,
+    35, 42, 49, 56, 57, 50, 43, 36,
+    29, 22, 15, 23, 30, 37, 44, 51,
+    58, 59, 52, 45, 38, 31, 39, 46,
+    53, 60, 61, 54, 47, 55, 62, 63,
+];
+
+/// A Huffman table given as the number of codes of each length (1 to 16 bits) and the symbols
+/// sorted by code
+struct HuffmanSpec {
+    lengths: [u8; 16],
+    symbols: &'static [u8],
+}
+
+/// The luminance DC table (table K.3)
+const LUMA_DC: HuffmanSpec = HuffmanSpec {
+    lengths: [0, 1, 5, 1, 1, 1, 1, 1, 1, 0, 0, 0, 0, 0, 0, 0],
+    symbols: &[0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11],
+};
+/// The chrominance DC table (table K.4)
+const CHROMA_DC: HuffmanSpec = HuffmanSpec {
+    lengths: [0, 3, 1, 1, 1, 1, 1, 1, 1, 1, 1, 0, 0, 0, 0, 0],
+    symbols: &[0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11],
+};
+/// The luminance AC table (table K.5)
+#[rustfmt::skip]
+const LUMA_AC: HuffmanSpec = HuffmanSpec {
+    lengths: [0, 2, 1, 3, 3, 2, 4, 3, 5, 5, 4, 4, 0, 0, 1, 0x7d],
+    symbols: &[
+        0x01, 0x02, 0x03, 0x00, 0x04, 0x11, 0x05, 0x12, 0x21, 0x31, 0x41, 0x06, 0x13, 0x51, 0x61, 0x07,
+        0x22, 0x71, 0x14, 0x32, 0x81, 0x91, 0xa1, 0x08, 0x23, 0x42, 0xb1, 0xc1, 0x15, 0x52, 0xd1, 0xf0,
+        0x24, 0x33, 0x62, 0x72, 0x82, 0x09, 0x0a, 0x16, 0x17, 0x18, 0x19, 0x1a, 0x25, 0x26, 0x27, 0x28,
+        0x29, 0x2a, 0x34, 0x35, 0x36, 0x37, 0x38, 0x39, 0x3a, 0x43, 0x44, 0x45, 0x46, 0x47, 0x48, 0x49,
+        0x4a, 0x53, 0x54, 0x55, 0x56, 0x57, 0x58, 0x59, 0x5a, 0x63, 0x64, 0x65, 0x66, 0x67, 0x68, 0x69,
+        0x6a, 0x73, 0x74, 0x75, 0x76, 0x77, 0x78, 0x79, 0x7a, 0x83, 0x84, 0x85, 0x86, 0x87, 0x88, 0x89,
+        0x8a, 0x92, 0x93, 0x94, 0x95, 0x96, 0x97, 0x98, 0x99, 0x9a, 0xa2, 0xa3, 0xa4, 0xa5, 0xa6, 0xa7,
+        0xa8, 0xa9, 0xaa, 0xb2, 0xb3, 0xb4, 0xb5, 0xb6, 0xb7, 0xb8, 0xb9, 0xba, 0xc2, 0xc3, 0xc4, 0xc5,
+        0xc6, 0xc7, 0xc8, 0xc9, 0xca, 0xd2, 0xd3, 0xd4, 0xd5, 0xd6, 0xd7, 0xd8, 0xd9, 0xda, 0xe1, 0xe2,
+        0xe3, 0xe4, 0xe5, 0xe6, 0xe7, 0xe8, 0xe9, 0xea, 0xf1, 0xf2, 0xf3, 0xf4, 0xf5, 0xf6, 0xf7, 0xf8,
+        0xf9, 0xfa,
+    ],
+};
+/// The chrominance AC table (table K.6)
+#[rustfmt::skip]
+const CHROMA_AC: HuffmanSpec = HuffmanSpec {
+    lengths: [0, 2, 1, 2, 4, 4, 3, 4, 7, 5, 4, 4, 0, 1, 2, 0x77],
+    symbols: &[
+        0x00, 0x01, 0x02, 0x03, 0x11, 0x04, 0x05, 0x21, 0x31, 0x06, 0x12, 0x41, 0x51, 0x07, 0x61, 0x71,
+        0x13, 0x22, 0x32, 0x81, 0x08, 0x14, 0x42, 0x91, 0xa1, 0xb1, 0xc1, 0x09, 0x23, 0x33, 0x52, 0xf0,
+        0x15, 0x62, 0x72, 0xd1, 0x0a, 0x16, 0x24, 0x34, 0xe1, 0x25, 0xf1, 0x17, 0x18, 0x19, 0x1a, 0x26,
+        0x27, 0x28, 0x29, 0x2a, 0x35, 0x36, 0x37, 0x38, 0x39, 0x3a, 0x43, 0x44, 0x45, 0x46, 0x47, 0x48,
+        0x49, 0x4a, 0x53, 0x54, 0x55, 0x56, 0x57, 0x58, 0x59, 0x5a, 0x63, 0x64, 0x65, 0x66, 0x67, 0x68,
+        0x69, 0x6a, 0x73, 0x74, 0x75, 0x76, 0x77, 0x78, 0x79, 0x7a, 0x82, 0x83, 0x84, 0x85, 0x86, 0x87,
+        0x88, 0x89, 0x8a, 0x92, 0x93, 0x94, 0x95, 0x96, 0x97, 0x98, 0x99, 0x9a, 0xa2, 0xa3, 0xa4, 0xa5,
+        0xa6, 0xa7, 0xa8, 0xa9, 0xaa, 0xb2, 0xb3, 0xb4, 0xb5, 0xb6, 0xb7, 0xb8, 0xb9, 0xba, 0xc2, 0xc3,
+        0xc4, 0xc5, 0xc6, 0xc7, 0xc8, 0xc9, 0xca, 0xd2, 0xd3, 0xd4, 0xd5, 0xd6, 0xd7, 0xd8, 0xd9, 0xda,
+        0xe2, 0xe3, 0xe4, 0xe5, 0xe6, 0xe7, 0xe8, 0xe9, 0xea, 0xf2, 0xf3, 0xf4, 0xf5, 0xf6, 0xf7, 0xf8,
+        0xf9, 0xfa,
+    ],
+};
+
+/// The scans of a progressive image: the components they hold and the band of coefficients (in
+/// zigzag order) they carry. The coarse luminance details come first since they matter the most.
+const SCANS: [(&[usize], usize, usize); 5] = [
+    (&[0, 1, 2], 0, 0),
+    (&[0], 1, 5),
+    (&[1], 1, 63),
+    (&[2], 1, 63),
+    (&[0], 6, 63),
+];
+
+/// The only scan of a baseline image, which holds all the coefficients of all the components
+const BASELINE: [(&[usize], usize, usize); 1] = [(&[0, 1, 2], 0, 63)];
+
+/// Encodes `im` as a JPEG of the given `quality` (1..=100) into `out`, progressive or baseline,
+/// with its chroma subsampled as per `chroma`. The JFIF header records the `dpi` resolution, if
+/// any (otherwise only the 1:1 aspect ratio of the pixels).
+pub(crate) fn encode(im: &RgbImage, quality: u8, dpi: Option<u16>, chroma: Chroma, progressive: bool, out: &mut impl Write) -> io::Result<()> {
+    let tables = [quantization(&LUMA_QUANTIZATION, quality), quantization(&CHROMA_QUANTIZATION, quality)];
+    let (h, v)     = chroma.sampling();
+    let components = components(im, (h, v), &tables);
+    let dc         = [codes(&LUMA_DC), codes(&CHROMA_DC)];
+    let ac         = [codes(&LUMA_AC), codes(&CHROMA_AC)];
+
+    let mut data = vec![0xFF, 0xD8];
+    let (units, density) = match dpi {
+        Some(dpi) => (1, dpi),
+        None      => (0, 1),
+    };
+    let mut jfif = b"JFIF\0\x01\x01".to_vec();
+    jfif.push(units);
+    jfif.extend(density.to_be_bytes());
+    jfif.extend(density.to_be_bytes());
+    jfif.extend([0, 0]);
+    segment(&mut data, 0xE0, &jfif);
+    for (id, table) in tables.iter().enumerate() {
+        let mut dqt = vec![id as u8];
+        dqt.extend(ZIGZAG.iter().map(|&i| table[i] as u8));
+        segment(&mut data, 0xDB, &dqt);
+    }
+    let (w, ht) = (im.width() as u16, im.height() as u16);
+    let mut sof = vec![8];
+    sof.extend(ht.to_be_bytes());
+    sof.extend(w.to_be_bytes());
+    sof.extend([3, 1, (h << 4 | v) as u8, 0, 2, 0x11, 1, 3, 0x11, 1]);
+    segment(&mut data, if progressive { 0xC2 } else { 0xC0 }, &sof);
+    for (class, id, spec) in [(0, 0, &LUMA_DC), (0, 1, &CHROMA_DC), (1, 0, &LUMA_AC), (1, 1, &CHROMA_AC)] {
+        let mut dht = vec![class << 4 | id];
+        dht.extend(spec.lengths);
+        dht.extend(spec.symbols);
+        segment(&mut data, 0xC4, &dht);
+    }
+
+    let scans: &[_] = if progressive { &SCANS } else { &BASELINE };
+    for &(ids, start, end) in scans {
+        let mut sos = vec![ids.len() as u8];
+        for &c in ids {
+            // the luminance uses the tables 0, the chrominance the tables 1
+            let table = (c > 0) as u8;
+            sos.extend([c as u8 + 1, table << 4 | table]);
+        }
+        sos.extend([start as u8, end as u8, 0]);
+        segment(&mut data, 0xDA, &sos);
+
+        let mut bits = BitWriter { out: &mut data, acc: 0, n: 0 };
+        let mut predictions = [0; 3];
+        for (c, block) in scan_order(&components, ids) {
+            let table = (c > 0) as usize;
+            if start == 0 {
+                let diff = block[0] - predictions[c];
+                predictions[c] = block[0];
+                let (size, value) = magnitude(diff);
+                bits.put(dc[table][size as usize]);
+                bits.put((value, size));
+            }
+            if end == 0 {
+                continue;
+            }
+            let codes = &ac[table];
+            let mut run = 0;
+            for &coefficient in &block[start.max(1)..=end] {
+                if coefficient == 0 {
+                    run += 1;
+                    continue;
+                }
+                while run > 15 {
+                    bits.put(codes[0xF0]);
+                    run -= 16;
+                }
+                let (size, value) = magnitude(coefficient);
+                bits.put(codes[(run << 4 | size) as usize]);
+                bits.put((value, size));
+                run = 0;
+            }
+            if run > 0 {
+                bits.put(codes[0x00]);
+            }
+        }
+        bits.flush();
+    }
+    data.extend([0xFF, 0xD9]);
+    out.write_all(&data)
+}
+
+/// The `base` quantization table scaled for the given `quality` (as libjpeg does)
+fn quantization(base: &[u8; 64], quality: u8) -> [u16; 64] {
+    let quality = quality.clamp(1, 100) as u32;
+    let scale = if quality < 50 { 5000 / quality } else { 200 - 2 * quality };
+    base.map(|q| ((q as u32 * scale + 50) / 100).clamp(1, 255) as u16)
+}
+
+/// The blocks of 8x8 samples of one component (Y, Cb or Cr) of the image
+struct Component {
+    /// The quantized DCT coefficients of each block (in zigzag order), the blocks being listed
+    /// in raster order
+    blocks: Vec<[i32; 64]>,
+    /// The number of blocks in each row
+    columns: usize,
+    /// The number of blocks of the component in each MCU, horizontally and vertically
+    sampling: (usize, usize),
+    /// The number of columns and rows of blocks which cover the image itself: the others only
+    /// pad the last MCUs
+    covered: (usize, usize),
+}
+
+/// Splits the image into MCUs of 8h x 8v pixels (the last ones are padded by repeating the edge
+/// pixels), converts it to YCbCr and averages its chroma over the h x v pixels sharing each
+/// sample. Returns the quantized DCT coefficients of the blocks of each component.
+fn components(im: &RgbImage, (h, v): (usize, usize), tables: &[[u16; 64]; 2]) -> [Component; 3] {
+    let (w, ht) = (im.width() as usize, im.height() as usize);
+    let (pw, ph) = (w.div_ceil(8 * h) * 8 * h, ht.div_ceil(8 * v) * 8 * v);
+    let mut planes = [vec![0.0_f32; pw * ph], vec![0.0_f32; pw * ph], vec![0.0_f32; pw * ph]];
+    for y in 0..ph {
+        for x in 0..pw {
+            let [r, g, b] = im.get_pixel(x.min(w - 1) as u32, y.min(ht - 1) as u32).0.map(|c| c as f32);
+            let i = y * pw + x;
+            planes[0][i] =  0.299    * r + 0.587    * g + 0.114    * b - 128.0;
+            planes[1][i] = -0.168736 * r - 0.331264 * g + 0.5      * b;
+            planes[2][i] =  0.5      * r - 0.418688 * g - 0.081312 * b;
+        }
+    }
+    let (cw, ch) = (pw / h, ph / v);
+    if (h, v) != (1, 1) {
+        for plane in planes[1..].iter_mut() {
+            *plane = (0..cw * ch)
+                .map(|i| {
+                    let (x, y) = (i % cw * h, i / cw * v);
+                    (0..v).flat_map(|dy| (0..h).map(move |dx| (y + dy) * pw + x + dx)).map(|j| plane[j]).sum::<f32>() / (h * v) as f32
+                })
+                .collect();
+        }
+    }
+
+    let cosines = cosines();
+    let component = |c: usize, plane: &[f32], width: usize, sampling: (usize, usize), covered: (usize, usize)| {
+        let table = &tables[(c > 0) as usize];
+        let columns = width / 8;
+        let blocks = (0..plane.len() / 64)
+            .map(|b| {
+                let (bx, by) = (b % columns * 8, b / columns * 8);
+                let samples = std::array::from_fn(|i| plane[(by + i / 8) * width + bx + i % 8]);
+                let coefficients = fdct(&samples, &cosines);
+                std::array::from_fn(|k| (coefficients[ZIGZAG[k]] / table[ZIGZAG[k]] as f32).round() as i32)
+            })
+            .collect();
+        Component { blocks, columns, sampling, covered }
+    };
+    let chroma = (w.div_ceil(h).div_ceil(8), ht.div_ceil(v).div_ceil(8));
+    [
+        component(0, &planes[0], pw, (h, v), (w.div_ceil(8), ht.div_ceil(8))),
+        component(1, &planes[1], cw, (1, 1), chroma),
+        component(2, &planes[2], cw, (1, 1), chroma),
+    ]
+}
+
+/// The blocks coded by a scan of the `ids` components, in order (along with their component). A
+/// scan of several components goes through the MCUs in raster order, and through the blocks of
+/// each MCU component by component. A scan of one component goes through the blocks covering the
+/// image in raster order.
+fn scan_order<'a>(components: &'a [Component; 3], ids: &[usize]) -> Vec<(usize, &'a [i32; 64])> {
+    if let [c] = *ids {
+        let component = &components[c];
+        let (columns, rows) = component.covered;
+        return (0..rows)
+            .flat_map(|y| (0..columns).map(move |x| (c, &component.blocks[y * component.columns + x])))
+            .collect();
+    }
+    let luma = &components[0];
+    let (h, v) = luma.sampling;
+    let (columns, rows) = (luma.columns / h, luma.blocks.len() / luma.columns / v);
+    let mut order = vec![];
+    for my in 0..rows {
+        for mx in 0..columns {
+            for &c in ids {
+                let component = &components[c];
+                let (h, v) = component.sampling;
+                for y in my * v..(my + 1) * v {
+                    for x in mx * h..(mx + 1) * h {
+                        order.push((c, &component.blocks[y * component.columns + x]));
+                    }
+                }
+            }
+        }
+    }
+    order
+}
+
+/// The (scaled) cosines of the DCT: the weight of the sample `x` in the coefficient `u`
+fn cosines() -> [[f32; 8]; 8] {
+    let mut cosines = [[0.0_f32; 8]; 8];
+    for (u, row) in cosines.iter_mut().enumerate() {
+        let scale = if u == 0 { std::f32::consts::FRAC_1_SQRT_2 } else { 1.0 } / 2.0;
+        for (x, c) in row.iter_mut().enumerate() {
+            *c = scale * (((2 * x + 1) * u) as f32 * std::f32::consts::PI / 16.0).cos();
+        }
+    }
+    cosines
+}
+
+/// The forward discrete cosine transform of an 8x8 block (in natural order)
+fn fdct(samples: &[f32; 64], cosines: &[[f32; 8]; 8]) -> [f32; 64] {
+    // rows first, then columns
+    let mut rows = [0.0_f32; 64];
+    for y in 0..8 {
+        for u in 0..8 {
+            rows[y * 8 + u] = (0..8).map(|x| cosines[u][x] * samples[y * 8 + x]).sum();
+        }
+    }
+    let mut coefficients = [0.0_f32; 64];
+    for v in 0..8 {
+        for u in 0..8 {
+            coefficients[v * 8 + u] = (0..8).map(|y| cosines[v][y] * rows[y * 8 + u]).sum();
+        }
+    }
+    coefficients
+}
+
+/// The number of bits needed to code `value` and these bits (negative values being coded as
+/// their one's complement)
+fn magnitude(value: i32) -> (u8, u16) {
+    let size = 32 - value.unsigned_abs().leading_zeros();
+    let bits = if value < 0 { value - 1 } else { value };
+    (size as u8, (bits & ((1 << size) - 1)) as u16)
+}
+
+/// The code and length of each symbol of a Huffman table (as per annex C of the specification)
+fn codes(spec: &HuffmanSpec) -> [(u16, u8); 256] {
+    let mut codes = [(0, 0); 256];
+    let mut code = 0_u16;
+    let mut symbols = spec.symbols.iter();
+    for (length, &count) in spec.lengths.iter().enumerate() {
+        for symbol in symbols.by_ref().take(count as usize) {
+            codes[*symbol as usize] = (code, length as u8 + 1);
+            code += 1;
+        }
+        code <<= 1;
+    }
+    codes
+}
+
+/// Writes a marker segment holding `data` to `out`
+fn segment(out: &mut Vec<u8>, marker: u8, data: &[u8]) {
+    out.extend([0xFF, marker]);
+    out.extend((data.len() as u16 + 2).to_be_bytes());
+    out.extend_from_slice(data);
+}
+
+/// Packs the entropy coded data of one scan into bytes
+struct BitWriter<'a> {
+    out: &'a mut Vec<u8>,
+    /// The bits which have not been written yet
+    acc: u32,
+    /// The number of bits in `acc`
+    n: u8,
+}
+impl BitWriter<'_> {
+    /// Writes the `length` lowest bits of `bits`
+    fn put(&mut self, (bits, length): (u16, u8)) {
+        self.acc = self.acc << length | bits as u32;
+        self.n += length;
+        while self.n >= 8 {
+            self.n -= 8;
+            let byte = (self.acc >> self.n) as u8;
+            self.out.push(byte);
+            // a 0xFF byte would be mistaken for a marker
+            if byte == 0xFF {
+                self.out.push(0);
+            }
+        }
+        self.acc &= (1 << self.n) - 1;
+    }
+    /// Pads the last byte with 1 bits
+    fn flush(&mut self) {
+        if self.n > 0 {
+            let padding = 8 - self.n;
+            self.put(((1 << padding) - 1, padding));
+        }
+    }
+}
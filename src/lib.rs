@@ -0,0 +1,76 @@
+//! The purpose of this library is to create image thumbnails in bulk, in an attempt to maximize 
+//! the creation throughput. It is the engine behind the `thumbnails` command line tool, but it can
+//! just as well be used from any other rust program:
+//! 
+//! * `prepare` walks a source folder and lists the (source, destination) pairs to process
+//!   (`prepare_files` does the same for an explicit list of files),
+//! * `resize_image` creates *one* thumbnail as described by a `ThumbnailJob` (`resize_image_into`
+//!   encodes it straight into any writer, e.g. the destination file, `resize_image_pooled`
+//!   reuses the buffers of a `BufferPool` and `resize_decoded` resizes an image which is
+//!   already decoded),
+//! * `run` creates the thumbnails of a whole list in parallel (`montage` tiles them on one single
+//!   contact sheet instead, and an `Archive` gathers them in one zip or tar file), while
+//!   `run_streaming` starts creating them as soon as `prepare_streaming` finds the first images,
+//! * `run_stream` runs the pipeline in the background and yields the outcome of each image as
+//!   an async stream (e.g. to show the progress of a run in a web page),
+//! * the source images can be fetched from `http://` URLs by an `HttpSource`,
+//! * `watch` keeps creating the thumbnails of the images as they land in the source folder,
+//! * a `Manifest` records the content hash of the source of each thumbnail, so that the
+//!   thumbnails are only regenerated when their source changes (see `Overwrite::IfChanged`),
+//! * `lqip` and `blurhash` create the tiny placeholders of an image, to be shown in a web page
+//!   while its thumbnail loads,
+//! * `suggest_rotations` guesses the rotation which would turn the scanned documents of each
+//!   folder upright, when they carry no EXIF orientation,
+//! * a `Watermark` (e.g. a logo) can be composited onto each thumbnail,
+//! * `Stats` summarizes a whole run (processed, skipped and failed images, bytes read and written)
+//!   and `Timings` break down the time spent decoding, resizing and encoding.
+
+mod error;
+mod decode;
+mod exif;
+mod metadata;
+mod color;
+mod jpeg;
+mod options;
+mod config;
+mod resize;
+mod naming;
+mod glob;
+mod thumbignore;
+mod manifest;
+mod prepare;
+mod pool;
+mod pipeline;
+mod montage;
+mod watch;
+mod stream;
+mod archive;
+mod http;
+mod placeholder;
+mod orientation;
+mod watermark;
+mod progress;
+mod stats;
+#[cfg(test)]
+mod testing;
+
+pub use error::*;
+pub use options::*;
+pub use config::*;
+pub use resize::*;
+pub use naming::*;
+pub use glob::*;
+pub use manifest::*;
+pub use prepare::*;
+pub use pool::*;
+pub use pipeline::*;
+pub use montage::*;
+pub use watch::*;
+pub use stream::*;
+pub use archive::*;
+pub use http::*;
+pub use placeholder::*;
+pub use orientation::*;
+pub use watermark::*;
+pub use progress::*;
+pub use stats::*;
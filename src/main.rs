@@ -1,230 +1,969 @@
-use std::{str::FromStr, path::{PathBuf, Path}, fs, io::Cursor};
+use std::{env, ffi::OsString, fs, io::{self, BufRead, BufReader, Read, Write}, num::NonZeroU16, path::{Path, PathBuf}, process::ExitCode, sync::atomic::{AtomicBool, Ordering}, time::{Duration, Instant}};
 
-use image::ImageOutputFormat;
-use rayon::iter::{ParallelIterator, IntoParallelIterator};
-use smol::{io::AsyncReadExt, channel::{Sender, Receiver}};
-use structopt::StructOpt;
+use log::{LevelFilter, Log, Metadata, Record};
+use structopt::{clap, StructOpt};
+use thumbnails::{Archive, ArchiveFormat, BlurhashComponents, Case, Chroma, Color, ConfigValue, Error, Frame, Extensions, Failure, FilterType, Glob, HttpSource, Incremental, Manifest, NameTemplate, OnCollision, OutputFormat, Overwrite, Pipeline, Progress, ResizeMode, Scan, Shape, SizeRange, Sizes, Stats, Suggestion, Task, ThumbnailJob, Timings, Watermark, WatermarkPosition, ZipSource, parse_brightness, parse_contrast, parse_opacity, parse_quality, parse_rotation, parse_scale, parse_sharpen, parse_watermark_size, is_url, prepare, suggest_rotations, ORIENTATION_SAMPLES, prepare_archive, prepare_files, read_config, resize_image, resize_image_into, run, run_streaming};
 
 /// the purpose of this tool is to create image thumbnails in bulk an attempt to maxize the
 /// creation throughput.
 #[derive(structopt::StructOpt)]
 struct Args {
-    /// Path to the source folder
+    /// Path to the source folder (with `--from-file`, the subfolders of the destination folder 
+    /// are named after the location of the files relative to this folder). When it is '-', one 
+    /// single image is read from stdin and its thumbnail is written to `dst` (see below). When it
+    /// is a '.zip' file, the images are read from this archive without extracting it: their 
+    /// thumbnails are written to the subfolders of `dst` matching their location in the archive.
+    #[structopt(required_unless_one=&["list-filters", "list-formats"])]
     src: String,
-    /// Path to the destination folder
+    /// Path to the destination folder. When either `src` or `dst` is '-', one single image is 
+    /// processed and `dst` is the path of its thumbnail, or '-' to write it to stdout (in the
+    /// format given by `--format`). When it ends with '.zip' or '.tar', the thumbnails are written
+    /// into this archive (named after their location relative to the source folder) rather than
+    /// as loose files. The entries come in the order in which the thumbnails are completed. The
+    /// destination folder may lie inside the source folder: it is then left out of the scan.
+    #[structopt(required_unless_one=&["list-filters", "list-formats"])]
     dst: String,
-    /// Width of the generated thumbnails
-    #[structopt(short, long, default_value="120")]
-    width: u32,
-    /// Height of the generated thumbnails
-    #[structopt(short, long, default_value="150")]
-    height: u32,
-    /// Limits the number of concurrent futures running at the same time.
+    /// Reads the options from this TOML file, one `option = value` line per option, e.g. 
+    /// `width = 240`, `filter = "lanczos3"`, `sorted = true` or `include = ["*.tif", "*.png"]`.
+    /// The keys are the long names of the options (with either dashes or underscores), and the
+    /// options given on the command line win over those of the file. The paths are relative to
+    /// the current folder.
+    #[structopt(long)]
+    config: Option<PathBuf>,
+    /// Width of the generated thumbnails. When only `--height` is given, the width of each 
+    /// thumbnail follows from the aspect ratio of its image. When neither is given, the thumbnails
+    /// are 120x150 pixels (as they have always been): only giving both of them prevents the
+    /// default height from applying to a `--width` (or the default width to a `--height`).
+    #[structopt(short, long)]
+    width: Option<u32>,
+    /// Height of the generated thumbnails. When only `--width` is given, the height of each
+    /// thumbnail follows from the aspect ratio of its image, whatever the `--mode` (e.g. `--width
+    /// 200` turns a 4000x3000 image into a 200x150 thumbnail). In 'fit' mode, the images are still
+    /// never scaled up unless `--allow-upscale` is given. A contact sheet (see `--montage`) needs 
+    /// both sizes: the missing one is then 120 (width) or 150 (height). The `{width}` and
+    /// `{height}` tokens of a name template are 0 when the dimension is not given.
+    #[structopt(short, long)]
+    height: Option<u32>,
+    /// Generates several thumbnails of each image in one pass (the image is only decoded once).
+    /// The sizes are given as comma separated WIDTHxHEIGHT pairs (e.g. '120x150,240x300') and 
+    /// replace `--width` and `--height`. When more than one size is given, the size is appended
+    /// to the name of each thumbnail (e.g. 'name_120x150.jpg').
+    #[structopt(long)]
+    sizes: Option<Sizes>,
+    /// Generates square NxN thumbnails, center-cropping the images as needed. This is a shorthand
+    /// for `--mode fill --width N --height N`, hence it cannot be combined with any of these.
+    #[structopt(long, conflicts_with_all=&["width", "height", "sizes", "mode", "keep-aspect"])]
+    square: Option<u32>,
+    /// Resizes each image by this factor of its own dimensions (e.g. 0.5 halves both its sides,
+    /// no side dropping below 1px) rather than to a fixed size. This is handy for sources of
+    /// mixed resolutions. The `{width}` and `{height}` tokens of a name template still stand 
+    /// for `--width` and `--height`.
+    #[structopt(long, conflicts_with_all=&["width", "height", "sizes", "square", "mode", "keep-aspect", "montage"], parse(try_from_str=parse_scale))]
+    scale: Option<f32>,
+    /// Creates one single contact sheet instead of one thumbnail per image: the thumbnails are 
+    /// tiled in a grid of this many columns (each cell being `--width` x `--height` pixels) and 
+    /// `dst` is the path of the sheet itself (e.g. 'sheet.jpg'). The whole sheet is held in memory
+    /// until it is written, which takes 4 bytes per pixel: about 1.5 GiB for 10.000 thumbnails of
+    /// 200x200 pixels.
+    #[structopt(long, conflicts_with_all=&["sizes", "bench"])]
+    montage: Option<u32>,
+    /// Limits the number of images in flight (read but whose thumbnails are not written yet) at 
+    /// the same time, in both the synchronous and the asynchronous mode. This bounds the memory 
+    /// used by the decoded images. Setting it lower than `--threads` leaves some threads idle.
+    /// At least one image is in flight: 0 stands for 1.
     #[structopt(short, long, default_value="10")]
     limit: usize,
-    ///
-    #[structopt(short, long)]
-    threads: Option<usize>,
+    /// Number of threads used to resize the images, both in synchronous and asynchronous mode. 
+    /// This allows capping the cpu usage on a shared machine. 0 (default) means one thread per 
+    /// cpu core.
+    #[structopt(short, long, default_value="0")]
+    threads: usize,
     /// Not all files should be considered when processing the images. Actually, we only want to
     /// process those files having a specific extension and leave out all the others. This flag
-    /// allows you to set the only extension to use for that purpose.
+    /// allows you to set the extensions to use for that purpose as a comma separated list 
+    /// (e.g. 'tif,jpg,png'). The special value 'all' (or an empty list) selects every file 
+    /// having an extension which the image library knows how to decode.
     #[structopt(short, long, default_value="tif")]
-    extension: String,
+    extension: Extensions,
+    /// Only process the files whose name matches this glob pattern (e.g. 'IMG_*.tif'). Can be
+    /// repeated, in which case a file is processed when it matches any of the patterns. This 
+    /// composes with `--extension`.
+    #[structopt(long, number_of_values=1)]
+    include: Vec<Glob>,
+    /// Processes the files listed in this file (one path per line, '-' reads the list from stdin)
+    /// instead of scanning the source folder. The listed files are processed as they are: 
+    /// `--extension`, `--include` and `--exclude` do not apply to them. This makes it possible
+    /// to drive the tool from `find` or `git diff`. The list may also name `http://` URLs, which
+    /// are fetched (see `--http-timeout`) and whose thumbnails are written in `dst`.
+    #[structopt(long)]
+    from_file: Option<String>,
+    /// Never process the files whose name matches this glob pattern (e.g. '*_thumb.tif'). Can be
+    /// repeated. Excludes win over includes. The files and folders listed in the `.thumbignore`
+    /// files found while scanning are skipped as well.
+    #[structopt(long, number_of_values=1)]
+    exclude: Vec<Glob>,
+    /// Limits how deep the subfolders of the source folder are scanned: 0 only processes the 
+    /// images of the source folder itself, 1 also those of its direct subfolders, and so on.
+    /// By default, there is no limit.
+    #[structopt(long)]
+    max_depth: Option<usize>,
+    /// Follow the symbolic links pointing to folders. By default, these are ignored. A link 
+    /// leading back to one of its own parent folders is never followed (so as not to loop).
+    #[structopt(long)]
+    follow_symlinks: bool,
+    /// Scans the folders in the order of the file names, so that the images are listed and 
+    /// numbered (see the {n} token of `--name-template`) the same way on every run. The images 
+    /// are still resized in parallel. Sorting costs a little extra time and memory on very large
+    /// folders.
+    #[structopt(long)]
+    sorted: bool,
     /// The find of filter to use when creating the thumbnails. 
-    /// Can be either of: 'nearest' (default), 'triangle', 'gaussian', 'catmull-rom', 'lanczos3'
-    /// The fastest algo is 'nearest' which iterpolates nearest pixels.
+    /// Can be either of: 'nearest' (default, or 'nn'), 'box' (or 'area'), 'triangle', 'gaussian', 
+    /// 'catmull-rom' (or 'cubic'), 'lanczos3' (or 'lanczos'), in any case (see `--list-filters`)
+    /// The fastest algo is 'nearest' which iterpolates nearest pixels. 'box' averages the pixels
+    /// covered by each pixel of the thumbnail: it is almost as fast and looks much better for
+    /// large downscale ratios. A `.thumbfilter` file naming a filter overrides this one for the
+    /// images of its folder (and subfolders).
     #[structopt(short, long, default_value="nearest")]
     filter: FilterType, 
-    /// Do we want to perform asynchronous io operations ?
+    /// Quality of the generated jpeg thumbnails. Must be in the range 1..=100 where 1 is the
+    /// worst possible quality and 100 is the best.
+    #[structopt(short, long, default_value="80", parse(try_from_str=parse_quality))]
+    quality: u8,
+    /// The largest size (in bytes) of each thumbnail. The thumbnails of the lossy formats (jpeg) 
+    /// which would be larger are encoded again with the highest quality (below `--quality`, and
+    /// no lower than 10) at which they fit. The other formats are left as they are.
+    #[structopt(long)]
+    max_bytes: Option<u64>,
+    /// Writes progressive jpeg thumbnails, which are shown coarsely by the web browsers as soon as
+    /// their first bytes are received and refined as the rest arrives. They tend to be somewhat
+    /// larger than the baseline ones for tiny sizes. 
+    #[structopt(long)]
+    progressive: bool,
+    /// How the chroma (the color) of the jpeg thumbnails is subsampled: '444' keeps it at full
+    /// resolution, which keeps colored text and line art crisp, '422' halves it horizontally and
+    /// '420' halves it in both directions, which makes smaller files. Ignored by the other formats.
+    #[structopt(long, default_value="444")]
+    chroma: Chroma,
+    /// The format of the generated thumbnails. 
+    /// Can be either of: 'jpeg' (default), 'png', 'webp', 'avif', 'bmp', 'gif' (see `--list-formats`)
+    /// When it is not given, the format is inferred from the extension of the name template (or
+    /// of `dst` when it names a single file, see `--montage`), e.g. '{stem}.png' writes png files.
+    /// The quality is taken into account for the lossy formats (jpeg, webp, avif). Encoding webp
+    /// and avif requires the 'webp-encoder' and 'avif-encoder' features of the image crate which
+    /// this build does not enable: asking for these formats is reported as an error.
+    #[structopt(short="o", long)]
+    format: Option<OutputFormat>,
+    /// Encodes the thumbnails losslessly (e.g. for icons and other graphics), ignoring `--quality`.
+    /// Webp then uses its lossless encoder rather than the lossy one, png and bmp are always
+    /// lossless anyway, while jpeg, avif and gif (whose palette holds 256 colors at most) have no
+    /// lossless mode: asking for it is an error.
+    #[structopt(long)]
+    lossless: bool,
+    /// How the images are fit into the width x height box.
+    /// Can be either of: 'exact' (default) which stretches the image to the exact box size, 'fit' 
+    /// which preserves the aspect ratio and fits the image inside the box, 'fill' which preserves 
+    /// the aspect ratio and center-crops the overflow so that the thumbnail is exactly the box size.
+    #[structopt(short, long, default_value="exact")]
+    mode: ResizeMode,
+    /// Shorthand for `--mode fit`: the width and height are treated as a bounding box and the 
+    /// image is scaled so as to fit inside that box while preserving its aspect ratio.
     #[structopt(short, long)]
+    keep_aspect: bool,
+    /// By default, an image which is already smaller than the bounding box is left at its own size
+    /// in 'fit' mode. This flag allows such images to be scaled up to fit the box. The jpeg and
+    /// png images left at their own size are copied as they are when they are written in the
+    /// output format, no other transformation is asked for and they carry no metadata (EXIF, XMP
+    /// nor ICC profile) or `--preserve-metadata` is given, in which case the copies keep them all.
+    #[structopt(long)]
+    allow_upscale: bool,
+    /// The color (as RRGGBB hexadecimal digits) over which the transparent images are flattened
+    /// when the output format has no transparency (jpeg and bmp). The other formats keep the 
+    /// transparency intact.
+    #[structopt(long, default_value="ffffff")]
+    background: Color,
+    /// The shape of the thumbnails: 'rectangle' (default), 'circle' (the largest circle centered
+    /// in the thumbnail, e.g. for avatars) or 'rounded:R' (rounded corners of R pixels radius).
+    /// Out of the shape, the thumbnails are transparent when the output format supports it and 
+    /// painted with the background color otherwise.
+    #[structopt(long, default_value="rectangle")]
+    shape: Shape,
+    /// An image (e.g. a logo, preferably a png with transparency) composited onto each thumbnail.
+    /// It is scaled relative to each thumbnail (see `--watermark-size`), never used at its own
+    /// size.
+    #[structopt(long, value_name="PATH")]
+    watermark: Option<PathBuf>,
+    /// Where the watermark is placed: 'tl' (top left), 'tr', 'bl', 'br' (default) or 'center'
+    #[structopt(long, default_value="br", value_name="POSITION")]
+    watermark_position: WatermarkPosition,
+    /// The opacity of the watermark, from 0 (invisible) to 1 (fully opaque)
+    #[structopt(long, default_value="0.5", value_name="OPACITY", parse(try_from_str=parse_opacity))]
+    watermark_opacity: f32,
+    /// The largest fraction of the width and height of the thumbnails the watermark may cover
+    /// (its aspect ratio is preserved)
+    #[structopt(long, default_value="0.25", value_name="FRACTION", parse(try_from_str=parse_watermark_size))]
+    watermark_size: f32,
+    /// A template for the names of the thumbnails, e.g. '{parent}_{stem}_{width}x{height}.{ext}'.
+    /// The available tokens are {stem} (source name without extension), {name} (source name with
+    /// its extension), {ext} (extension of the output format), {width}, {height}, {parent} (name of the source folder) and {n} (a counter
+    /// of the source images, numbered in the order of the scan whatever the number of threads, see
+    /// `--sorted`). The template replaces the default naming entirely; two thumbnails 
+    /// getting the same name are handled as per `--on-collision`.
+    #[structopt(long)]
+    name_template: Option<NameTemplate>,
+    /// A suffix inserted before the extension of the thumbnail names (e.g. '_thumb' turns 
+    /// 'photo.tif' into 'photo_thumb.jpg'). The files whose name already ends with that suffix
+    /// are never processed, which makes it possible to write the thumbnails next to the source
+    /// images (using the source folder as destination). Not used with `--name-template`.
+    #[structopt(long, default_value="")]
+    suffix: String,
+    /// A prefix inserted at the start of the thumbnail names (e.g. 'thumb_' turns 'photo.tif' 
+    /// into 'thumb_photo.jpg'). Just like with `--suffix`, the files whose name already starts 
+    /// with that prefix are never processed. Not used with `--name-template`.
+    #[structopt(long, default_value="")]
+    prefix: String,
+    /// Keeps the extension of the source images in the thumbnail names, e.g. 'photo.tif' gets the
+    /// thumbnail 'photo.tif.jpg' rather than 'photo.jpg'. This keeps apart the thumbnails of the 
+    /// images bearing the same name with different extensions (e.g. 'a.tif' and 'a.png'). With
+    /// `--name-template`, use the {name} token instead.
+    #[structopt(long, conflicts_with="name-template")]
+    keep_original_ext: bool,
+    /// Replaces the characters which some file systems do not allow in a file name (e.g. ':' 
+    /// on Windows) by underscores in the thumbnail names. The dots and spaces ending a name are
+    /// stripped and the names reserved by Windows (e.g. 'con') are prefixed with an underscore.
+    /// The renamed thumbnails are reported with `-v`.
+    #[structopt(long)]
+    sanitize: bool,
+    /// Turns the thumbnail names to lowercase (after `--sanitize`)
+    #[structopt(long, conflicts_with="uppercase")]
+    lowercase: bool,
+    /// Turns the thumbnail names to uppercase (after `--sanitize`)
+    #[structopt(long)]
+    uppercase: bool,
+    /// What to do when two images would produce a thumbnail with the same name (e.g. 'a.tif' 
+    /// and 'a.png'). Can be either of: 'error' (default) which aborts the run, 'skip' which 
+    /// skips the second image, 'rename' which appends '_1', '_2', ... to the second thumbnail name.
+    #[structopt(long, default_value="error")]
+    on_collision: OnCollision,
+    /// Writes all the thumbnails in `dst` itself rather than in the subfolders mirroring those of
+    /// `src`. The images of different folders bearing the same name then get the same thumbnail
+    /// name, which is handled as per `--on-collision` (e.g. 'rename'). A `--name-template` still
+    /// applies and its names are written in `dst` as well: include the {parent} token (e.g. 
+    /// '{parent}_{stem}.{ext}') to tell these images apart by the name of their folder.
+    #[structopt(long)]
+    flatten: bool,
+    /// The images having more than this number of pixels (width x height) are not processed: they
+    /// are reported as failures without being decoded. This keeps the memory usage in check when
+    /// some source images are huge (or are decompression bombs). By default, there is no limit.
+    #[structopt(long)]
+    max_pixels: Option<u64>,
+    /// The source images which are already written in the output format (jpeg or png) and have
+    /// the size of their thumbnail are copied as they are, rather than decoded and encoded again
+    /// (which would lose some quality), as long as no other transformation applies and they carry
+    /// no metadata which would leak into the copies (see `--preserve-metadata`). This sets how
+    /// many pixels (in each dimension) their size may differ from that of their thumbnail for
+    /// them to be copied: 0 (the default) only copies the images having exactly that size.
+    #[structopt(long, default_value="0", value_name="pixels")]
+    same_format_passthrough: u32,
+    /// Skips the source images narrower than this (in pixels). Like the other size bounds, it 
+    /// applies to the size of the image as stored (before any rotation), which is read from its
+    /// header without decoding it. The skipped images are counted as such.
+    #[structopt(long)]
+    min_width: Option<u32>,
+    /// Skips the source images shorter than this (in pixels)
+    #[structopt(long)]
+    min_height: Option<u32>,
+    /// Skips the source images wider than this (in pixels)
+    #[structopt(long)]
+    max_width: Option<u32>,
+    /// Skips the source images taller than this (in pixels)
+    #[structopt(long)]
+    max_height: Option<u32>,
+    /// The (0-based) index of the frame of the animated images (gif, png and webp) or of the page
+    /// of the multi-page tiff images to turn into a thumbnail. Images having a single frame fail
+    /// when this is not 0.
+    #[structopt(long, default_value="0")]
+    frame: usize,
+    /// Creates one thumbnail per frame (or page) of each image, named after the index of the 
+    /// frame (e.g. 'name_0.jpg', 'name_1.jpg', ...). Images having a single frame get a single
+    /// thumbnail, which is named 'name_0.jpg' as well. This overrides `--frame`. 
+    #[structopt(long)]
+    all_frames: bool,
+    /// Rotates and flips the images according to their EXIF orientation so that the thumbnails
+    /// are upright (only for JPEG and TIFF sources). Use `--auto-orient false` to disable it.
+    #[structopt(long, default_value="true", parse(try_from_str))]
+    auto_orient: bool,
+    /// Rotates the images clockwise by this angle: 0 (default), 90, 180 or 270 degrees. This is 
+    /// applied after the auto-orientation (e.g. for batches of scans which are all rotated the 
+    /// same way) and before the flips.
+    #[structopt(long, default_value="0", parse(try_from_str=parse_rotation))]
+    rotate: u16,
+    /// Flips the images horizontally (after the rotation)
+    #[structopt(long)]
+    flip_h: bool,
+    /// Flips the images vertically (after the rotation and the horizontal flip)
+    #[structopt(long)]
+    flip_v: bool,
+    /// Converts the thumbnails to shades of gray (e.g. for documents)
+    #[structopt(long)]
+    grayscale: bool,
+    /// Brightens (positive values) or darkens (negative values) the thumbnails. This is added to 
+    /// each color channel and must be in the range -255..=255.
+    #[structopt(long, default_value="0", allow_hyphen_values=true, parse(try_from_str=parse_brightness))]
+    brightness: i32,
+    /// Increases (positive values) or decreases (negative values) the contrast of the thumbnails.
+    /// This is a percentage in the range -100..=100. The color adjustments are applied after the
+    /// resize, in this order: grayscale, brightness, contrast.
+    #[structopt(long, default_value="0", allow_hyphen_values=true, parse(try_from_str=parse_contrast))]
+    contrast: f32,
+    /// Sharpens the thumbnails right after the resize (before the color adjustments) with an 
+    /// unsharp mask, which makes up for the softness of the downscaled images (especially with 
+    /// the triangle and gaussian filters). The amount ranges from 0 (no sharpening) to 5, 1 being
+    /// a plain unsharp mask.
+    #[structopt(long, default_value="0", value_name="AMOUNT", parse(try_from_str=parse_sharpen))]
+    sharpen: f32,
+    /// Copies the ICC color profile and the EXIF orientation of the source images into the
+    /// thumbnails (for the jpeg and png formats only). By default, the thumbnails carry no 
+    /// metadata at all: this keeps them small and avoids leaking private information (e.g. the
+    /// GPS position of a photo). With this flag, the source images copied as they are (see
+    /// `--same-format-passthrough`) keep all their metadata.
+    #[structopt(long)]
+    preserve_metadata: bool,
+    /// Records this resolution (in dots per inch) in the JPEG and PNG thumbnails, so that the 
+    /// applications (e.g. a print preview) know their physical size. The other formats ignore it.
+    #[structopt(long)]
+    dpi: Option<NonZeroU16>,
+    /// Converts the thumbnails to sRGB from the color space of the ICC profile embedded in their
+    /// source (e.g. Adobe RGB or Display P3), which would otherwise look desaturated in the 
+    /// browsers ignoring the profile. Only the matrix-based profiles are converted (which is 
+    /// what the RGB working spaces are made of), the other images are left untouched.
+    #[structopt(long)]
+    to_srgb: bool,
+    /// Gives the thumbnails the same access and modification times as their source image (which
+    /// helps sorting them). Note that `--overwrite if-newer` then only regenerates the thumbnails
+    /// whose source has been modified since.
+    #[structopt(long)]
+    preserve_timestamps: bool,
+    /// When the existing thumbnails are regenerated. Can be either of: 'always' (default), 
+    /// 'never' which leaves the existing thumbnails untouched, 'if-newer' which only regenerates
+    /// the thumbnails which are older than their source image (that is, when the source image has
+    /// been modified since the thumbnail was generated) or 'if-changed' which only regenerates the
+    /// thumbnails whose source image has a different content (as per the hashes recorded in a
+    /// `.thumbmanifest` file of `dst`, which is more robust than the modification times but
+    /// reads every source image on each run). The images whose thumbnails need not be 
+    /// regenerated are skipped before anything gets decoded.
+    #[structopt(long, default_value="always")]
+    overwrite: Overwrite,
+    /// Shorthand for `--overwrite never`: only the images missing some of their thumbnails in
+    /// `dst` are processed, whatever the modification times (which tops up a
+    /// thumbnail cache).
+    #[structopt(long, visible_alias="missing-only")]
+    skip_existing: bool,
+    /// Shorthand for `--overwrite if-newer` (`--incremental` or `--incremental=mtime`) or for
+    /// `--overwrite if-changed` (`--incremental=hash`)
+    #[structopt(short, long, value_name="MODE", require_equals=true)]
+    incremental: Option<Option<Incremental>>,
+    /// Shows a progress bar (with an ETA) while the thumbnails are being generated. The bar is
+    /// only drawn when stderr is a terminal.
+    #[structopt(long, default_value="true", parse(try_from_str))]
+    progress: bool,
+    /// Do not print anything but the errors (this hides the progress bar, the number of images
+    /// selected by the scan and the summary printed at the end of the run).
+    #[structopt(long)]
+    quiet: bool,
+    /// Logs what happens on stderr: '-v' tells which files are selected or ignored (and why) and
+    /// when each file is processed, '-vv' also tells where each thumbnail is written.
+    #[structopt(short, long, parse(from_occurrences))]
+    verbose: u8,
+    /// Abort the whole run as soon as one file cannot be processed. By default, the files which
+    /// cannot be processed are reported at the end of the run.
+    #[structopt(long)]
+    fail_fast: bool,
+    /// Writes a JSON report of the run to this file: the summary counters and, for each source 
+    /// image, its thumbnails, its status ('ok', 'skipped' or 'failed'), the error (if any) and 
+    /// the number of bytes read and written. The report is written even with `--quiet`.
+    #[structopt(long)]
+    report: Option<PathBuf>,
+    /// Also creates a low quality image placeholder of each source (a blurred 16px jpeg, encoded 
+    /// as a base64 data URI to be inlined in a web page while the thumbnails load) and records it
+    /// in the report, under the 'lqip' key of the image.
+    #[structopt(long, requires="report")]
+    lqip: bool,
+    /// Deflates the entries of a zip destination (see `dst`). By default, they are stored as they
+    /// are since the thumbnails are compressed already: deflating them seldom saves much.
+    #[structopt(long)]
+    deflate: bool,
+    /// Also computes the blurhash of each source (a short string from which a blurred preview is
+    /// drawn while the thumbnails load) and records it in the report, under the 'blurhash' key of 
+    /// the image. The value gives the number of components along the x and y axes (1..=9 each):
+    /// the more components, the more details.
+    #[structopt(long, requires="report", value_name="XxY")]
+    blurhash: Option<Option<BlurhashComponents>>,
+    /// How many times a read or a write failing with a transient io error (a timeout or an 
+    /// interruption, e.g. on a network share) is retried before the file is counted as failed.
+    /// The decoding errors are never retried.
+    #[structopt(long, default_value="0")]
+    retries: u32,
+    /// The delay (in milliseconds) before the first retry. It doubles after each retry.
+    #[structopt(long, default_value="100")]
+    retry_delay: u64,
+    /// How long (in seconds) the server of a source image given as an `http://` URL may take to
+    /// accept the connection, to take the request or to send each part of its response. A timeout
+    /// is retried as per `--retries`. The `https://` URLs are not supported by this build.
+    #[structopt(long, default_value="30")]
+    http_timeout: u64,
+    /// The largest number of source images fetched from `http://` URLs at the same time.
+    #[structopt(long, default_value="4")]
+    http_limit: usize,
+    /// Exit successfully even when some files could not be processed.
+    #[structopt(long)]
+    ignore_errors: bool,
+    /// Only prints the images which would be processed and where their thumbnails would be 
+    /// written, without creating any folder or file.
+    #[structopt(long)]
+    dry_run: bool,
+    /// Only guesses (without creating any folder or file) the rotation which would turn upright the
+    /// scanned documents of each folder, and prints the likely `--rotate` value of each folder. The
+    /// guess is made from the first 5 images of the folder which have no thumbnail yet (or all of
+    /// them with `--overwrite always`), and assumes they are pages of left-aligned text.
+    #[structopt(long)]
+    detect_orientation: bool,
+    /// Keeps running after the images of `src` are processed, and processes the images which are
+    /// created or modified in `src` afterwards (with the very same options) until Ctrl-C is hit.
+    /// The folder is scanned again every `--watch-interval`: an image is only processed once its
+    /// size and modification time are the same on two successive scans, that is once it is
+    /// completely written. The destination cannot be an archive, nor can the source.
+    #[structopt(long, conflicts_with_all=&["montage", "from-file", "dry-run", "bench"])]
+    watch: bool,
+    /// The delay (in milliseconds) between two scans of the source folder in `--watch` mode.
+    #[structopt(long, default_value="1000")]
+    watch_interval: u64,
+    /// Do we want to perform asynchronous io operations ? By default, the asynchronous pipeline is
+    /// only used for large batches (1000 images or more) and for the images lying on a network 
+    /// file system (nfs, smb, sshfs...), where it keeps many reads in flight.
+    #[structopt(short, long, conflicts_with="synchronous")]
     asynchronous: bool,
+    /// Always use the synchronous pipeline (see `--asynchronous`).
+    #[structopt(long)]
+    synchronous: bool,
+    /// Measures the time spent decoding the images, resizing them and encoding the thumbnails, and
+    /// prints the breakdown at the end of the run. The durations are cumulated over all the
+    /// threads; the encoding includes the writing of the thumbnails which are streamed to their
+    /// files.
+    #[structopt(long)]
+    timings: bool,
+    /// Benchmarks the throughput of each filter with both the synchronous and the asynchronous
+    /// pipelines instead of performing a single run. All benchmark runs process the same images
+    /// (ignoring `--filter` and `--asynchronous`) and overwrite the same thumbnails. The result
+    /// is printed as a table giving the number of images processed per second.
+    #[structopt(long)]
+    bench: bool,
+    /// Prints the filters accepted by `--filter` (along with their aliases) and exits.
+    // read from the matches by `parse_args`, since the `Args` cannot be built without `src`
+    #[allow(dead_code)]
+    #[structopt(long)]
+    list_filters: bool,
+    /// Prints the formats accepted by `--format` (along with their aliases and the feature their
+    /// encoder requires, when this build lacks it) and exits.
+    #[allow(dead_code)]
+    #[structopt(long)]
+    list_formats: bool,
 }
 
-/// The kind of errors that could potentially happen
-#[derive(Debug, thiserror::Error)]
-pub enum Error {
-    #[error("Cannot parse filter type. The only authorized values are 'nearest', 'triangle', 'gaussian', 'catmull-rom', 'lanczos3'")]
-    CannotParseFilterType,
-    #[error("problem while processing image {0}")]
-    Image(#[from] image::error::ImageError),
-    #[error("io error {0}")]
-    Io(#[from] std::io::Error),
-    #[error("smol send error {0}")]
-    SendError(String),
-    #[error("error joining scoped threads {0}")]
-    JoinError(String),
-}
-
-/// Resizes *one* image and save it to the new folder
-fn resize_image(input: &[u8], output: &mut Cursor<Vec<u8>>, w: u32, h: u32, f: image::imageops::FilterType) -> Result<(), self::Error>
-{  
-    let im = image::load_from_memory(input)?;
-    let im = image::imageops::resize(&im, w, h, f);
-    im.write_to(output, ImageOutputFormat::Jpeg(8))?;
-    Ok(())
+/// The width of the thumbnails when neither `--width` nor `--height` is given
+const DEFAULT_WIDTH: u32 = 120;
+
+/// The height of the thumbnails when neither `--width` nor `--height` is given
+const DEFAULT_HEIGHT: u32 = 150;
+
+/// The options taking a boolean value, which are not mere flags
+const BOOLEAN_OPTIONS: &[&str] = &["auto-orient", "progress"];
+
+/// The options which can be repeated (the other ones take a comma separated list of values)
+const REPEATED_OPTIONS: &[&str] = &["include", "exclude"];
+
+/// Parses the command line arguments, completed with the options of the `--config` file (if
+/// any, see `config_arguments`).
+fn parse_args() -> Result<Args, Error> {
+    let matches = Args::clap().get_matches();
+    if matches.is_present("list-filters") || matches.is_present("list-formats") {
+        // just like `--help`, the lists do not need the rest of the arguments
+        if matches.is_present("list-filters") {
+            for filter in FilterType::ALL {
+                println!("{}", with_aliases(filter.name(), filter.aliases()));
+            }
+        }
+        if matches.is_present("list-formats") {
+            for format in OutputFormat::ALL {
+                match format.missing_feature() {
+                    Some(feature) => println!("{} (requires the {feature} feature)", with_aliases(format.name(), format.aliases())),
+                    None          => println!("{}", with_aliases(format.name(), format.aliases())),
+                }
+            }
+        }
+        std::process::exit(0);
+    }
+    let args = Args::from_clap(&matches);
+    let Some(config) = args.config else {
+        return Ok(args);
+    };
+    let cli = env::args_os().collect::<Vec<_>>();
+    let options = config_arguments(read_config(&config)?, &cli);
+    let (program, args) = cli.split_first().expect("the command line names the program");
+    Ok(Args::from_iter(std::iter::once(program.clone()).chain(options.into_iter().map(Into::into)).chain(args.iter().cloned())))
 }
 
-fn sync_version(src: PathBuf, dst: PathBuf, width: u32, height: u32, filter: image::imageops::FilterType) -> Result<(), self::Error>{
-    let input = fs::read(src)?;
-    let mut output = Cursor::new(vec![]);
-    resize_image(&input, &mut output, width, height, filter)?;
-    fs::write(dst, output.into_inner())?;
-    Ok(())
+/// The command line arguments which the options of a config file stand for (a flag set to true
+/// is given, an array repeats the options which can be repeated for each of its values and is 
+/// joined with commas for the other ones) once the command line `cli` has
+/// taken precedence: an option is left out when it is on the command line, or when one of the
+/// arguments of the command line conflicts with it (e.g. `--asynchronous` with the option
+/// `synchronous`, or `--square` with `width`).
+fn config_arguments(config: Vec<(String, ConfigValue)>, cli: &[OsString]) -> Vec<String> {
+    let matches = Args::clap().get_matches_from_safe(cli).ok();
+    let (program, args) = cli.split_first().expect("the command line names the program");
+    let mut options = vec![];
+    for (key, value) in config {
+        let name = key.replace('_', "-");
+        if matches.as_ref().is_some_and(|matches| matches.occurrences_of(&name) > 0) {
+            continue;
+        }
+        let long = format!("--{name}");
+        let arguments = match value {
+            ConfigValue::Boolean(set) if !BOOLEAN_OPTIONS.contains(&name.as_str()) => if set { vec![long] } else { vec![] },
+            ConfigValue::Array(values) if REPEATED_OPTIONS.contains(&name.as_str()) => values.into_iter().map(|value| format!("{long}={value}")).collect(),
+            value => vec![format!("{long}={value}")],
+        };
+        // clap tells which arguments conflict with each other
+        let trial = std::iter::once(program.clone()).chain(arguments.iter().map(Into::into)).chain(args.iter().cloned());
+        if let Err(error) = Args::clap().get_matches_from_safe(trial) {
+            if error.kind == clap::ErrorKind::ArgumentConflict {
+                continue;
+            }
+        }
+        options.extend(arguments);
+    }
+    options
 }
-/*
-async fn async_version(srcname: PathBuf, dstname: PathBuf, w: u32, h: u32, f: image::imageops::FilterType) -> Result<(), self::Error>{
-    let input = tokio::fs::read(srcname).await?;
-    
-    let output = tokio::task::spawn_blocking(move || async move {
-        let mut out = Cursor::new(vec![]);
-        _ = resize_image(&input, &mut out, w, h, f).unwrap();
-        out
-    });
 
-    let output = output.await?.await;
+/// The name of a value accepted on the command line followed by its aliases, e.g. 'box, area'
+fn with_aliases(name: &str, aliases: &[&str]) -> String {
+    std::iter::once(name).chain(aliases.iter().copied()).collect::<Vec<_>>().join(", ")
+}
+
+/// The time spent in each stage of the run (see `--timings`)
+static TIMINGS: Timings = Timings::new();
+
+/// Raised when the user hits Ctrl-C
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
 
-    tokio::fs::write(dstname, output.into_inner()).await?;
+/// Makes Ctrl-C stop the run gracefully: the files being processed are completed but no other
+/// file is. Hitting Ctrl-C a second time kills the process right away.
+fn handle_interrupts() -> Result<(), Error> {
+    // SAFETY: the action only uses async-signal-safe operations (an atomic swap and _exit)
+    unsafe {
+        signal_hook_registry::register(libc::SIGINT, || {
+            if INTERRUPTED.swap(true, Ordering::Relaxed) {
+                libc::_exit(130);
+            }
+        })?;
+    }
     Ok(())
 }
-*/
-fn prepare(src: &str, dst: &str, extension: &str, list: &mut Vec<(PathBuf, PathBuf)>) -> Result<(), Error>{
-    if !Path::new(dst).try_exists()? {
-        fs::create_dir_all(dst)?;
-    }
-
-    let entries = std::fs::read_dir(src)?;
-    for entry in entries {
-        let entry = entry?;
-        let path = entry.path();
-        if path.is_dir() {
-            let out = PathBuf::from_str(dst).unwrap().join(path.file_name().unwrap().to_str().unwrap());
-            prepare(path.to_str().unwrap(), out.to_str().unwrap(), extension, list)?;
-        } else {
-            let ext = path.extension();
-            if let Some(ext) = ext {
-                if ext.eq_ignore_ascii_case(extension) {
-                    let fstem = path.file_stem().map(|x| x.to_str()).unwrap_or_default().unwrap_or("unk");
-                    let dstname = PathBuf::from(&dst).join(format!("{fstem}.jpg"));
-                    
-                    list.push((path, dstname));
-                }
-            }
+
+/// A minimal logger printing the messages of this crate on stderr (the messages of the 
+/// dependencies, e.g. smol, are not relevant to the users)
+struct Logger;
+static LOGGER: Logger = Logger;
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= log::max_level() && metadata.target().starts_with("thumbnails")
+    }
+    fn log(&self, record: &Record) {
+        if self.enabled(record.metadata()) {
+            eprintln!("[{}] {}", record.level(), record.args());
         }
     }
+    fn flush(&self) {}
+}
 
-    Ok(())
+pub fn main() -> ExitCode {
+    match try_main() {
+        Ok(code)   => code,
+        Err(error) => {
+            eprintln!("error: {error}");
+            ExitCode::FAILURE
+        },
+    }
 }
 
-pub fn main() -> Result<(), self::Error>{
-    let Args { src, dst, width, height, threads, limit, extension, filter, asynchronous } = Args::from_args();
+/// Performs the whole run asked by the command line arguments
+fn try_main() -> Result<ExitCode, Error>{
+    let Args { src, dst, config: _, width, height, sizes, square, scale, montage, threads, limit, extension, include, from_file, exclude, max_depth, follow_symlinks, sorted, filter, quality, max_bytes, progressive, chroma, format, lossless, mode, keep_aspect, allow_upscale, background, shape, watermark, watermark_position, watermark_opacity, watermark_size, name_template, suffix, prefix, keep_original_ext, sanitize, lowercase, uppercase, on_collision, flatten, max_pixels, same_format_passthrough, min_width, min_height, max_width, max_height, frame, all_frames, auto_orient, rotate, flip_h, flip_v, grayscale, brightness, contrast, sharpen, preserve_metadata, dpi, to_srgb, preserve_timestamps, overwrite, skip_existing, incremental, progress, quiet, verbose, fail_fast, report, lqip, deflate, blurhash, retries, retry_delay, http_timeout, http_limit, ignore_errors, dry_run, detect_orientation, watch, watch_interval, asynchronous, synchronous, timings, bench, .. } = parse_args()?;
     
-    let mut list = vec![];
-    prepare(&src, &dst, &extension, &mut list)?;
-
-    let f = filter.into();
-    if asynchronous {
-        type Input  = (Vec<u8>, PathBuf);
-        type InSx   = Sender<Input>;
-        type InRx   = Receiver<Input>;
-        type Output = (Vec<u8>, PathBuf);
-        type OutSx  = Sender<Output>;
-        type OutRx  = Receiver<Output>;
-        
-        let (input_sx, input_rx): (InSx, InRx) = smol::channel::bounded(limit);
-        let (output_sx, output_rx): (OutSx, OutRx) = smol::channel::bounded(limit);
-        
-        // opening n-files asynchronously 
-        let open_files = smol::spawn(
-            async move {
-                for (src, dst) in list {
-                    let mut x= smol::fs::File::open(src).await?;
-                    let mut content = vec![];
-                    x.read_to_end(&mut content).await?; 
-                    input_sx.send((content, dst)).await.map_err(|se| self::Error::SendError(format!("{se}")))?;
-                }
+    log::set_logger(&LOGGER).expect("no other logger is installed");
+    log::set_max_level(match (quiet, verbose) {
+        (true, _) => LevelFilter::Error,
+        (_, 0)    => LevelFilter::Info,
+        (_, 1)    => LevelFilter::Debug,
+        _         => LevelFilter::Trace,
+    });
 
-                drop(input_sx);
-                Result::<(), self::Error>::Ok(())
-            });
+    let single = src == "-" || dst == "-";
+    let format = format
+        .or_else(|| match (single || montage.is_some(), &name_template) {
+            (true, _)              => OutputFormat::from_path(Path::new(&dst)),
+            (false, Some(template)) => template.extension().and_then(OutputFormat::from_extension),
+            (false, None)          => None,
+        })
+        .unwrap_or(OutputFormat::Jpeg);
+    let (width, height) = match (width, height) {
+        (None, None)                          => (DEFAULT_WIDTH, DEFAULT_HEIGHT),
+        // each cell of a contact sheet has a fixed size
+        (width, height) if montage.is_some() => (width.unwrap_or(DEFAULT_WIDTH), height.unwrap_or(DEFAULT_HEIGHT)),
+        (width, height)                      => (width.unwrap_or(0), height.unwrap_or(0)),
+    };
+    let (width, height, mode) = match square {
+        Some(n) => (n, n, ResizeMode::Fill),
+        None    => (width, height, if keep_aspect { ResizeMode::Fit } else { mode }),
+    };
+    // the watermark is shared by all the thumbnails, until the very end of the run
+    let watermark = match watermark {
+        Some(path) => Some(&*Box::leak(Box::new(Watermark::open(&path, watermark_position, watermark_opacity, watermark_size)?))),
+        None       => None,
+    };
+    let job = ThumbnailJob { width, height, scale, filter, quality, max_bytes, progressive, chroma, lossless, format, mode, allow_upscale, auto_orient, rotate, flip_h, flip_v, grayscale, brightness, contrast, sharpen, preserve_metadata, to_srgb, shape, watermark, background, frame: if all_frames { Frame::All } else { Frame::Index(frame) }, dpi: dpi.map(NonZeroU16::get), max_pixels, passthrough: same_format_passthrough };
+    let jobs = match sizes {
+        None            => vec![job],
+        Some(Sizes(ss)) => ss.into_iter().map(|(width, height)| ThumbnailJob { width, height, ..job }).collect(),
+    };
 
-        let write_files = smol::spawn(async move {
-            while let Ok((out_data, out_path)) = output_rx.recv().await {
-                smol::fs::write(out_path, out_data).await?;
+    if single {
+        single_image(&src, &dst, &job)?;
+        return Ok(ExitCode::SUCCESS);
+    }
+
+    let overwrite = match (skip_existing, incremental) {
+        (true, _)                    => Overwrite::Never,
+        (_, Some(Some(incremental))) => incremental.overwrite(),
+        (_, Some(None))              => Overwrite::IfNewer,
+        _                            => overwrite,
+    };
+    // the thumbnails go to an archive rather than to the destination folder
+    let archive = if montage.is_none() { ArchiveFormat::from_path(Path::new(&dst)) } else { None };
+    let folder  = if archive.is_some() { "" } else { dst.as_str() };
+    check_paths(Path::new(&src), Path::new(&dst), montage.is_some() || archive.is_some(), from_file.is_none())?;
+    // all the images appear on the contact sheet (or in the archive), whatever thumbnails exist
+    let overwrite = if montage.is_some() || archive.is_some() { Overwrite::Always } else { overwrite };
+    // the manifest is shared by the scan and the pipeline, until the very end of the run
+    let manifest = match overwrite {
+        Overwrite::IfChanged => Some(&*Box::leak(Box::new(Manifest::open(Path::new(folder))?))),
+        _                    => None,
+    };
+    let case = match (lowercase, uppercase) {
+        (true, _) => Case::Lower,
+        (_, true) => Case::Upper,
+        _         => Case::Keep,
+    };
+    let scan = Scan { extension, include, exclude, overwrite, manifest, template: name_template, suffix, prefix, keep_original_ext, sanitize, case, on_collision, max_depth, follow_symlinks, sorted, flatten };
+    let stats = if report.is_some() { Stats::with_report() } else { Stats::new() };
+    // the source images are the entries of a zip archive, which is read until the very end of the run
+    let source = match ArchiveFormat::from_path(Path::new(&src)) {
+        Some(ArchiveFormat::Zip) if Path::new(&src).is_file() => Some(&*Box::leak(Box::new(ZipSource::open(Path::new(&src))?))),
+        _ => None,
+    };
+    if watch {
+        if source.is_some() {
+            return Err(Error::InvalidSource(PathBuf::from(&src), "a zip archive cannot be watched".to_string()));
+        }
+        if archive.is_some() {
+            return Err(Error::InvalidDestination(PathBuf::from(&dst), "an archive cannot be written while watching".to_string()));
+        }
+    }
+    // the images of a plain run are processed while the source folder is scanned
+    let streamed = from_file.is_none() && source.is_none() && montage.is_none() && !(watch || dry_run || detect_orientation || bench);
+    let list = match (from_file, source) {
+        // the watch performs its own scans
+        _ if watch || streamed => vec![],
+        (Some(file), _)        => prepare_files(read_list(&file)?, &src, folder, &scan, &jobs, &stats)?,
+        (_, Some(source))      => prepare_archive(source, folder, &scan, &jobs, &stats)?,
+        _                      => prepare(&src, folder, &scan, &jobs, &stats)?,
+    };
+    // a sanity check before a long run, e.g. against a misspelled extension
+    if !(watch || streamed) {
+        log::info!("{} image(s) selected, {} skipped", list.len(), stats.skipped_count());
+    }
+
+    if dry_run {
+        let mut bytes = 0;
+        for Task { src, dst: thumbnails, .. } in list.iter() {
+            bytes += match source {
+                Some(source)           => source.size(src).unwrap_or_default(),
+                // the size of a URL is only known once it is fetched
+                None if is_url(src)    => 0,
+                None                   => fs::metadata(src)?.len(),
+            };
+            match (montage, archive) {
+                (Some(_), _) => println!("{} -> {dst}", src.display()),
+                (_, Some(_)) => for d in thumbnails {
+                    println!("{} -> {dst}:{}", src.display(), d.display());
+                },
+                _            => for d in thumbnails {
+                    println!("{} -> {}", src.display(), d.display());
+                },
             }
-            
-            drop(output_rx);
-            Result::<(), self::Error>::Ok(())
-        });
-
-        let cpu = threads.unwrap_or_else(num_cpus::get);
-        std::thread::scope(|s| {
-            let mut threads = vec![];
-            for _ in 0..cpu {
-                let irx = input_rx.clone();
-                let osx = output_sx.clone();
-                threads.push(s.spawn(move || {
-                    while let Ok((data, path)) = irx.recv_blocking() {
-                        let mut output = Cursor::new(vec![]);
-                        resize_image(&data, &mut output, width, height, f)?;
-                        osx.send_blocking((output.into_inner(), path)).map_err(|se| self::Error::SendError(format!("{se}")))?;
-                    }
-                    Result::<(), self::Error>::Ok(())
-                }));
+        }
+        println!("{} image(s) would be processed ({:.1} MiB to read)", list.len(), bytes as f64 / (1024.0 * 1024.0));
+        return Ok(ExitCode::SUCCESS);
+    }
+    // the http source is shared by all the readers until the very end of the run
+    let http = &*Box::leak(Box::new(HttpSource::new(Duration::from_secs(http_timeout), http_limit)));
+    if detect_orientation {
+        let pipeline = Pipeline { source, http: Some(http), ..Pipeline::default() };
+        for Suggestion { folder, rotate, votes, sampled } in suggest_rotations(&list, ORIENTATION_SAMPLES, auto_orient, &pipeline) {
+            match rotate {
+                Some(rotate) => println!("{}: --rotate {rotate} ({votes} of {sampled} sampled image(s))", folder.display()),
+                None         => println!("{}: undecided ({votes} of {sampled} sampled image(s))", folder.display()),
             }
-            for t in threads {
-                t.join()
-                    .map_err(|je| self::Error::JoinError(format!("{je:?}")))??;
+        }
+        return Ok(ExitCode::SUCCESS);
+    }
+
+    handle_interrupts()?;
+    let asynchronous = match (asynchronous, synchronous) {
+        (true, _) => Some(true),
+        (_, true) => Some(false),
+        _         => None,
+    };
+    // the archive is shared by all the stages of the pipeline until the very end of the run
+    let archive = match archive {
+        Some(format) => Some(&*Box::leak(Box::new(Archive::create(Path::new(&dst), format, deflate)?))),
+        None         => None,
+    };
+    let pipeline = Pipeline { asynchronous, limit, threads, fail_fast, interrupt: Some(&INTERRUPTED), preserve_timestamps, retries, retry_delay: Duration::from_millis(retry_delay), lqip, blurhash: blurhash.map(Option::unwrap_or_default), archive, source, http: Some(http), sizes: SizeRange { min_width, min_height, max_width, max_height }, timings: timings.then_some(&TIMINGS), manifest };
+    if bench {
+        return bench_run(&jobs, &pipeline, &list).map(|_| ExitCode::SUCCESS);
+    }
+
+    let progress = Progress::new(list.len(), progress && !quiet);
+    let failures = match montage {
+        Some(columns) => {
+            let sources = list.into_iter().map(|task| task.src).collect::<Vec<_>>();
+            thumbnails::montage(&sources, &job, columns, Path::new(&dst), &pipeline, &progress, &stats)?
+        },
+        None if watch => thumbnails::watch(&src, folder, &scan, &jobs, Duration::from_millis(watch_interval), &pipeline, &stats)?,
+        None => {
+            let failures = match streamed {
+                true  => run_streaming(&src, folder, &scan, &jobs, &pipeline, &progress, &stats),
+                false => run(&jobs, &pipeline, list, &progress, &stats),
+            };
+            // even an aborted run leaves a valid archive behind
+            if let Some(archive) = archive {
+                archive.finish()?;
             }
-            drop(input_rx);
-            drop(output_sx);
-            Result::<(), self::Error>::Ok(())
-        })?;
+            failures?
+        },
+    };
 
-        smol::block_on(async {
-            open_files.await?;
-            write_files.await?;
-            Result::<(), self::Error>::Ok(())
-        })?;
+    if let Some(manifest) = manifest {
+        manifest.save()?;
+    }
+    if !failures.is_empty() {
+        eprintln!("{} file(s) could not be processed:", failures.len());
+        for Failure { src, error } in failures.iter() {
+            eprintln!("  {}: {error}", src.display());
+        }
+    }
+    if !quiet {
+        println!("{stats}");
+    }
+    if timings {
+        println!("{TIMINGS}");
+    }
+    if let Some(report) = report {
+        stats.write_report(&mut io::BufWriter::new(fs::File::create(report)?))?;
+    }
+    // Ctrl-C is the normal way to stop watching
+    if INTERRUPTED.load(Ordering::Relaxed) && !watch {
+        eprintln!("interrupted: the remaining files have not been processed");
+        return Ok(ExitCode::from(130));
+    }
+    if failures.is_empty() || ignore_errors {
+        Ok(ExitCode::SUCCESS)
     } else {
-        list.into_par_iter().for_each(|(s, d)| {
-            sync_version(s, d, width, height, f).unwrap();
-        });
+        Ok(ExitCode::FAILURE)
+    }
+}
+
+/// Makes sure, before anything is done, that the source folder (or zip archive) `src` can be read
+/// when it is to be `scanned`, and that the destination `dst` can be written or created: it is a
+/// folder, or a file when `is_file` is set (the contact sheet or an archive).
+fn check_paths(src: &Path, dst: &Path, is_file: bool, scanned: bool) -> Result<(), Error> {
+    if scanned {
+        let invalid  = |reason: String| Error::InvalidSource(src.to_path_buf(), reason);
+        let metadata = fs::metadata(src).map_err(|e| invalid(e.to_string()))?;
+        let readable = match () {
+            _ if metadata.is_dir() => fs::read_dir(src).map(|_| ()),
+            _ if ArchiveFormat::from_path(src) == Some(ArchiveFormat::Zip) => fs::File::open(src).map(|_| ()),
+            _ => return Err(invalid("it is neither a folder nor a zip archive".to_string())),
+        };
+        readable.map_err(|e| invalid(e.to_string()))?;
+    }
+
+    let invalid = |reason: &str| Error::InvalidDestination(dst.to_path_buf(), reason.to_string());
+    if dst.is_dir() == is_file && dst.exists() {
+        return Err(invalid(if is_file { "it is a folder" } else { "it is not a folder" }));
+    }
+    // the folders which do not exist yet will be created in the nearest existing one
+    let folder = if is_file { dst.parent().unwrap_or(Path::new("")) } else { dst };
+    let existing = folder.ancestors().find(|p| p.as_os_str().is_empty() || p.exists()).unwrap_or(Path::new(""));
+    let existing = if existing.as_os_str().is_empty() { Path::new(".") } else { existing };
+    if !existing.is_dir() {
+        return Err(invalid(&format!("{} is not a folder", existing.display())));
+    }
+    if !writable(existing) {
+        return Err(invalid(&format!("{} is not writable", existing.display())));
     }
-    
     Ok(())
 }
 
-/// Utility cruft to make the cli more user friendly
-#[derive(Debug, Clone, Copy)]
-enum FilterType {
-    Nearest, 
-    Triangle, 
-    Gaussian, 
-    CatmullRom,
-    Lanczos3
-}
-impl FromStr for FilterType {
-    type Err = self::Error;
-
-    fn from_str(s: &str) -> Result<Self, Self::Err> {
-        match s {
-            "nearest"     => Ok(Self::Nearest),
-            "triangle"    => Ok(Self::Triangle),
-            "gaussian"    => Ok(Self::Gaussian),
-            "catmull-rom" => Ok(Self::CatmullRom),
-            "lanczos3"    => Ok(Self::Lanczos3),
-            _             => Err(self::Error::CannotParseFilterType)
+/// Tells whether the current user may create files in the `folder`
+#[cfg(unix)]
+fn writable(folder: &Path) -> bool {
+    use std::os::unix::ffi::OsStrExt;
+    let Ok(path) = std::ffi::CString::new(folder.as_os_str().as_bytes()) else {
+        return false;
+    };
+    // SAFETY: `path` is a valid nul terminated string
+    unsafe { libc::access(path.as_ptr(), libc::W_OK) == 0 }
+}
+#[cfg(not(unix))]
+fn writable(folder: &Path) -> bool {
+    fs::metadata(folder).map(|m| !m.permissions().readonly()).unwrap_or(false)
+}
+
+/// Processes the images of `list` once with each filter and each pipeline (synchronous or not),
+/// and prints the number of images processed per second in each configuration
+fn bench_run(jobs: &[ThumbnailJob], pipeline: &Pipeline, list: &[Task]) -> Result<(), Error> {
+    println!("{:<12} {:>12} {:>12}", "filter", "sync img/s", "async img/s");
+    // every image is resized with the filter being benchmarked, whatever its `.thumbfilter`
+    let list = list.iter().map(|task| Task { filter: None, ..task.clone() }).collect::<Vec<_>>();
+    for filter in FilterType::ALL {
+        let jobs = jobs.iter().map(|job| ThumbnailJob { filter, ..*job }).collect::<Vec<_>>();
+        let mut throughputs = vec![];
+        for asynchronous in [false, true] {
+            let start = Instant::now();
+            let pipeline = Pipeline { asynchronous: Some(asynchronous), ..*pipeline };
+            run(&jobs, &pipeline, list.to_vec(), &Progress::hidden(), &Stats::new())?;
+            throughputs.push(list.len() as f64 / start.elapsed().as_secs_f64());
+        }
+        println!("{:<12} {:>12.1} {:>12.1}", filter.name(), throughputs[0], throughputs[1]);
+        if INTERRUPTED.load(Ordering::Relaxed) {
+            break;
         }
     }
+    Ok(())
+}
+
+/// Creates the thumbnail `dst` of the image `src` as described by `job`, without scanning any 
+/// folder. The special name '-' stands for stdin (as `src`) or stdout (as `dst`).
+fn single_image(src: &str, dst: &str, job: &ThumbnailJob) -> Result<(), Error> {
+    let input = if src == "-" {
+        let mut input = vec![];
+        io::stdin().lock().read_to_end(&mut input)?;
+        input
+    } else {
+        fs::read(src)?
+    };
+    if dst == "-" {
+        // stdout cannot seek, which some encoders need
+        let mut output = io::Cursor::new(vec![]);
+        resize_image(&input, &mut output, job)?;
+        let mut stdout = io::stdout().lock();
+        stdout.write_all(output.get_ref())?;
+        stdout.flush()?;
+    } else {
+        let mut output = io::BufWriter::new(fs::File::create(dst)?);
+        resize_image_into(&input, &mut output, job)?;
+        output.flush()?;
+    }
+    Ok(())
 }
-impl From<FilterType> for image::imageops::FilterType {
-    fn from(value: FilterType) -> Self {
-        match value {
-            FilterType::Nearest    => image::imageops::FilterType::Nearest,
-            FilterType::Triangle   => image::imageops::FilterType::Triangle,
-            FilterType::Gaussian   => image::imageops::FilterType::Gaussian,
-            FilterType::CatmullRom => image::imageops::FilterType::CatmullRom,
-            FilterType::Lanczos3   => image::imageops::FilterType::Lanczos3,
+
+/// Reads the paths listed in `file` (one per line, blank lines are ignored). The special name '-'
+/// stands for stdin.
+fn read_list(file: &str) -> Result<Vec<PathBuf>, Error> {
+    let reader: Box<dyn BufRead> = if file == "-" {
+        Box::new(io::stdin().lock())
+    } else {
+        Box::new(BufReader::new(fs::File::open(file)?))
+    };
+    let mut paths = vec![];
+    for line in reader.split(b'\n') {
+        let line = line?;
+        let line = line.trim_ascii_end();
+        if !line.is_empty() {
+            paths.push(path_from_bytes(line)?);
         }
     }
-}
\ No newline at end of file
+    Ok(paths)
+}
+
+/// The path spelled by the raw bytes of a line of a list of files: any path can be listed on
+/// unix, where the paths need not be valid unicode
+#[cfg(unix)]
+fn path_from_bytes(bytes: &[u8]) -> Result<PathBuf, Error> {
+    use std::os::unix::ffi::OsStrExt;
+    Ok(PathBuf::from(std::ffi::OsStr::from_bytes(bytes)))
+}
+#[cfg(not(unix))]
+fn path_from_bytes(bytes: &[u8]) -> Result<PathBuf, Error> {
+    let path = std::str::from_utf8(bytes).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    Ok(PathBuf::from(path))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::ffi::OsStr;
+
+    /// The arguments which the `config` options add to the command line `cli`
+    fn arguments(config: &[(&str, ConfigValue)], cli: &[&str]) -> Vec<String> {
+        let config = config.iter().map(|(key, value)| (key.to_string(), value.clone())).collect();
+        let cli = std::iter::once("thumbnails").chain(cli.iter().copied()).chain(["src", "dst"]).map(OsString::from).collect::<Vec<_>>();
+        config_arguments(config, &cli)
+    }
+
+    /// An array of strings
+    fn array(values: &[&str]) -> ConfigValue {
+        ConfigValue::Array(values.iter().map(|value| ConfigValue::String(value.to_string())).collect())
+    }
+
+    #[test]
+    fn the_command_line_overrides_the_options_it_repeats() {
+        let config = [("quality", ConfigValue::Integer(90)), ("extension", array(&["png", "tif"]))];
+        assert_eq!(arguments(&config, &[]), ["--quality=90", "--extension=png,tif"]);
+        assert_eq!(arguments(&config, &["--quality", "50"]), ["--extension=png,tif"]);
+    }
+
+    #[test]
+    fn the_arrays_are_accepted_by_the_command_line() {
+        let config = [("extension", array(&["bmp", "tif"])), ("include", array(&["a*", "b*"])), ("exclude", array(&["*_thumb.*"]))];
+        let options = arguments(&config, &[]);
+        assert_eq!(options, ["--extension=bmp,tif", "--include=a*", "--include=b*", "--exclude=*_thumb.*"]);
+        let args = Args::from_iter_safe(["thumbnails".to_string()].into_iter().chain(options).chain(["src".into(), "dst".into()])).unwrap();
+        assert_eq!((args.include.len(), args.exclude.len()), (2, 1));
+        assert!(["bmp", "tif"].iter().all(|ext| args.extension.matches(OsStr::new(ext))) && !args.extension.matches(OsStr::new("png")));
+        // an option which cannot be repeated is rejected when it is
+        assert!(Args::from_iter_safe(["thumbnails", "--extension=bmp", "--extension=tif", "src", "dst"]).is_err());
+        assert!(REPEATED_OPTIONS.iter().all(|name| Args::from_iter_safe(["thumbnails".to_string(), format!("--{name}=a"), format!("--{name}=b"), "src".into(), "dst".into()]).is_ok()));
+    }
+
+    #[test]
+    fn the_command_line_overrides_the_options_it_conflicts_with() {
+        let config = [("synchronous", ConfigValue::Boolean(true)), ("width", ConfigValue::Integer(200)), ("quality", ConfigValue::Integer(90))];
+        assert_eq!(arguments(&config, &[]), ["--synchronous", "--width=200", "--quality=90"]);
+        assert_eq!(arguments(&config, &["--asynchronous"]), ["--width=200", "--quality=90"]);
+        assert_eq!(arguments(&config, &["--square", "100"]), ["--synchronous", "--quality=90"]);
+    }
+}
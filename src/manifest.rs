@@ -0,0 +1,299 @@
+//! The manifests of the destination folders: the content hash of the source of each thumbnail,
+//! which tells whether the thumbnail is up to date regardless of the modification times.
+
+use std::{collections::HashMap, fmt::Write as _, fs::{self, File}, io::{self, Write}, path::{Path, PathBuf}, sync::Mutex};
+
+use crate::{Error, ZipSource, error::WithPath, pipeline::write_file};
+
+/// The name of the manifest file, written in the destination folder
+pub(crate) const MANIFEST_FILE: &str = ".thumbmanifest";
+
+/// The content hash of a source image (its SHA-256 digest)
+pub(crate) type Hash = [u8; 32];
+
+/// The manifest of a destination folder. It maps each thumbnail (relative to the folder) to the
+/// content hash of the source it was generated from: a thumbnail whose source still has the same
+/// hash is up to date, whatever the modification times say (e.g. after a restore from a backup,
+/// or when the clocks of two machines disagree).
+///
+/// The manifest is read once before the scan and written back with `save` at the end of the run.
+/// The scan tells it which sources are expected to be processed (along with their hashes) and
+/// the pipeline tells it which sources have actually been processed: only the thumbnails of the
+/// latter are recorded, so that a failed (or interrupted) run never marks a stale thumbnail as up
+/// to date.
+#[derive(Debug)]
+pub struct Manifest {
+    /// The destination folder
+    root: PathBuf,
+    /// The content of the manifest
+    entries: Mutex<Entries>,
+}
+
+/// The content of a manifest
+#[derive(Debug, Default)]
+struct Entries {
+    /// The hash of the source of each recorded thumbnail (relative to the destination folder)
+    recorded: HashMap<PathBuf, Hash>,
+    /// The sources which are to be processed, along with their hash and their thumbnails
+    pending: HashMap<PathBuf, (Hash, Vec<PathBuf>)>,
+}
+
+impl Manifest {
+    /// Reads the manifest of the destination folder `dst`. A folder which has no manifest yet
+    /// (e.g. because it does not even exist) gets an empty one. The manifest holds one line per
+    /// thumbnail: the hexadecimal hash of its source, two spaces and the path of the thumbnail
+    /// relative to `dst` (as `sha256sum` would write it).
+    pub fn open(dst: &Path) -> Result<Self, Error> {
+        let path = dst.join(MANIFEST_FILE);
+        let mut recorded = HashMap::new();
+        match fs::read(&path) {
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {},
+            Err(e)   => return Err(e).with_path(&path),
+            Ok(data) => for line in data.split(|b| *b == b'\n').filter(|line| !line.is_empty()) {
+                match parse_line(line) {
+                    Some((thumbnail, hash)) => { recorded.insert(thumbnail, hash); },
+                    None => log::warn!("ignoring a malformed line of {}", path.display()),
+                }
+            },
+        }
+        log::debug!("{} thumbnail(s) recorded in {}", recorded.len(), path.display());
+        Ok(Self { root: dst.to_path_buf(), entries: Mutex::new(Entries { recorded, pending: HashMap::new() }) })
+    }
+    /// Tells whether the `thumbnail` has been generated from a source having this `hash`
+    pub(crate) fn is_current(&self, thumbnail: &Path, hash: &Hash) -> bool {
+        let entries = self.entries.lock().unwrap();
+        entries.recorded.get(self.relative(thumbnail)) == Some(hash)
+    }
+    /// Records that the `thumbnails` of the source `src`, whose content has this `hash`, are
+    /// about to be generated
+    pub(crate) fn expect(&self, src: &Path, hash: Hash, thumbnails: Vec<PathBuf>) {
+        let mut entries = self.entries.lock().unwrap();
+        entries.pending.insert(src.to_path_buf(), (hash, thumbnails));
+    }
+    /// Records that the thumbnails of the source `src` have been generated
+    pub(crate) fn processed(&self, src: &Path) {
+        let mut entries = self.entries.lock().unwrap();
+        if let Some((hash, thumbnails)) = entries.pending.remove(src) {
+            for thumbnail in thumbnails {
+                let thumbnail = self.relative(&thumbnail).to_path_buf();
+                entries.recorded.insert(thumbnail, hash);
+            }
+        }
+    }
+    /// Writes the manifest back to the destination folder (atomically), sorted by thumbnail. The
+    /// thumbnails which do not exist anymore are left out.
+    pub fn save(&self) -> Result<(), Error> {
+        if !self.root.is_dir() {
+            return Ok(());
+        }
+        let entries = self.entries.lock().unwrap();
+        let mut recorded = entries.recorded.iter()
+            .filter(|(thumbnail, _)| self.root.join(thumbnail).exists())
+            .collect::<Vec<_>>();
+        recorded.sort();
+        let path = self.root.join(MANIFEST_FILE);
+        log::debug!("recording {} thumbnail(s) in {}", recorded.len(), path.display());
+        write_file(&path, None, |out| {
+            for (thumbnail, hash) in recorded {
+                let hex = hash.iter().fold(String::with_capacity(64), |mut hex, b| { _ = write!(hex, "{b:02x}"); hex });
+                out.write_all(hex.as_bytes()).with_path(&path)?;
+                out.write_all(b"  ").with_path(&path)?;
+                out.write_all(&path_bytes(thumbnail)).with_path(&path)?;
+                out.write_all(b"\n").with_path(&path)?;
+            }
+            Ok(())
+        })?;
+        Ok(())
+    }
+    /// The path of the `thumbnail` relative to the destination folder
+    fn relative<'a>(&self, thumbnail: &'a Path) -> &'a Path {
+        thumbnail.strip_prefix(&self.root).unwrap_or(thumbnail)
+    }
+}
+
+/// Computes the content hash of the image `src`, which is an entry of the zip `source` if given
+/// and a file otherwise. The file is hashed as it is read, it is never held in memory.
+pub(crate) fn content_hash(src: &Path, source: Option<&ZipSource>) -> Result<Hash, Error> {
+    let mut hasher = Sha256::new();
+    match source {
+        Some(source) => {
+            let mut content = vec![];
+            source.read(src, &mut content)?;
+            hasher.update(&content);
+        },
+        None => {
+            let mut file = File::open(src).with_path(src)?;
+            io::copy(&mut file, &mut hasher).with_path(src)?;
+        },
+    }
+    Ok(hasher.finish())
+}
+
+/// Parses a line of a manifest: the hexadecimal hash, two spaces and the thumbnail
+fn parse_line(line: &[u8]) -> Option<(PathBuf, Hash)> {
+    let (hex, thumbnail) = (line.get(..64)?, line.get(64..)?.strip_prefix(b"  ")?);
+    let mut hash = [0; 32];
+    for (b, digits) in hash.iter_mut().zip(hex.chunks(2)) {
+        *b = u8::from_str_radix(std::str::from_utf8(digits).ok()?, 16).ok()?;
+    }
+    Some((path_from_bytes(thumbnail)?, hash))
+}
+
+/// The bytes of `path` as written in a manifest
+#[cfg(unix)]
+fn path_bytes(path: &Path) -> Vec<u8> {
+    use std::os::unix::ffi::OsStrExt;
+    path.as_os_str().as_bytes().to_vec()
+}
+#[cfg(not(unix))]
+fn path_bytes(path: &Path) -> Vec<u8> {
+    path.to_string_lossy().as_bytes().to_vec()
+}
+
+/// The path written as these `bytes` in a manifest
+#[cfg(unix)]
+fn path_from_bytes(bytes: &[u8]) -> Option<PathBuf> {
+    use std::os::unix::ffi::OsStrExt;
+    Some(PathBuf::from(std::ffi::OsStr::from_bytes(bytes)))
+}
+#[cfg(not(unix))]
+fn path_from_bytes(bytes: &[u8]) -> Option<PathBuf> {
+    std::str::from_utf8(bytes).ok().map(PathBuf::from)
+}
+
+/// The round constants of SHA-256
+const K: [u32; 64] = [
+    0x428a2f98, 0x71374491, 0xb5c0fbcf, 0xe9b5dba5, 0x3956c25b, 0x59f111f1, 0x923f82a4, 0xab1c5ed5,
+    0xd807aa98, 0x12835b01, 0x243185be, 0x550c7dc3, 0x72be5d74, 0x80deb1fe, 0x9bdc06a7, 0xc19bf174,
+    0xe49b69c1, 0xefbe4786, 0x0fc19dc6, 0x240ca1cc, 0x2de92c6f, 0x4a7484aa, 0x5cb0a9dc, 0x76f988da,
+    0x983e5152, 0xa831c66d, 0xb00327c8, 0xbf597fc7, 0xc6e00bf3, 0xd5a79147, 0x06ca6351, 0x14292967,
+    0x27b70a85, 0x2e1b2138, 0x4d2c6dfc, 0x53380d13, 0x650a7354, 0x766a0abb, 0x81c2c92e, 0x92722c85,
+    0xa2bfe8a1, 0xa81a664b, 0xc24b8b70, 0xc76c51a3, 0xd192e819, 0xd6990624, 0xf40e3585, 0x106aa070,
+    0x19a4c116, 0x1e376c08, 0x2748774c, 0x34b0bcb5, 0x391c0cb3, 0x4ed8aa4a, 0x5b9cca4f, 0x682e6ff3,
+    0x748f82ee, 0x78a5636f, 0x84c87814, 0x8cc70208, 0x90befffa, 0xa4506ceb, 0xbef9a3f7, 0xc67178f2,
+];
+
+/// A (minimal) SHA-256 hasher, fed through `update` (or as a writer)
+struct Sha256 {
+    /// The intermediate hash value
+    state: [u32; 8],
+    /// The bytes which do not fill a whole block yet
+    block: Vec<u8>,
+    /// The number of bytes hashed so far
+    length: u64,
+}
+impl Sha256 {
+    fn new() -> Self {
+        let state = [0x6a09e667, 0xbb67ae85, 0x3c6ef372, 0xa54ff53a, 0x510e527f, 0x9b05688c, 0x1f83d9ab, 0x5be0cd19];
+        Self { state, block: Vec::with_capacity(64), length: 0 }
+    }
+    /// Hashes the `data`
+    fn update(&mut self, mut data: &[u8]) {
+        self.length += data.len() as u64;
+        while !data.is_empty() {
+            let n = (64 - self.block.len()).min(data.len());
+            self.block.extend_from_slice(&data[..n]);
+            data = &data[n..];
+            if self.block.len() == 64 {
+                let block = std::mem::take(&mut self.block);
+                self.compress(&block);
+                self.block = block;
+                self.block.clear();
+            }
+        }
+    }
+    /// Pads the data hashed so far and returns the digest
+    fn finish(mut self) -> Hash {
+        let bits = self.length.wrapping_mul(8);
+        self.update(&[0x80]);
+        while self.block.len() != 56 {
+            self.update(&[0]);
+        }
+        self.update(&bits.to_be_bytes());
+        let mut digest = [0; 32];
+        for (chunk, word) in digest.chunks_mut(4).zip(self.state) {
+            chunk.copy_from_slice(&word.to_be_bytes());
+        }
+        digest
+    }
+    /// Processes one block of 64 bytes
+    fn compress(&mut self, block: &[u8]) {
+        let mut w = [0_u32; 64];
+        for (i, word) in block.chunks(4).enumerate() {
+            w[i] = u32::from_be_bytes([word[0], word[1], word[2], word[3]]);
+        }
+        for i in 16..64 {
+            let s0 = w[i - 15].rotate_right(7) ^ w[i - 15].rotate_right(18) ^ (w[i - 15] >> 3);
+            let s1 = w[i - 2].rotate_right(17) ^ w[i - 2].rotate_right(19) ^ (w[i - 2] >> 10);
+            w[i] = w[i - 16].wrapping_add(s0).wrapping_add(w[i - 7]).wrapping_add(s1);
+        }
+        let [mut a, mut b, mut c, mut d, mut e, mut f, mut g, mut h] = self.state;
+        for i in 0..64 {
+            let s1 = e.rotate_right(6) ^ e.rotate_right(11) ^ e.rotate_right(25);
+            let ch = (e & f) ^ (!e & g);
+            let t1 = h.wrapping_add(s1).wrapping_add(ch).wrapping_add(K[i]).wrapping_add(w[i]);
+            let s0 = a.rotate_right(2) ^ a.rotate_right(13) ^ a.rotate_right(22);
+            let maj = (a & b) ^ (a & c) ^ (b & c);
+            let t2 = s0.wrapping_add(maj);
+            (h, g, f, e, d, c, b, a) = (g, f, e, d.wrapping_add(t1), c, b, a, t1.wrapping_add(t2));
+        }
+        for (s, v) in self.state.iter_mut().zip([a, b, c, d, e, f, g, h]) {
+            *s = s.wrapping_add(v);
+        }
+    }
+}
+impl Write for Sha256 {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        self.update(data);
+        Ok(data.len())
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The hexadecimal digest of `data`, hashed in pieces of `piece` bytes
+    fn digest(data: &[u8], piece: usize) -> String {
+        let mut hasher = Sha256::new();
+        for chunk in data.chunks(piece.max(1)) {
+            hasher.update(chunk);
+        }
+        hasher.finish().iter().map(|b| format!("{b:02x}")).collect()
+    }
+
+    #[test]
+    fn sha256_matches_the_fips_180_2_vectors() {
+        let vectors: [(&[u8], &str); 3] = [
+            (b"",    "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"),
+            (b"abc", "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"),
+            // the 448 bits message, whose padding takes a second block
+            (b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq", "248d6a61d20638b8e5c026930c3e6039a33ce45964ff2167f6ecedd419db06c1"),
+        ];
+        for (data, expected) in vectors {
+            assert_eq!(digest(data, data.len()), expected);
+        }
+        assert_eq!(digest(&vec![b'a'; 1_000_000], 4096), "cdc76e5c9914fb9281a1c7e284d73e67f1809a48a497200e046d39ccc7112cd0");
+    }
+
+    #[test]
+    fn sha256_pads_the_messages_around_the_end_of_a_block() {
+        // 55 bytes still leave room for the length in the last block, 56 do not
+        let vectors = [
+            (55, "9f4390f8d30c2dd92ec9f095b65e2b9ae9b0a925a5258e241c9f1e910f734318"),
+            (56, "b35439a4ac6f0948b6d6f9e3c6af0f5f590ce20f1bde7090ef7970686ec6738a"),
+            (63, "7d3e74a05d7db15bce4ad9ec0658ea98e3f06eeecf16b4c6fff2da457ddc2f34"),
+            (64, "ffe054fe7ae0cb6dc65c3af9b61d5209f439851db43d0ba5997337df154668eb"),
+            (65, "635361c48bb9eab14198e76ea8ab7f1a41685d6ad62aa9146d301d4f17eb0ae0"),
+        ];
+        for (length, expected) in vectors {
+            let data = vec![b'a'; length];
+            for piece in [1, 7, length] {
+                assert_eq!(digest(&data, piece), expected, "{length} bytes hashed in pieces of {piece}");
+            }
+        }
+    }
+}
@@ -0,0 +1,179 @@
+//! Carrying the color profile and the orientation of the source images over to the thumbnails.
+//! 
+//! By default, the thumbnails are written without any metadata at all. This is intentional: it
+//! keeps the thumbnails small and it avoids leaking information such as the GPS position or the 
+//! camera serial number of the source images. When metadata are preserved, only the ICC profile
+//! and the orientation are copied, and only to the formats which can hold them (JPEG and PNG).
+
+use std::io::{Cursor, Write};
+
+use flate2::{write::ZlibEncoder, Compression};
+use image::{codecs::{jpeg::JpegDecoder, png::PngDecoder, tiff::TiffDecoder, webp::WebPDecoder}, ImageDecoder, ImageFormat};
+
+use crate::{OutputFormat, exif};
+
+/// The maximum size of the ICC data in one JPEG APP2 segment (64K minus the length and the header)
+const ICC_CHUNK: usize = 65519;
+
+/// The metadata which may be carried over from a source image to its thumbnails
+#[derive(Debug, Default, Clone)]
+pub(crate) struct Metadata {
+    /// The embedded ICC color profile
+    pub icc: Option<Vec<u8>>,
+    /// The EXIF orientation of the thumbnail
+    pub orientation: Option<u16>,
+}
+impl Metadata {
+    /// Reads the metadata of the encoded image `input`
+    pub fn read(input: &[u8]) -> Self {
+        Self { icc: icc_profile(input), orientation: exif::orientation(input) }
+    }
+    /// Embeds these metadata in the `encoded` thumbnail. Formats which cannot hold them are 
+    /// returned untouched.
+    pub fn embed(&self, encoded: Vec<u8>, format: OutputFormat) -> Vec<u8> {
+        match format {
+            OutputFormat::Jpeg => self.embed_jpeg(encoded),
+            OutputFormat::Png  => self.embed_png(encoded),
+            _                  => encoded
+        }
+    }
+    /// Inserts APP1 (EXIF) and APP2 (ICC) segments right after the JFIF header
+    fn embed_jpeg(&self, encoded: Vec<u8>) -> Vec<u8> {
+        let mut segments = vec![];
+        if let Some(orientation) = self.orientation {
+            let mut exif_data = b"Exif\0\0".to_vec();
+            exif_data.extend(exif::orientation_tiff(orientation));
+            segment(&mut segments, 0xE1, &exif_data);
+        }
+        if let Some(icc) = &self.icc {
+            let chunks = icc.chunks(ICC_CHUNK).collect::<Vec<_>>();
+            for (i, chunk) in chunks.iter().enumerate() {
+                let mut data = b"ICC_PROFILE\0".to_vec();
+                data.push(i as u8 + 1);
+                data.push(chunks.len() as u8);
+                data.extend_from_slice(chunk);
+                segment(&mut segments, 0xE2, &data);
+            }
+        }
+        // the SOI marker is immediately followed by the APP0 (JFIF) segment
+        let at = if encoded.get(2..4) == Some(&[0xFF, 0xE0]) {
+            4 + u16::from_be_bytes([encoded[4], encoded[5]]) as usize
+        } else {
+            2
+        };
+        let mut out = Vec::with_capacity(encoded.len() + segments.len());
+        out.extend_from_slice(&encoded[..at]);
+        out.extend(segments);
+        out.extend_from_slice(&encoded[at..]);
+        out
+    }
+    /// Inserts iCCP and eXIf chunks right after the IHDR chunk
+    fn embed_png(&self, encoded: Vec<u8>) -> Vec<u8> {
+        let mut chunks = vec![];
+        if let Some(icc) = &self.icc {
+            let mut data = b"icc\0\0".to_vec();
+            let mut z = ZlibEncoder::new(vec![], Compression::default());
+            if z.write_all(icc).is_ok() {
+                if let Ok(compressed) = z.finish() {
+                    data.extend(compressed);
+                    chunk(&mut chunks, b"iCCP", &data);
+                }
+            }
+        }
+        if let Some(orientation) = self.orientation {
+            chunk(&mut chunks, b"eXIf", &exif::orientation_tiff(orientation));
+        }
+        after_ihdr(encoded, &chunks)
+    }
+}
+
+/// Inserts a pHYs chunk right after the IHDR chunk of the `encoded` PNG image, which records its
+/// resolution (in dots per inch, stored as pixels per meter)
+pub(crate) fn with_png_density(encoded: Vec<u8>, dpi: u16) -> Vec<u8> {
+    let ppm = (dpi as f64 / 0.0254).round() as u32;
+    let mut data = vec![];
+    data.extend(ppm.to_be_bytes());
+    data.extend(ppm.to_be_bytes());
+    data.push(1);
+    let mut chunks = vec![];
+    chunk(&mut chunks, b"pHYs", &data);
+    after_ihdr(encoded, &chunks)
+}
+
+/// Inserts the given `chunks` right after the IHDR chunk of the `encoded` PNG image
+fn after_ihdr(encoded: Vec<u8>, chunks: &[u8]) -> Vec<u8> {
+    // 8 bytes of signature followed by IHDR (length, type, 13 bytes of data, crc)
+    let at = 8 + 4 + 4 + 13 + 4;
+    if encoded.len() < at {
+        return encoded;
+    }
+    let mut out = Vec::with_capacity(encoded.len() + chunks.len());
+    out.extend_from_slice(&encoded[..at]);
+    out.extend_from_slice(chunks);
+    out.extend_from_slice(&encoded[at..]);
+    out
+}
+
+/// Tells whether the encoded jpeg or png image `input` carries metadata which would leak into a
+/// verbatim copy of it: EXIF, XMP or an ICC profile (i.e. any application segment but the JFIF
+/// header or any comment of a jpeg, any textual, EXIF or ICC chunk of a png). An image which
+/// cannot be parsed is deemed to carry some.
+pub(crate) fn has_metadata(input: &[u8]) -> bool {
+    match image::guess_format(input) {
+        Ok(ImageFormat::Jpeg) => {
+            let mut at = 2;
+            // the metadata segments all come before the first scan
+            while let Some(&[0xFF, marker, hi, lo]) = input.get(at..at + 4) {
+                match marker {
+                    0xDA | 0xD9          => return false,
+                    0xE1..=0xEF | 0xFE   => return true,
+                    _                    => at += 2 + u16::from_be_bytes([hi, lo]) as usize,
+                }
+            }
+            true
+        },
+        Ok(ImageFormat::Png) => {
+            let mut at = 8;
+            while let Some(header) = input.get(at..at + 8) {
+                let length = u32::from_be_bytes([header[0], header[1], header[2], header[3]]) as usize;
+                match &header[4..] {
+                    b"IEND"                                     => return false,
+                    b"eXIf" | b"iCCP" | b"iTXt" | b"tEXt" | b"zTXt" => return true,
+                    _                                           => at += 12 + length,
+                }
+            }
+            true
+        },
+        _ => true,
+    }
+}
+
+/// Reads the ICC profile embedded in the encoded image `input` (if any)
+fn icc_profile(input: &[u8]) -> Option<Vec<u8>> {
+    let reader = Cursor::new(input);
+    match image::guess_format(input).ok()? {
+        ImageFormat::Jpeg => JpegDecoder::new(reader).ok()?.icc_profile(),
+        ImageFormat::Png  => PngDecoder::new(reader).ok()?.icc_profile(),
+        ImageFormat::Tiff => TiffDecoder::new(reader).ok()?.icc_profile(),
+        ImageFormat::WebP => WebPDecoder::new(reader).ok()?.icc_profile(),
+        _                 => None
+    }
+}
+
+/// Appends a JPEG segment with the given marker and payload to `out`
+fn segment(out: &mut Vec<u8>, marker: u8, data: &[u8]) {
+    out.extend([0xFF, marker]);
+    out.extend((data.len() as u16 + 2).to_be_bytes());
+    out.extend_from_slice(data);
+}
+
+/// Appends a PNG chunk with the given type and payload to `out`
+fn chunk(out: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    let mut crc = crc32fast::Hasher::new();
+    crc.update(kind);
+    crc.update(data);
+    out.extend((data.len() as u32).to_be_bytes());
+    out.extend_from_slice(kind);
+    out.extend_from_slice(data);
+    out.extend(crc.finalize().to_be_bytes());
+}
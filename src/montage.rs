@@ -0,0 +1,83 @@
+use std::{fs, path::{Path, PathBuf}};
+
+use image::{DynamicImage, RgbaImage, Rgba};
+use rayon::iter::{IntoParallelRefIterator, ParallelIterator};
+
+use crate::{Error, Failure, error::WithPath, Pipeline, Progress, Stage, Stats, ThumbnailJob, stats::timed, pipeline::{check_jobs, in_range, interrupted, load_sources, read_source, write_file}, resize::write_image};
+
+/// Creates a single contact sheet (montage) showing the thumbnails of all the `sources` images,
+/// tiled in a grid of `columns` columns and written to `output`. Each thumbnail is created as 
+/// described by the `job` and centered in a cell of `job.width` x `job.height` pixels. The cells
+/// are filled in the order of `sources` (row by row), the images which cannot be processed are
+/// left out of the grid (as are those whose size is out of the range of the `pipeline`) and the
+/// empty space is painted with the background color of the job.
+/// 
+/// The thumbnails are created in parallel on `pipeline.threads` threads. Beware that the whole
+/// sheet is held in memory until it is encoded: it takes 4 bytes per pixel, which amounts to 
+/// about 1.5 GiB for 10.000 thumbnails of 200x200 pixels.
+pub fn montage(sources: &[PathBuf], job: &ThumbnailJob, columns: u32, output: &Path, pipeline: &Pipeline, progress: &Progress, stats: &Stats) -> Result<Vec<Failure>, Error> {
+    check_jobs(std::slice::from_ref(job))?;
+    let threads = if pipeline.threads == 0 { num_cpus::get() } else { pipeline.threads };
+    let dst = [output.to_path_buf()];
+    let thumbnail = |src: &PathBuf| -> Option<Result<Option<(RgbaImage, u64)>, Error>> {
+        if interrupted(pipeline.interrupt) {
+            return None;
+        }
+        log::debug!("processing {}", src.display());
+        let mut input = vec![];
+        let outcome = read_source(pipeline, src, &mut input).and_then(|_| {
+            if !in_range(src, &input, pipeline.sizes) {
+                return Ok(None);
+            }
+            let sources = load_sources(src, &input, std::slice::from_ref(job), pipeline)?;
+            let im = sources.frames(job).first().map(|im| sources.thumbnail(job, im)).unwrap_or_default();
+            Ok(Some((im, input.len() as u64)))
+        });
+        progress.inc();
+        Some(outcome)
+    };
+    let thumbnails = rayon::ThreadPoolBuilder::new()
+        .num_threads(threads)
+        .build()?
+        .install(|| sources.par_iter().map(thumbnail).collect::<Vec<_>>());
+
+    let mut cells = vec![];
+    let mut failures = vec![];
+    for (src, outcome) in sources.iter().zip(thumbnails) {
+        match outcome {
+            None                                => {},
+            Some(Ok(None))                      => stats.skipped(src, &dst),
+            Some(Ok(Some((im, read))))          => {
+                stats.processed(src, &dst, read, 0);
+                cells.push(im);
+            },
+            Some(Err(error)) if pipeline.fail_fast => return Err(error),
+            Some(Err(error))                    => {
+                stats.failed(src, &dst, &error);
+                failures.push(Failure { src: src.clone(), error });
+            },
+        }
+    }
+    progress.finish();
+
+    let columns = columns.max(1);
+    let rows = (cells.len() as u32).div_ceil(columns).max(1);
+    let [r, g, b] = job.background.0;
+    let mut sheet = RgbaImage::from_pixel(columns * job.width, rows * job.height, Rgba([r, g, b, 255]));
+    for (i, im) in cells.iter().enumerate() {
+        let (column, row) = (i as u32 % columns, i as u32 / columns);
+        let x = column * job.width  + job.width.saturating_sub(im.width())   / 2;
+        let y = row    * job.height + job.height.saturating_sub(im.height()) / 2;
+        image::imageops::overlay(&mut sheet, im, x as i64, y as i64);
+    }
+    drop(cells);
+
+    if let Some(parent) = output.parent() {
+        fs::create_dir_all(parent).with_path(parent)?;
+    }
+    log::trace!("writing {}", output.display());
+    let sheet = DynamicImage::ImageRgba8(sheet);
+    let sheet = if job.format.keeps_alpha() { sheet } else { DynamicImage::ImageRgb8(sheet.to_rgb8()) };
+    timed(pipeline.timings, Stage::Encode, || write_file(output, None, |out| write_image(job, &sheet, out)))?;
+    Ok(failures)
+}
@@ -0,0 +1,200 @@
+use std::{ffi::{OsStr, OsString}, str::FromStr, path::{Path, PathBuf}};
+
+use crate::{Error, Frame, ThumbnailJob};
+
+/// The tokens which can be used in a name template
+const TOKENS: [&str; 7] = ["stem", "name", "ext", "width", "height", "parent", "n"];
+
+/// The tokens which can be used in a name template, as they are written in it (e.g. '{stem}, {ext}')
+pub(crate) fn tokens() -> String {
+    TOKENS.map(|token| format!("{{{token}}}")).join(", ")
+}
+
+/// A template used to build the file name of each thumbnail. The template is a plain string in
+/// which the following tokens get replaced:
+/// 
+/// * `{stem}`   the name of the source file without its extension,
+/// * `{name}`   the full name of the source file, extension included (e.g. 'photo.tif'),
+/// * `{ext}`    the extension of the output format (e.g. 'jpg'),
+/// * `{width}`  the width of the generated thumbnail box,
+/// * `{height}` the height of the generated thumbnail box,
+/// * `{parent}` the name of the folder containing the source file,
+/// * `{n}`      a counter incremented for each source image (starting at 1, in traversal order).
+/// 
+/// The images are numbered by the scan, before they are dispatched to the threads resizing them:
+/// their numbers only depend on the traversal order, which is that of the file names when the
+/// scan is `sorted` (see `Scan::sorted`) and that of the file system otherwise.
+/// 
+/// For instance, the default naming is equivalent to '{stem}.{ext}'.
+#[derive(Debug, Clone)]
+pub struct NameTemplate(String);
+impl NameTemplate {
+    /// The literal extension ending this template (e.g. 'png' for '{stem}.png'), if any
+    pub fn extension(&self) -> Option<&str> {
+        let (_, ext) = self.0.rsplit_once('.')?;
+        (!ext.is_empty() && !ext.contains(['{', '}', '/', '\\'])).then_some(ext)
+    }
+    /// Renders the name of the thumbnail generated by `job` for the `n`-th source image `src`.
+    /// The name of the source file and of its folder are used as they are, even when they are
+    /// not valid unicode.
+    pub fn render(&self, src: &Path, job: &ThumbnailJob, n: usize) -> OsString {
+        let stem   = src.file_stem().unwrap_or(OsStr::new("unk"));
+        let full   = src.file_name().unwrap_or(stem);
+        let parent = src.parent().and_then(|p| p.file_name()).unwrap_or_default();
+        let mut name = OsString::new();
+        let mut rest = self.0.as_str();
+        while let Some(start) = rest.find('{') {
+            let end = start + rest[start..].find('}').expect("the tokens of a template are closed");
+            name.push(&rest[..start]);
+            match &rest[start + 1..end] {
+                "stem"   => name.push(stem),
+                "name"   => name.push(full),
+                "ext"    => name.push(job.format.extension()),
+                "width"  => name.push(job.width.to_string()),
+                "height" => name.push(job.height.to_string()),
+                "parent" => name.push(parent),
+                _        => name.push(n.to_string()),
+            }
+            rest = &rest[end + 1..];
+        }
+        name.push(rest);
+        name
+    }
+}
+impl FromStr for NameTemplate {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut rest = s;
+        while let Some(start) = rest.find('{') {
+            let end = rest[start..].find('}').ok_or_else(|| Error::InvalidNameTemplate(s.to_string()))?;
+            if !TOKENS.contains(&&rest[start + 1..start + end]) {
+                return Err(Error::InvalidNameTemplate(s.to_string()));
+            }
+            rest = &rest[start + end + 1..];
+        }
+        if s.is_empty() {
+            return Err(Error::InvalidNameTemplate(s.to_string()));
+        }
+        Ok(Self(s.to_string()))
+    }
+}
+
+/// How the letters of the thumbnail names are cased
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Case {
+    /// The names are left as they are rendered
+    Keep,
+    /// The names are turned to lowercase
+    Lower,
+    /// The names are turned to uppercase
+    Upper,
+}
+
+/// The characters which cannot appear in a file name on some file system (chiefly Windows)
+const ILLEGAL: [char; 9] = ['<', '>', ':', '"', '/', '\\', '|', '?', '*'];
+/// The names which Windows reserves for its devices (whatever their extension)
+const RESERVED: [&str; 22] = ["CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8", "COM9", "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9"];
+
+/// Normalizes the `name` of a thumbnail (which may span several folders, as in 'a/b.jpg') so
+/// that it can be written on any file system. When `sanitize` is set, each character which is
+/// illegal in a file name (`< > : " \ | ? *` and the control characters) is replaced by an 
+/// underscore (as are the bytes which are not valid unicode), the dots and spaces ending each part of the name are stripped and the names
+/// reserved by Windows (e.g. 'con.jpg') are prefixed with an underscore. The name is then cased
+/// as per `case`.
+/// The names which are not valid unicode only get their ascii letters cased.
+pub(crate) fn normalize(name: &OsStr, sanitize: bool, case: Case) -> OsString {
+    let name = if sanitize {
+        OsString::from(name.to_string_lossy().split('/').map(sanitize_part).collect::<Vec<_>>().join("/"))
+    } else {
+        name.to_os_string()
+    };
+    match (case, name.to_str()) {
+        (Case::Keep,  _)          => name,
+        (Case::Lower, Some(name)) => name.to_lowercase().into(),
+        (Case::Upper, Some(name)) => name.to_uppercase().into(),
+        (Case::Lower, None)       => name.to_ascii_lowercase(),
+        (Case::Upper, None)       => name.to_ascii_uppercase(),
+    }
+}
+
+/// Sanitizes one part of a thumbnail name (a folder or file name, see `normalize`)
+fn sanitize_part(part: &str) -> String {
+    let part = part.chars()
+        .map(|c| if c.is_control() || ILLEGAL.contains(&c) || c == char::REPLACEMENT_CHARACTER { '_' } else { c })
+        .collect::<String>();
+    let part = part.trim_end_matches(['.', ' ']);
+    let device = part.split('.').next().unwrap_or_default();
+    if RESERVED.iter().any(|r| r.eq_ignore_ascii_case(device)) {
+        format!("_{part}")
+    } else {
+        part.to_string()
+    }
+}
+
+/// Returns the default name of the thumbnail of the image having the given stem. When several 
+/// jobs are performed on each image, the size of the thumbnail is appended to its name 
+/// (e.g. 'name_120x150.jpg'). The `suffix` always comes last, right before the extension
+/// (e.g. 'name_120x150_thumb.jpg'), while the `prefix` comes first (e.g. 'thumb_name.jpg').
+pub(crate) fn default_name(stem: &OsStr, prefix: &str, suffix: &str, job: &ThumbnailJob, jobs: &[ThumbnailJob]) -> OsString {
+    let mut name = OsString::from(prefix);
+    name.push(stem);
+    if jobs.len() > 1 {
+        name.push(format!("_{}x{}", job.width, job.height));
+    }
+    name.push(format!("{suffix}.{}", job.format.extension()));
+    name
+}
+
+/// Inserts `suffix` at the end of the file name of `path`, right before its extension
+/// (e.g. 'a/photo.jpg' becomes 'a/photo_1.jpg' for the suffix '_1')
+pub(crate) fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = path.file_stem().unwrap_or(OsStr::new("unk")).to_os_string();
+    name.push(suffix);
+    if let Some(ext) = path.extension() {
+        name.push(".");
+        name.push(ext);
+    }
+    path.with_file_name(name)
+}
+
+/// Returns the path of the thumbnail of the `index`-th frame of an image when `dst` is the path
+/// of its thumbnail. The index is only appended when the job produces one thumbnail per frame 
+/// (e.g. 'photo.jpg' becomes 'photo_0.jpg', 'photo_1.jpg', ...), even for single-frame images.
+pub(crate) fn frame_name(dst: &Path, job: &ThumbnailJob, index: usize) -> PathBuf {
+    match job.frame {
+        Frame::All      => with_suffix(dst, &format!("_{index}")),
+        Frame::Index(_) => dst.to_path_buf(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::job;
+
+    #[test]
+    fn an_invalid_template_error_lists_every_token() {
+        let message = "{size}.jpg".parse::<NameTemplate>().unwrap_err().to_string();
+        for token in TOKENS {
+            assert!(message.contains(&format!("{{{token}}}")), "{token} is missing from: {message}");
+        }
+        assert!("{name}_{n}.{ext}".parse::<NameTemplate>().is_ok());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn names_which_are_not_unicode_are_kept_as_they_are() {
+        use std::os::unix::ffi::{OsStrExt, OsStringExt};
+
+        // 'café' and 'année' spelled in latin-1
+        let src = Path::new(OsStr::from_bytes(b"/photos/ann\xe9e/caf\xe9.tif"));
+        let template = "{parent}_{stem}_{n}.{ext}".parse::<NameTemplate>().unwrap();
+        let name = template.render(src, &job(10, 10), 3);
+        assert_eq!(name.as_bytes(), b"ann\xe9e_caf\xe9_3.jpg");
+
+        assert_eq!(normalize(&name, false, Case::Keep).as_bytes(), b"ann\xe9e_caf\xe9_3.jpg");
+        assert_eq!(normalize(&name, false, Case::Upper).into_vec(), b"ANN\xe9E_CAF\xe9_3.JPG");
+        assert_eq!(normalize(&name, true, Case::Keep), "ann_e_caf__3.jpg");
+    }
+}
@@ -0,0 +1,627 @@
+use std::{str::FromStr, ffi::OsStr, path::Path};
+
+use image::ImageOutputFormat;
+
+use crate::Error;
+
+/// Parses the jpeg quality and makes sure it lies in the range 1..=100
+pub fn parse_quality(s: &str) -> Result<u8, Error> {
+    match s.parse::<u8>() {
+        Ok(q) if (1..=100).contains(&q) => Ok(q),
+        _ => Err(Error::InvalidQuality(s.to_string()))
+    }
+}
+
+/// Parses a rotation angle (in degrees, clockwise) and makes sure it is a multiple of 90
+pub fn parse_rotation(s: &str) -> Result<u16, Error> {
+    match s.parse::<u16>() {
+        Ok(angle) if [0, 90, 180, 270].contains(&angle) => Ok(angle),
+        _ => Err(Error::InvalidRotation(s.to_string()))
+    }
+}
+
+/// Parses a brightness adjustment and makes sure it lies in the range -255..=255
+pub fn parse_brightness(s: &str) -> Result<i32, Error> {
+    match s.parse::<i32>() {
+        Ok(b) if (-255..=255).contains(&b) => Ok(b),
+        _ => Err(Error::InvalidBrightness(s.to_string()))
+    }
+}
+
+/// Parses a contrast adjustment and makes sure it lies in the range -100..=100
+pub fn parse_contrast(s: &str) -> Result<f32, Error> {
+    match s.parse::<f32>() {
+        Ok(c) if (-100.0..=100.0).contains(&c) => Ok(c),
+        _ => Err(Error::InvalidContrast(s.to_string()))
+    }
+}
+
+/// Parses a sharpening amount and makes sure it lies in the range 0..=5
+pub fn parse_sharpen(s: &str) -> Result<f32, Error> {
+    match s.parse::<f32>() {
+        Ok(a) if (0.0..=5.0).contains(&a) => Ok(a),
+        _ => Err(Error::InvalidSharpen(s.to_string()))
+    }
+}
+
+/// Parses the opacity of a watermark and makes sure it lies in the range 0..=1
+pub fn parse_opacity(s: &str) -> Result<f32, Error> {
+    match s.parse::<f32>() {
+        Ok(o) if (0.0..=1.0).contains(&o) => Ok(o),
+        _ => Err(Error::InvalidOpacity(s.to_string()))
+    }
+}
+
+/// Parses the size of a watermark (a fraction of the thumbnail) and makes sure it lies in the
+/// range 0..=1 (0 excluded)
+pub fn parse_watermark_size(s: &str) -> Result<f32, Error> {
+    match s.parse::<f32>() {
+        Ok(f) if f > 0.0 && f <= 1.0 => Ok(f),
+        _ => Err(Error::InvalidWatermarkSize(s.to_string()))
+    }
+}
+
+/// Parses a scale factor and makes sure it is a positive number no larger than 10
+pub fn parse_scale(s: &str) -> Result<f32, Error> {
+    match s.parse::<f32>() {
+        Ok(f) if f > 0.0 && f <= 10.0 => Ok(f),
+        _ => Err(Error::InvalidScale(s.to_string()))
+    }
+}
+
+/// A list of thumbnail sizes, written as comma separated `WIDTHxHEIGHT` pairs (e.g. '120x150,240x300')
+#[derive(Debug, Clone)]
+pub struct Sizes(pub Vec<(u32, u32)>);
+impl FromStr for Sizes {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        s.split(',')
+            .map(|size| {
+                let (w, h) = size.trim().split_once(['x', 'X']).ok_or_else(|| Error::CannotParseSizes(s.to_string()))?;
+                match (w.parse::<u32>(), h.parse::<u32>()) {
+                    (Ok(w), Ok(h)) if w > 0 && h > 0 => Ok((w, h)),
+                    _ => Err(Error::CannotParseSizes(s.to_string()))
+                }
+            })
+            .collect::<Result<Vec<_>, _>>()
+            .map(Sizes)
+    }
+}
+
+/// The range of dimensions (in pixels) of the source images to process: the images lying outside
+/// of it are skipped. Each bound is optional.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SizeRange {
+    /// The smallest width of the images to process
+    pub min_width: Option<u32>,
+    /// The smallest height of the images to process
+    pub min_height: Option<u32>,
+    /// The largest width of the images to process
+    pub max_width: Option<u32>,
+    /// The largest height of the images to process
+    pub max_height: Option<u32>,
+}
+impl SizeRange {
+    /// Tells whether there is no bound at all
+    pub fn is_unbounded(&self) -> bool {
+        *self == Self::default()
+    }
+    /// Tells whether an image of `width` x `height` pixels lies within the range
+    pub fn contains(&self, width: u32, height: u32) -> bool {
+        self.min_width.map(|w| width >= w).unwrap_or(true)
+            && self.min_height.map(|h| height >= h).unwrap_or(true)
+            && self.max_width.map(|w| width <= w).unwrap_or(true)
+            && self.max_height.map(|h| height <= h).unwrap_or(true)
+    }
+}
+
+/// The number of components of a blurhash along the x and y axes, written as `XxY` (e.g. '4x3').
+/// Both lie in the range 1..=9: the more components, the more details in the blurhash.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BlurhashComponents {
+    /// Number of components along the x axis
+    pub x: u32,
+    /// Number of components along the y axis
+    pub y: u32,
+}
+impl Default for BlurhashComponents {
+    fn default() -> Self {
+        Self { x: 4, y: 3 }
+    }
+}
+impl FromStr for BlurhashComponents {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (x, y) = s.trim().split_once(['x', 'X']).ok_or_else(|| Error::InvalidBlurhashComponents(s.to_string()))?;
+        match (x.parse::<u32>(), y.parse::<u32>()) {
+            (Ok(x), Ok(y)) if (1..=9).contains(&x) && (1..=9).contains(&y) => Ok(Self { x, y }),
+            _ => Err(Error::InvalidBlurhashComponents(s.to_string()))
+        }
+    }
+}
+
+/// An opaque color, written as `RRGGBB` hexadecimal digits (e.g. 'ffffff' for white). A leading
+/// '#' is accepted as well.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Color(pub [u8; 3]);
+impl Default for Color {
+    fn default() -> Self {
+        Self([255, 255, 255])
+    }
+}
+impl FromStr for Color {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let hex = s.trim_start_matches('#');
+        if hex.len() != 6 || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Err(Error::CannotParseColor(s.to_string()));
+        }
+        let channel = |i: usize| u8::from_str_radix(&hex[i..i + 2], 16).unwrap();
+        Ok(Self([channel(0), channel(2), channel(4)]))
+    }
+}
+
+/// Which frame(s) of an animated (or multi-page) image are turned into thumbnails
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Frame {
+    /// Only the frame having this (0-based) index
+    Index(usize),
+    /// Every single frame (one thumbnail per frame)
+    All,
+}
+impl Default for Frame {
+    fn default() -> Self {
+        Self::Index(0)
+    }
+}
+
+/// When an existing thumbnail is to be regenerated
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Overwrite {
+    /// Always regenerate the thumbnails
+    Always,
+    /// Never regenerate an existing thumbnail
+    Never,
+    /// Only regenerate a thumbnail when it is older than its source
+    IfNewer,
+    /// Only regenerate a thumbnail when the content of its source has changed since it was
+    /// generated, as per the `Manifest` of the destination folder
+    IfChanged,
+}
+impl FromStr for Overwrite {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "always"     => Ok(Self::Always),
+            "never"      => Ok(Self::Never),
+            "if-newer"   => Ok(Self::IfNewer),
+            "if-changed" => Ok(Self::IfChanged),
+            _            => Err(Error::CannotParseOverwritePolicy)
+        }
+    }
+}
+
+/// How an incremental run tells which thumbnails are up to date
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Incremental {
+    /// By the modification times of the thumbnails and their sources
+    Mtime,
+    /// By the content hashes of the sources
+    Hash,
+}
+impl Incremental {
+    /// The overwrite policy of the incremental runs
+    pub fn overwrite(self) -> Overwrite {
+        match self {
+            Self::Mtime => Overwrite::IfNewer,
+            Self::Hash  => Overwrite::IfChanged,
+        }
+    }
+}
+impl FromStr for Incremental {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "mtime" => Ok(Self::Mtime),
+            "hash"  => Ok(Self::Hash),
+            _       => Err(Error::CannotParseIncremental)
+        }
+    }
+}
+
+/// What to do when two source images would produce a thumbnail with the same destination
+#[derive(Debug, Clone, Copy)]
+pub enum OnCollision {
+    /// Abort with an error
+    Error,
+    /// Skip the image which comes second (with a warning)
+    Skip,
+    /// Rename the second thumbnail by appending '_1', '_2', ... to its name
+    Rename
+}
+impl FromStr for OnCollision {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "error"  => Ok(Self::Error),
+            "skip"   => Ok(Self::Skip),
+            "rename" => Ok(Self::Rename),
+            _        => Err(Error::CannotParseCollisionPolicy)
+        }
+    }
+}
+
+/// Utility cruft to make the cli more user friendly
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FilterType {
+    Nearest, 
+    /// Averages the area of the source image covered by each pixel of the thumbnail (see 
+    /// `ThumbnailJob::resize`)
+    Box,
+    Triangle, 
+    Gaussian, 
+    CatmullRom,
+    Lanczos3
+}
+/// Parses the name (or one of the aliases) of a filter, as listed by `--list-filters`. Unlike the
+/// other values of the command line, the names are compared case-insensitively since they are
+/// often spelled after the algorithms: 'nearest', 'Nearest' and 'NEAREST' are the same filter.
+impl FromStr for FilterType {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.to_ascii_lowercase();
+        let s = s.as_str();
+        Self::ALL.into_iter()
+            .find(|filter| filter.name() == s || filter.aliases().contains(&s))
+            .ok_or(Error::CannotParseFilterType)
+    }
+}
+impl FilterType {
+    /// All the available filters, from the fastest to the slowest
+    pub const ALL: [FilterType; 6] = [Self::Nearest, Self::Box, Self::Triangle, Self::CatmullRom, Self::Gaussian, Self::Lanczos3];
+
+    /// The name of the filter on the command line
+    pub fn name(self) -> &'static str {
+        match self {
+            FilterType::Nearest    => "nearest",
+            FilterType::Box        => "box",
+            FilterType::Triangle   => "triangle",
+            FilterType::Gaussian   => "gaussian",
+            FilterType::CatmullRom => "catmull-rom",
+            FilterType::Lanczos3   => "lanczos3",
+        }
+    }
+    /// The other names under which the filter is accepted on the command line
+    pub fn aliases(self) -> &'static [&'static str] {
+        match self {
+            FilterType::Nearest    => &["nn"],
+            FilterType::Box        => &["area"],
+            FilterType::CatmullRom => &["cubic"],
+            FilterType::Lanczos3   => &["lanczos"],
+            _                      => &[],
+        }
+    }
+}
+/// The filter of the image library closest to each filter. The box filter has no counterpart; it
+/// is replaced by 'triangle' (e.g. when upscaling).
+impl From<FilterType> for image::imageops::FilterType {
+    fn from(value: FilterType) -> Self {
+        match value {
+            FilterType::Nearest    => image::imageops::FilterType::Nearest,
+            FilterType::Box        => image::imageops::FilterType::Triangle,
+            FilterType::Triangle   => image::imageops::FilterType::Triangle,
+            FilterType::Gaussian   => image::imageops::FilterType::Gaussian,
+            FilterType::CatmullRom => image::imageops::FilterType::CatmullRom,
+            FilterType::Lanczos3   => image::imageops::FilterType::Lanczos3,
+        }
+    }
+}
+/// The set of file extensions which are selected for processing
+#[derive(Debug, Clone)]
+pub enum Extensions {
+    /// Any extension that can be decoded by the image library
+    All,
+    /// Only the listed extensions (compared case-insensitively)
+    Only(Vec<String>)
+}
+impl Extensions {
+    /// Returns true iff a file having the extension `ext` must be processed
+    pub fn matches(&self, ext: &OsStr) -> bool {
+        match self {
+            Extensions::All         => image::ImageFormat::from_extension(ext).is_some(),
+            Extensions::Only(exts)  => exts.iter().any(|e| ext.eq_ignore_ascii_case(e)),
+        }
+    }
+}
+impl FromStr for Extensions {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let exts = s.split(',')
+            .map(|e| e.trim().trim_start_matches('.'))
+            .filter(|e| !e.is_empty())
+            .map(|e| e.to_string())
+            .collect::<Vec<_>>();
+
+        if exts.is_empty() || exts.iter().any(|e| e.eq_ignore_ascii_case("all")) {
+            Ok(Self::All)
+        } else {
+            Ok(Self::Only(exts))
+        }
+    }
+}
+
+/// The various ways to fit an image into the thumbnail box
+#[derive(Debug, Clone, Copy)]
+pub enum ResizeMode {
+    Exact,
+    Fit,
+    Fill
+}
+impl FromStr for ResizeMode {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "exact" => Ok(Self::Exact),
+            "fit"   => Ok(Self::Fit),
+            "fill"  => Ok(Self::Fill),
+            _       => Err(Error::CannotParseResizeMode)
+        }
+    }
+}
+
+/// How the chroma (the color) of the jpeg thumbnails is subsampled with respect to their luminance
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Chroma {
+    /// 4:4:4, the chroma is kept at full resolution
+    #[default]
+    Full,
+    /// 4:2:2, the chroma is halved horizontally
+    Half,
+    /// 4:2:0, the chroma is halved horizontally and vertically
+    Quarter,
+}
+impl Chroma {
+    /// The horizontal and vertical sampling factors of the luminance (those of the chroma being 1)
+    pub(crate) fn sampling(self) -> (usize, usize) {
+        match self {
+            Self::Full    => (1, 1),
+            Self::Half    => (2, 1),
+            Self::Quarter => (2, 2),
+        }
+    }
+}
+impl FromStr for Chroma {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "444" => Ok(Self::Full),
+            "422" => Ok(Self::Half),
+            "420" => Ok(Self::Quarter),
+            _     => Err(Error::CannotParseChroma)
+        }
+    }
+}
+
+/// The shape of the thumbnails: the pixels lying out of it are made transparent (or painted with
+/// the background color when the output format has no alpha channel)
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum Shape {
+    /// The whole thumbnail is kept
+    #[default]
+    Rectangle,
+    /// The largest circle centered in the thumbnail
+    Circle,
+    /// The thumbnail with its corners rounded along a quarter circle of this radius (in pixels)
+    Rounded(f32),
+}
+impl FromStr for Shape {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "rectangle" => Ok(Self::Rectangle),
+            "circle"    => Ok(Self::Circle),
+            _ => match s.strip_prefix("rounded:").map(str::parse::<f32>) {
+                Some(Ok(radius)) if radius.is_finite() && radius > 0.0 => Ok(Self::Rounded(radius)),
+                _ => Err(Error::CannotParseShape(s.to_string()))
+            }
+        }
+    }
+}
+
+/// Where a watermark is placed on the thumbnails
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WatermarkPosition {
+    TopLeft,
+    TopRight,
+    BottomLeft,
+    BottomRight,
+    Center,
+}
+impl FromStr for WatermarkPosition {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "tl"     => Ok(Self::TopLeft),
+            "tr"     => Ok(Self::TopRight),
+            "bl"     => Ok(Self::BottomLeft),
+            "br"     => Ok(Self::BottomRight),
+            "center" => Ok(Self::Center),
+            _        => Err(Error::CannotParseWatermarkPosition)
+        }
+    }
+}
+
+/// The image formats in which the thumbnails can be written
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputFormat {
+    Jpeg,
+    Png,
+    WebP,
+    Avif,
+    Bmp,
+    Gif
+}
+impl OutputFormat {
+    /// All the formats, whether their encoder is part of this build or not
+    pub const ALL: [OutputFormat; 6] = [Self::Jpeg, Self::Png, Self::WebP, Self::Avif, Self::Bmp, Self::Gif];
+
+    /// The name of the format on the command line
+    pub fn name(self) -> &'static str {
+        match self {
+            OutputFormat::Jpeg => "jpeg",
+            OutputFormat::Png  => "png",
+            OutputFormat::WebP => "webp",
+            OutputFormat::Avif => "avif",
+            OutputFormat::Bmp  => "bmp",
+            OutputFormat::Gif  => "gif",
+        }
+    }
+    /// The other names under which the format is accepted on the command line
+    pub fn aliases(self) -> &'static [&'static str] {
+        match self {
+            OutputFormat::Jpeg => &["jpg"],
+            _                  => &[],
+        }
+    }
+    /// The format denoted by the file extension `ext` (compared case-insensitively), if any
+    pub fn from_extension(ext: &str) -> Option<Self> {
+        ext.to_ascii_lowercase().parse().ok()
+    }
+    /// The format denoted by the extension of `path`, if any
+    pub fn from_path(path: &Path) -> Option<Self> {
+        path.extension().and_then(|x| x.to_str()).and_then(Self::from_extension)
+    }
+    /// The extension given to the generated thumbnails
+    pub fn extension(self) -> &'static str {
+        match self {
+            OutputFormat::Jpeg => "jpg",
+            OutputFormat::Png  => "png",
+            OutputFormat::WebP => "webp",
+            OutputFormat::Avif => "avif",
+            OutputFormat::Bmp  => "bmp",
+            OutputFormat::Gif  => "gif",
+        }
+    }
+    /// Does this format keep the transparency of the thumbnails ? The thumbnails written in a
+    /// format which does not are flattened over a background color beforehand.
+    pub fn keeps_alpha(self) -> bool {
+        !matches!(self, OutputFormat::Jpeg | OutputFormat::Bmp)
+    }
+    /// Can this format be encoded without any loss ? Webp has both a lossy and a lossless 
+    /// encoder, png and bmp are always lossless while jpeg and avif (as encoded by ravif) are
+    /// always lossy, and gif quantizes the colors of the images to a palette of 256 colors.
+    pub fn has_lossless_mode(self) -> bool {
+        !matches!(self, OutputFormat::Jpeg | OutputFormat::Avif | OutputFormat::Gif)
+    }
+    /// Is the quality of this format taken into account ? The lossy formats (jpeg, webp, avif)
+    /// trade the quality of the thumbnails for their size.
+    pub fn is_lossy(self) -> bool {
+        matches!(self, OutputFormat::Jpeg | OutputFormat::WebP | OutputFormat::Avif)
+    }
+    /// The feature of the image crate providing the encoder of this format, if that feature is
+    /// not enabled in this build. 
+    /// 
+    /// Encoding webp requires the `webp-encoder` feature (which links against libwebp) and avif
+    /// requires the `avif-encoder` feature (which pulls the rav1e/ravif encoder). Both are
+    /// lossy formats which would honor the quality setting, but neither is enabled in this build.
+    pub fn missing_feature(self) -> Option<&'static str> {
+        match self {
+            OutputFormat::WebP => Some("webp-encoder"),
+            OutputFormat::Avif => Some("avif-encoder"),
+            _                  => None,
+        }
+    }
+    /// The format to use when encoding a thumbnail. The quality is ignored by all formats but jpeg.
+    /// The formats whose encoder is missing from this build are reported as unsupported.
+    pub fn image_output_format(self, quality: u8) -> ImageOutputFormat {
+        match self {
+            OutputFormat::Jpeg => ImageOutputFormat::Jpeg(quality),
+            OutputFormat::Png  => ImageOutputFormat::Png,
+            OutputFormat::WebP => ImageOutputFormat::Unsupported("webp (requires the webp-encoder feature of image)".to_string()),
+            OutputFormat::Avif => ImageOutputFormat::Unsupported("avif (requires the avif-encoder feature of image)".to_string()),
+            OutputFormat::Bmp  => ImageOutputFormat::Bmp,
+            OutputFormat::Gif  => ImageOutputFormat::Gif,
+        }
+    }
+}
+impl FromStr for OutputFormat {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::ALL.into_iter()
+            .find(|format| format.name() == s || format.aliases().contains(&s))
+            .ok_or(Error::CannotParseOutputFormat)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_filter_is_parsed_from_its_name() {
+        let names = [
+            ("nearest",     FilterType::Nearest),
+            ("box",         FilterType::Box),
+            ("triangle",    FilterType::Triangle),
+            ("gaussian",    FilterType::Gaussian),
+            ("catmull-rom", FilterType::CatmullRom),
+            ("lanczos3",    FilterType::Lanczos3),
+        ];
+        assert_eq!(names.len(), FilterType::ALL.len());
+        for (name, filter) in names {
+            assert_eq!(name.parse::<FilterType>().unwrap(), filter);
+            assert_eq!(filter.name(), name);
+        }
+    }
+
+    #[test]
+    fn filter_names_are_case_insensitive() {
+        assert_eq!("NEAREST".parse::<FilterType>().unwrap(), FilterType::Nearest);
+        assert_eq!("Nearest".parse::<FilterType>().unwrap(), FilterType::Nearest);
+        assert_eq!("Catmull-Rom".parse::<FilterType>().unwrap(), FilterType::CatmullRom);
+        assert_eq!("LaNcZoS3".parse::<FilterType>().unwrap(), FilterType::Lanczos3);
+    }
+
+    #[test]
+    fn filter_aliases_are_parsed() {
+        let aliases = [
+            ("nn",      FilterType::Nearest),
+            ("area",    FilterType::Box),
+            ("cubic",   FilterType::CatmullRom),
+            ("lanczos", FilterType::Lanczos3),
+        ];
+        for (alias, filter) in aliases {
+            assert_eq!(alias.parse::<FilterType>().unwrap(), filter);
+            assert_eq!(alias.to_ascii_uppercase().parse::<FilterType>().unwrap(), filter);
+            assert!(filter.aliases().contains(&alias));
+        }
+    }
+
+    #[test]
+    fn only_the_formats_which_keep_every_color_have_a_lossless_mode() {
+        for format in OutputFormat::ALL {
+            let lossless = matches!(format, OutputFormat::Png | OutputFormat::Bmp | OutputFormat::WebP);
+            assert_eq!(format.has_lossless_mode(), lossless, "{}", format.name());
+        }
+    }
+
+    #[test]
+    fn unknown_filters_are_rejected() {
+        for name in ["", "bicubic", "lanczos4", "near est", " nearest"] {
+            assert!(matches!(name.parse::<FilterType>(), Err(Error::CannotParseFilterType)), "{name:?} was accepted");
+        }
+    }
+}
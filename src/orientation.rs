@@ -0,0 +1,147 @@
+//! Guessing the rotation of the scanned documents which carry no EXIF orientation, so that the
+//! operators can pick the right `--rotate` for a whole batch. The guess relies on the layout of
+//! the text: its lines run along the width of an upright page, and being left-aligned (with a
+//! ragged right edge), they put more ink on the left half of the page than on its right half.
+//! This makes little sense for photographs, for which the guess is most often undecided.
+
+use std::{collections::BTreeMap, path::{Path, PathBuf}};
+
+use image::DynamicImage;
+use rayon::iter::{IntoParallelIterator, ParallelIterator};
+
+use crate::{Error, Pipeline, Task, decode, exif, pipeline::read_source};
+
+/// The number of images sampled in each folder by `--detect-orientation`
+pub const ORIENTATION_SAMPLES: usize = 5;
+
+/// The size of the box in which the images are shrunk before they are analyzed
+const ANALYSIS_SIZE: u32 = 512;
+
+/// How much more the ink profile across the lines must vary than the profile along them for the
+/// lines of text to be told apart (as a ratio of their coefficients of variation)
+const LINES_RATIO: f32 = 1.5;
+
+/// How much more ink the half of the page where the lines start must hold than the other half
+const START_RATIO: f32 = 1.1;
+
+/// The guessed rotation of the images of one folder
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Suggestion {
+    /// The folder holding the images
+    pub folder: PathBuf,
+    /// The rotation (clockwise, in degrees: 0, 90, 180 or 270) turning most of the sampled images
+    /// upright, if any (`None` when no guess is made for the majority of them)
+    pub rotate: Option<u16>,
+    /// The number of sampled images for which that rotation is guessed
+    pub votes: usize,
+    /// The number of images which have been sampled (and could be read)
+    pub sampled: usize,
+}
+
+/// Guesses the rotation which would turn the images of each folder of the `list` upright, from
+/// the first `samples` images of the folder (read as the `pipeline` would, in parallel). The
+/// folders are listed in order and the images which cannot be read (or decoded) are left out.
+/// When `auto_orient` is set, the guess applies to the images once they are oriented as per
+/// their EXIF data.
+pub fn suggest_rotations(list: &[Task], samples: usize, auto_orient: bool, pipeline: &Pipeline) -> Vec<Suggestion> {
+    let mut folders = BTreeMap::<&Path, Vec<&Path>>::new();
+    for task in list {
+        let sampled = folders.entry(task.src.parent().unwrap_or(Path::new(""))).or_default();
+        if sampled.len() < samples {
+            sampled.push(&task.src);
+        }
+    }
+    let guesses = folders.values().flatten().copied().collect::<Vec<_>>()
+        .into_par_iter()
+        .map(|src| {
+            let mut input = vec![];
+            let guess = read_source(pipeline, src, &mut input).and_then(|_| guess_rotation(&input, auto_orient));
+            if let Err(error) = &guess {
+                log::warn!("cannot guess the rotation of {}: {error}", src.display());
+            }
+            guess.ok()
+        })
+        .collect::<Vec<_>>();
+
+    let mut guesses = guesses.into_iter();
+    folders.into_iter()
+        .map(|(folder, sampled)| {
+            let guesses = guesses.by_ref().take(sampled.len()).flatten().collect::<Vec<_>>();
+            let mut votes = BTreeMap::<Option<u16>, usize>::new();
+            for &guess in guesses.iter() {
+                *votes.entry(guess).or_default() += 1;
+            }
+            // the undecided images win the ties, then the smallest rotation does
+            let (rotate, votes) = votes.into_iter().fold((None, 0), |best, (rotate, n)| if n > best.1 { (rotate, n) } else { best });
+            Suggestion { folder: folder.to_path_buf(), rotate, votes, sampled: guesses.len() }
+        })
+        .collect()
+}
+
+/// Guesses the rotation (clockwise, in degrees) which would turn the scanned document `input`
+/// upright. Returns `None` when the image does not look like a page of text or when its layout
+/// is ambiguous.
+pub fn guess_rotation(input: &[u8], auto_orient: bool) -> Result<Option<u16>, Error> {
+    let im = decode::decode(input)?;
+    let im = match exif::orientation(input) {
+        Some(orientation) if auto_orient => exif::apply_orientation(&im, orientation),
+        _                               => im,
+    };
+    Ok(rotation(&im))
+}
+
+/// Guesses the rotation which would turn the page `im` upright (see `guess_rotation`)
+fn rotation(im: &DynamicImage) -> Option<u16> {
+    let gray = im.thumbnail(ANALYSIS_SIZE, ANALYSIS_SIZE).to_luma8();
+    let (w, h) = gray.dimensions();
+    if w < 2 || h < 2 {
+        return None;
+    }
+    // the ink is what is noticeably darker than the paper
+    let mean = gray.pixels().map(|p| p[0] as f32).sum::<f32>() / (w * h) as f32;
+    let mut rows = vec![0.0; h as usize];
+    let mut cols = vec![0.0; w as usize];
+    for (x, y, p) in gray.enumerate_pixels() {
+        if (p[0] as f32) < 0.75 * mean {
+            rows[y as usize] += 1.0;
+            cols[x as usize] += 1.0;
+        }
+    }
+    let ink = rows.iter().sum::<f32>() / (w * h) as f32;
+    if !(0.002..=0.4).contains(&ink) {
+        return None;
+    }
+
+    let (across_rows, across_cols) = (variation(&rows), variation(&cols));
+    let half = |profile: &[f32]| {
+        let (first, second) = profile.split_at(profile.len() / 2);
+        (first.iter().sum::<f32>(), second.iter().sum::<f32>())
+    };
+    if across_rows > LINES_RATIO * across_cols {
+        // horizontal lines, starting on the left of an upright page
+        let (left, right) = half(&cols);
+        match () {
+            _ if left > START_RATIO * right => Some(0),
+            _ if right > START_RATIO * left => Some(180),
+            _                               => None,
+        }
+    } else if across_cols > LINES_RATIO * across_rows {
+        // vertical lines: the page has been turned a quarter, its left side lies at the top of
+        // the image when it has been turned clockwise
+        let (top, bottom) = half(&rows);
+        match () {
+            _ if top > START_RATIO * bottom => Some(270),
+            _ if bottom > START_RATIO * top => Some(90),
+            _                               => None,
+        }
+    } else {
+        None
+    }
+}
+
+/// The coefficient of variation (the standard deviation over the mean) of an ink profile
+fn variation(profile: &[f32]) -> f32 {
+    let mean = profile.iter().sum::<f32>() / profile.len() as f32;
+    let variance = profile.iter().map(|v| (v - mean) * (v - mean)).sum::<f32>() / profile.len() as f32;
+    if mean > 0.0 { variance.sqrt() / mean } else { 0.0 }
+}
@@ -0,0 +1,959 @@
+use std::{collections::HashSet, ffi::{OsStr, OsString}, path::{PathBuf, Path}, fs::{self, File, FileTimes}, io::{self, BufWriter, Cursor, Read, Seek, Write}, future::Future, sync::{Arc, atomic::{AtomicBool, Ordering}}, time::{Duration, SystemTime}};
+
+use rayon::iter::{ParallelBridge, ParallelIterator, IntoParallelIterator};
+use smol::{io::{AsyncReadExt, AsyncWriteExt}, channel::{Sender, Receiver}};
+
+use crate::{Archive, ZipSource, HttpSource, BlurhashComponents, BufferPool, Error, Manifest, Scan, SizeRange, Task, prepare_streaming, decode, error::WithPath, Progress, Stats, ThumbnailJob, Timings, blurhash, http::is_url, lqip, naming, resize::Sources};
+
+thread_local! {
+    /// The buffers into which each thread of the synchronous pipeline reads the source images
+    static SOURCES: BufferPool = BufferPool::new(1);
+}
+
+/// Generates the thumbnails `dst` (one per job) of the image `src`. Returns the number of bytes
+/// read and written. When `preserve_timestamps` is set, the thumbnails get the access and 
+/// modification times of `src`. The thumbnails are encoded straight into their files. The reads
+/// and writes failing with a transient error are retried as per the `pipeline`. The placeholders
+/// asked by the `pipeline` are recorded in the `stats`. Returns `None` when the image is skipped
+/// because its size is out of the range of the `pipeline`.
+fn sync_version(src: &Path, dst: &[PathBuf], jobs: &[ThumbnailJob], pipeline: &Pipeline, stats: &Stats) -> Result<Option<(u64, u64)>, Error>{
+    log::debug!("processing {}", src.display());
+    let times = if pipeline.preserve_timestamps { Some(file_times(pipeline.origin(src)).with_path(pipeline.origin(src))?) } else { None };
+    let mut input = SOURCES.with(|pool| pool.take());
+    retry(pipeline, src, || {
+        input.clear();
+        read_source(pipeline, src, &mut input)
+    })?;
+    if !in_range(src, &input, pipeline.sizes) {
+        SOURCES.with(|pool| pool.give(input));
+        return Ok(None);
+    }
+    let written = write_thumbnails(src, &input, dst, jobs, times, pipeline, stats);
+    let read = input.len() as u64;
+    SOURCES.with(|pool| pool.give(input));
+    let written = written?;
+    log::debug!("done with {}", src.display());
+    Ok(Some((read, written)))
+}
+
+/// Tells whether the size of the image `input` (read from `src`) lies within the `range`. The
+/// size is read from the header of the image, without decoding it: an image whose size cannot
+/// be read that way is deemed to be within the range. The images which are not are logged.
+pub(crate) fn in_range(src: &Path, input: &[u8], range: SizeRange) -> bool {
+    if range.is_unbounded() {
+        return true;
+    }
+    match decode::dimensions(input) {
+        Some((w, h)) if !range.contains(w, h) => {
+            log::debug!("skipping {}: its size ({w}x{h}) is out of range", src.display());
+            false
+        },
+        _ => true,
+    }
+}
+
+/// Tells whether the thumbnail of the image `input` made by `job` is a mere copy of the image
+/// (see `ThumbnailJob::is_copy`). It never is when the `pipeline` records placeholders, which
+/// are made from the decoded image.
+fn is_copy(input: &[u8], job: &ThumbnailJob, pipeline: &Pipeline) -> bool {
+    !pipeline.lqip && pipeline.blurhash.is_none() && job.is_copy(input)
+}
+
+/// Decodes the image `input` (read from `src`) for the `jobs` (see `Sources::new`). An empty 
+/// image is rejected with an `EmptyFile` error and an image which is cut short with a 
+/// `Truncated` error, both naming `src`.
+pub(crate) fn load_sources(src: &Path, input: &[u8], jobs: &[ThumbnailJob], pipeline: &Pipeline) -> Result<Sources, Error> {
+    if input.is_empty() {
+        return Err(Error::EmptyFile(src.to_path_buf()));
+    }
+    Sources::new(input, jobs, pipeline.timings).map_err(|error| decode::classify(error, src))
+}
+
+/// Decodes the image `input` (read from `src`) for those of the `jobs` whose thumbnails are not 
+/// mere copies of it
+fn decode_for(src: &Path, input: &[u8], jobs: &[ThumbnailJob], pipeline: &Pipeline) -> Result<Sources, Error> {
+    let decoded = jobs.iter().filter(|job| !is_copy(input, job, pipeline)).copied().collect::<Vec<_>>();
+    load_sources(src, input, &decoded, pipeline)
+}
+
+/// Creates the thumbnails `dst` of the image `input` (read from `src`) and encodes them straight
+/// into their files (or adds them to the archive of the `pipeline`). Returns the number of bytes
+/// written. The thumbnails which are mere copies of the image are written without decoding it.
+fn write_thumbnails(src: &Path, input: &[u8], dst: &[PathBuf], jobs: &[ThumbnailJob], times: Option<FileTimes>, pipeline: &Pipeline, stats: &Stats) -> Result<u64, Error> {
+    let sources = decode_for(src, input, jobs, pipeline)?;
+    placeholders(src, &sources, jobs, pipeline, stats)?;
+    let mut written = 0;
+    for (job, path) in jobs.iter().zip(dst) {
+        if is_copy(input, job, pipeline) {
+            log::trace!("copying {} to {}", src.display(), path.display());
+            written += match pipeline.archive {
+                Some(archive) => {
+                    archive.add(path, input, modified(pipeline.origin(src), pipeline.preserve_timestamps).with_path(pipeline.origin(src))?)?;
+                    input.len() as u64
+                },
+                None => retry(pipeline, path, || write_file(path, times, |out| out.write_all(input).with_path(path)))?,
+            };
+            continue;
+        }
+        for (index, im) in sources.frames(job).iter().enumerate() {
+            let path = naming::frame_name(path, job, index);
+            log::trace!("writing {}", path.display());
+            written += match pipeline.archive {
+                Some(archive) => {
+                    let mut encoded = Cursor::new(vec![]);
+                    sources.encode_into(job, im, &mut encoded)?;
+                    archive.add(&path, encoded.get_ref(), modified(pipeline.origin(src), pipeline.preserve_timestamps).with_path(pipeline.origin(src))?)?;
+                    encoded.get_ref().len() as u64
+                },
+                None => retry(pipeline, &path, || write_file(&path, times, |out| sources.encode_into(job, im, out)))?,
+            };
+        }
+    }
+    Ok(written)
+}
+
+/// Creates the thumbnails of the image `input` and pairs each of them with the path where it is to
+/// be written. `dst` holds the destination of each job: the thumbnails of the jobs producing one 
+/// thumbnail per frame are named after the index of their frame. The thumbnails are encoded into
+/// buffers taken from the `pool`. The placeholders of `src` asked by the `pipeline` are recorded
+/// in the `stats`. The thumbnails which are mere copies of the image are made without decoding it.
+fn thumbnails(src: &Path, input: &[u8], dst: &[PathBuf], jobs: &[ThumbnailJob], pool: &BufferPool, pipeline: &Pipeline, stats: &Stats) -> Result<Vec<(Vec<u8>, PathBuf)>, Error> {
+    let sources = decode_for(src, input, jobs, pipeline)?;
+    placeholders(src, &sources, jobs, pipeline, stats)?;
+    let mut thumbnails = vec![];
+    for (job, path) in jobs.iter().zip(dst) {
+        if is_copy(input, job, pipeline) {
+            let mut output = pool.take();
+            output.extend_from_slice(input);
+            thumbnails.push((output, path.clone()));
+            continue;
+        }
+        for (index, im) in sources.frames(job).iter().enumerate() {
+            let mut output = Cursor::new(pool.take());
+            sources.encode_into(job, im, &mut output)?;
+            thumbnails.push((output.into_inner(), naming::frame_name(path, job, index)));
+        }
+    }
+    Ok(thumbnails)
+}
+
+/// Records in the `stats` the placeholders of the image `src` asked by the `pipeline`. They are
+/// made from the first frame of the first job.
+fn placeholders(src: &Path, sources: &Sources, jobs: &[ThumbnailJob], pipeline: &Pipeline, stats: &Stats) -> Result<(), Error> {
+    if !pipeline.lqip && pipeline.blurhash.is_none() {
+        return Ok(());
+    }
+    let Some((job, im)) = jobs.first().and_then(|job| sources.frames(job).first().map(|im| (job, im))) else {
+        return Ok(());
+    };
+    if pipeline.lqip {
+        stats.placeholder(src, "lqip", lqip(im, job)?);
+    }
+    if let Some(components) = pipeline.blurhash {
+        stats.placeholder(src, "blurhash", blurhash(im, job, components));
+    }
+    Ok(())
+}
+
+/// Tells whether `error` is a transient io error (a timeout or an interruption), which is worth 
+/// retrying. The decoding errors never are.
+fn is_transient(error: &Error) -> bool {
+    matches!(error, Error::Io(e) | Error::IoWithPath { source: e, .. } if matches!(e.kind(), io::ErrorKind::TimedOut | io::ErrorKind::Interrupted | io::ErrorKind::WouldBlock))
+}
+
+/// Calls `f` (which reads or writes the file at `path`) until it succeeds or fails with an error
+/// which is not transient, at most `pipeline.retries` more times. The delay between two attempts 
+/// starts at `pipeline.retry_delay` and doubles after each attempt.
+fn retry<T>(pipeline: &Pipeline, path: &Path, mut f: impl FnMut() -> Result<T, Error>) -> Result<T, Error> {
+    let mut delay = pipeline.retry_delay;
+    for _ in 0..pipeline.retries {
+        match f() {
+            Err(error) if is_transient(&error) => {
+                log::warn!("{}: {error}, retrying in {delay:?}", path.display());
+                std::thread::sleep(delay);
+                delay *= 2;
+            },
+            outcome => return outcome,
+        }
+    }
+    f()
+}
+
+/// Same as `retry` but for the asynchronous pipeline
+async fn retry_async<T, F: Future<Output = Result<T, Error>>>(pipeline: &Pipeline, path: &Path, mut f: impl FnMut() -> F) -> Result<T, Error> {
+    let mut delay = pipeline.retry_delay;
+    for _ in 0..pipeline.retries {
+        match f().await {
+            Err(error) if is_transient(&error) => {
+                log::warn!("{}: {error}, retrying in {delay:?}", path.display());
+                smol::Timer::after(delay).await;
+                delay *= 2;
+            },
+            outcome => return outcome,
+        }
+    }
+    f().await
+}
+
+/// The temporary file in which the thumbnail `path` is written before it is renamed to `path`.
+/// It lies in the same folder, so that the renaming is atomic.
+fn temporary(path: &Path) -> PathBuf {
+    let mut name = OsString::from(".");
+    name.push(path.file_name().unwrap_or(OsStr::new("thumbnail")));
+    name.push(format!(".{}.tmp", std::process::id()));
+    path.with_file_name(name)
+}
+
+/// Moves the temporary file `tmp` to `path`. Should the rename be impossible (e.g. because the 
+/// temporary file ended up on another device), the file is copied instead.
+fn commit(tmp: &Path, path: &Path) -> io::Result<()> {
+    match fs::rename(tmp, path) {
+        Err(e) if e.kind() == io::ErrorKind::CrossesDevices => {
+            fs::copy(tmp, path)?;
+            fs::remove_file(tmp)
+        },
+        outcome => outcome,
+    }
+}
+
+/// Writes the file `path` atomically: `encode` writes the content to a temporary file which is
+/// then renamed to `path`. This way, `path` is either fully written or left untouched, even
+/// when the process crashes. The access and modification `times` of the file are set afterwards
+/// (when given). Returns the size of the file.
+pub(crate) fn write_file(path: &Path, times: Option<FileTimes>, encode: impl FnOnce(&mut BufWriter<File>) -> Result<(), Error>) -> Result<u64, Error> {
+    let tmp = temporary(path);
+    let outcome = File::create(&tmp).with_path(path)
+        .and_then(|file| {
+            let mut out = BufWriter::new(file);
+            encode(&mut out)?;
+            out.flush().with_path(path)?;
+            out.stream_position().with_path(path)
+        })
+        .and_then(|size| commit(&tmp, path).map(|_| size).with_path(path));
+    if outcome.is_err() {
+        _ = fs::remove_file(&tmp);
+    }
+    if let (Some(times), Ok(_)) = (times, &outcome) {
+        set_file_times(path, times).with_path(path)?;
+    }
+    outcome
+}
+
+/// Same as `write_file` but for the asynchronous pipeline, where the thumbnail is already encoded
+/// in `data`
+async fn write_file_async(path: PathBuf, data: &[u8], times: Option<FileTimes>) -> io::Result<()> {
+    let tmp = temporary(&path);
+    let mut outcome = create_file_async(&tmp, data).await;
+    if outcome.is_ok() {
+        outcome = smol::fs::rename(&tmp, &path).await;
+    }
+    if matches!(&outcome, Err(e) if e.kind() == io::ErrorKind::CrossesDevices) {
+        outcome = smol::fs::copy(&tmp, &path).await.map(|_| ());
+        _ = smol::fs::remove_file(&tmp).await;
+    }
+    if outcome.is_err() {
+        _ = smol::fs::remove_file(&tmp).await;
+    }
+    match times {
+        Some(times) if outcome.is_ok() => smol::unblock(move || set_file_times(&path, times)).await,
+        _ => outcome,
+    }
+}
+
+/// Creates the file `path` holding `data`
+async fn create_file_async(path: &Path, data: &[u8]) -> io::Result<()> {
+    let mut file = smol::fs::File::create(path).await?;
+    file.write_all(data).await?;
+    file.flush().await
+}
+
+/// The modification time of the entries an archive gets for the thumbnails of `src`: that of 
+/// `src` when `preserve_timestamps` is set, the current time otherwise
+fn modified(src: &Path, preserve_timestamps: bool) -> io::Result<SystemTime> {
+    if preserve_timestamps { fs::metadata(src)?.modified() } else { Ok(SystemTime::now()) }
+}
+
+/// The access and modification times of the file at `path`
+fn file_times(path: &Path) -> io::Result<FileTimes> {
+    let metadata = fs::metadata(path)?;
+    Ok(FileTimes::new().set_accessed(metadata.accessed()?).set_modified(metadata.modified()?))
+}
+
+/// Sets the access and modification times of the file at `path`
+fn set_file_times(path: &Path, times: FileTimes) -> io::Result<()> {
+    fs::File::options().write(true).open(path)?.set_times(times)
+}
+
+/// Reads the whole content of the source image `src` (a file, an entry of the source archive
+/// of the `pipeline` or a URL fetched by its http source) into the (empty) buffer `content`
+pub(crate) fn read_source(pipeline: &Pipeline, src: &Path, content: &mut Vec<u8>) -> Result<(), Error> {
+    match (pipeline.source, pipeline.http) {
+        (Some(archive), _)               => archive.read(src, content),
+        (None, Some(http)) if is_url(src) => http.read(src, content),
+        _                                => File::open(src).and_then(|mut file| file.read_to_end(content)).map(|_| ()).with_path(src),
+    }
+}
+
+/// Same as `read_source`, without blocking the executor
+async fn read_source_async(pipeline: &Pipeline, src: &Path, mut content: Vec<u8>) -> Result<Vec<u8>, Error> {
+    match (pipeline.source, pipeline.http) {
+        (Some(archive), _) => {
+            let src = src.to_path_buf();
+            smol::unblock(move || archive.read(&src, &mut content).map(|_| content)).await
+        },
+        (None, Some(http)) if is_url(src) => {
+            let src = src.to_path_buf();
+            smol::unblock(move || http.read(&src, &mut content).map(|_| content)).await
+        },
+        _ => read_file(src, content).await,
+    }
+}
+
+/// Reads the whole content of the file at `src` into the (empty) buffer `content`
+async fn read_file(src: &Path, mut content: Vec<u8>) -> Result<Vec<u8>, Error> {
+    let read = async {
+        smol::fs::File::open(src).await?.read_to_end(&mut content).await
+    };
+    read.await.with_path(src)?;
+    Ok(content)
+}
+
+/// Processes all images of the list as a three stages pipeline: `limit` async tasks read the 
+/// source files concurrently, `threads` worker threads resize them and one other async task 
+/// writes the thumbnails to disk. All three stages run concurrently and are connected through 
+/// bounded channels of capacity `limit`, which provides the backpressure between them. On top
+/// of that, no more than `limit` images are in flight (from the moment they are read until their
+/// thumbnails are written) at any time.
+/// 
+/// Each end of the channels is owned by the stage which uses it. This way, when a stage stops 
+/// (because it has completed or because it has failed) the channels get closed and the other
+/// stages terminate instead of being stuck forever.
+/// 
+/// When the `interrupt` flag is raised, the readers stop picking new files and the workers stop
+/// resizing the files which have been read. The thumbnails which are already produced are 
+/// written to completion and the pipeline then stops normally.
+/// 
+/// Every file which has been completely processed is reported on the `done` channel (which feeds
+/// the progress bar and the statistics) along with its thumbnails and the number of bytes read 
+/// and written. Unless `fail_fast` is set, a file which cannot be processed is reported on that
+/// same channel and the pipeline moves on to the next file. 
+/// 
+/// The buffers holding the source files and the encoded thumbnails are pooled: they are given
+/// back once the images have been resized (resp. written) and reused for the next files rather
+/// than allocated anew.
+fn async_version(todo_rx: Receiver<Task>, jobs: &[ThumbnailJob], pipeline: Pipeline, progress: &Progress, stats: &Stats) -> Result<Vec<Failure>, Error> {
+    let Pipeline { limit, threads, fail_fast, interrupt, preserve_timestamps, archive, .. } = pipeline;
+    // a channel needs some room: at least one file is read at a time
+    let limit = limit.max(1);
+    type Input  = (Vec<u8>, Task);
+    type InSx   = Sender<Input>;
+    type InRx   = Receiver<Input>;
+    type Output = (Vec<(Vec<u8>, PathBuf)>, PathBuf, u64);
+    type OutSx  = Sender<Output>;
+    type OutRx  = Receiver<Output>;
+    type Done   = (PathBuf, Vec<PathBuf>, Result<Option<(u64, u64)>, Error>);
+    type DoneSx = Sender<Done>;
+    type DoneRx = Receiver<Done>;
+    
+    let (input_sx, input_rx): (InSx, InRx) = smol::channel::bounded(limit);
+    let (output_sx, output_rx): (OutSx, OutRx) = smol::channel::bounded(limit);
+    let (done_sx, done_rx): (DoneSx, DoneRx) = smol::channel::unbounded();
+    // holds one token per image in flight: the readers put one in before reading a file and it is
+    // taken out once the file is done with (or dropped because of an interruption). A stage 
+    // failing for good closes it, which stops the readers.
+    let (permit_sx, permit_rx) = smol::channel::bounded::<()>(limit);
+
+    // at most this many buffers are in flight in each direction
+    let inputs  = Arc::new(BufferPool::new(limit + threads));
+    let outputs = Arc::new(BufferPool::new(limit + threads));
+    
+    // opening n-files asynchronously: the readers pick the files to read from a shared queue
+    let open_files = (0..limit)
+        .map(|_| {
+            let todo  = todo_rx.clone();
+            let input = input_sx.clone();
+            let done  = done_sx.clone();
+            let pool  = inputs.clone();
+            let permit = permit_sx.clone();
+            smol::spawn(async move {
+                while let Ok(task) = todo.recv().await {
+                    if interrupted(interrupt) || permit.send(()).await.is_err() {
+                        break;
+                    }
+                    match retry_async(&pipeline, &task.src, || read_source_async(&pipeline, &task.src, pool.take())).await {
+                        Ok(content) => input.send((content, task)).await.map_err(|se| Error::SendError(format!("{se}")))?,
+                        Err(error) if !fail_fast => _ = done.send((task.src, task.dst, Err(error))).await,
+                        Err(error) => {
+                            // the other readers must stop as well
+                            todo.close();
+                            permit.close();
+                            return Err(error);
+                        },
+                    }
+                }
+                Result::<(), Error>::Ok(())
+            })
+        })
+        .collect::<Vec<_>>();
+    drop(todo_rx);
+    drop(input_sx);
+    drop(permit_sx);
+
+    // writing the thumbnails as soon as they are produced
+    let done = done_sx.clone();
+    let pool = outputs.clone();
+    let permit = permit_rx.clone();
+    let write_files = smol::spawn(async move {
+        while let Ok((thumbnails, src, read)) = output_rx.recv().await {
+            let paths = thumbnails.iter().map(|(_, path)| path.clone()).collect::<Vec<_>>();
+            // the source has just been read, its metadata are readily available
+            let times = if preserve_timestamps { file_times(pipeline.origin(&src)).ok() } else { None };
+            let mut outcome = Ok(0);
+            for (out_data, out_path) in thumbnails {
+                log::trace!("writing {}", out_path.display());
+                let size    = out_data.len() as u64;
+                let written = match archive {
+                    Some(archive) => modified(pipeline.origin(&src), preserve_timestamps).with_path(pipeline.origin(&src))
+                        .and_then(|mtime| archive.add(&out_path, &out_data, mtime)),
+                    None => retry_async(&pipeline, &out_path, || async { write_file_async(out_path.clone(), &out_data, times).await.with_path(&out_path) }).await,
+                }.map(|_| size);
+                pool.give(out_data);
+                outcome = outcome.and_then(|total| written.map(|w| total + w));
+            }
+            match outcome {
+                Ok(written)              => {
+                    log::debug!("done with {}", src.display());
+                    _ = done.send((src, paths, Ok(Some((read, written))))).await
+                },
+                Err(error) if !fail_fast => _ = done.send((src, paths, Err(error))).await,
+                Err(error)               => {
+                    permit.close();
+                    return Err(error);
+                },
+            }
+        }
+        Result::<(), Error>::Ok(())
+    });
+
+    // resizing the images on the worker threads
+    let (workers, failures) = std::thread::scope(|s| {
+        let mut handles = vec![];
+        for _ in 0..threads {
+            let irx  = input_rx.clone();
+            let osx  = output_sx.clone();
+            let done = done_sx.clone();
+            let (inputs, outputs) = (&inputs, &outputs);
+            let permit = permit_rx.clone();
+            handles.push(s.spawn(move || {
+                while let Ok((data, task)) = irx.recv_blocking() {
+                    let task_jobs = task.jobs(jobs);
+                    let Task { src, dst, .. } = task;
+                    // the files which have been read but not resized yet are dropped
+                    if interrupted(interrupt) {
+                        _ = permit.try_recv();
+                        continue;
+                    }
+                    if !in_range(&src, &data, pipeline.sizes) {
+                        inputs.give(data);
+                        _ = done.send_blocking((src, dst, Ok(None)));
+                        continue;
+                    }
+                    log::debug!("processing {}", src.display());
+                    let outcome = thumbnails(&src, &data, &dst, &task_jobs, outputs, &pipeline, stats);
+                    let read = data.len() as u64;
+                    inputs.give(data);
+                    match outcome {
+                        Ok(output)               => osx.send_blocking((output, src, read)).map_err(|se| Error::SendError(format!("{se}")))?,
+                        Err(error) if !fail_fast => _ = done.send_blocking((src, dst, Err(error))),
+                        Err(error)               => {
+                            // this error ends the run, it must tell which image it happened on
+                            permit.close();
+                            stats.failed(&src, &dst, &error);
+                            return Err(error.failed(&src));
+                        },
+                    }
+                }
+                Result::<(), Error>::Ok(())
+            }));
+        }
+        // from now on, only the stages hold these ends of the channels
+        drop(input_rx);
+        drop(output_sx);
+        drop(done_sx);
+
+        // the progress is driven by the completed (or failed) files. This happens on a thread of
+        // its own because the tasks running on the executor cannot borrow the progress bar
+        let failures = s.spawn(move || {
+            let mut failures = vec![];
+            while let Ok((src, dst, outcome)) = done_rx.recv_blocking() {
+                _ = permit_rx.try_recv();
+                progress.inc();
+                match outcome {
+                    Ok(Some((read, written))) => {
+                        stats.processed(&src, &dst, read, written);
+                        if let Some(manifest) = pipeline.manifest {
+                            manifest.processed(&src);
+                        }
+                    },
+                    Ok(None)                  => stats.skipped(&src, &dst),
+                    Err(error)                => {
+                        stats.failed(&src, &dst, &error);
+                        failures.push(Failure { src, error });
+                    },
+                }
+            }
+            failures
+        });
+
+        let mut result = Ok(());
+        for t in handles {
+            let outcome = t.join().map_err(|je| Error::JoinError(format!("{je:?}")));
+            result = result.and(outcome.and_then(|r| r));
+        }
+        let failures = failures.join().map_err(|je| Error::JoinError(format!("{je:?}")));
+        (result, failures)
+    });
+
+    let io = smol::block_on(async {
+        let mut read = Ok(());
+        for reader in open_files {
+            read = read.and(reader.await);
+        }
+        let write = write_files.await;
+        read.and(write)
+    });
+
+    // a failing worker closes the input channel, which makes the reader fail in turn: report
+    // the root cause rather than the consequence
+    workers.and(io).and(failures)
+}
+
+/// How the thumbnails are to be produced
+#[derive(Debug, Clone, Copy)]
+pub struct Pipeline {
+    /// Do we want to perform asynchronous io operations ? When `None`, the pipeline is chosen 
+    /// automatically (see `prefers_async`).
+    pub asynchronous: Option<bool>,
+    /// Maximum number of images in flight (read but not written yet) at any time, which bounds the
+    /// memory used by the decoded images. When it is lower than `threads`, some threads are idle.  
+    pub limit: usize,
+    /// Number of threads resizing the images (0 means one per cpu core)
+    pub threads: usize,
+    /// Abort the whole run as soon as one file cannot be processed
+    pub fail_fast: bool,
+    /// A flag telling that the run must be interrupted (e.g. when the user hits Ctrl-C): from the
+    /// moment it is raised, no new file is processed. The files being processed are completed so
+    /// that no thumbnail is left half-written.
+    pub interrupt: Option<&'static AtomicBool>,
+    /// Should the thumbnails get the access and modification times of their source image ?
+    pub preserve_timestamps: bool,
+    /// How many times a read or a write failing with a transient io error (e.g. a timeout on a 
+    /// network share) is retried before the file is counted as failed
+    pub retries: u32,
+    /// The delay before the first retry (it doubles after each retry)
+    pub retry_delay: Duration,
+    /// Should the low quality image placeholder of each source (see `lqip`) be recorded in the 
+    /// stats, alongside its thumbnails ?
+    pub lqip: bool,
+    /// When set, the blurhash of each source (see `blurhash`) is recorded in the stats as well. It
+    /// is computed from the decoded image, which thus needs no second decoding.
+    pub blurhash: Option<BlurhashComponents>,
+    /// When set, the thumbnails are added to this archive rather than written to their own files:
+    /// their destinations are then the (relative) names of the archive entries. The archive must
+    /// be `finish`ed once the run is over.
+    pub archive: Option<&'static Archive>,
+    /// When set, the source images are the entries of this zip archive (see `prepare_archive`) 
+    /// rather than files of their own
+    pub source: Option<&'static ZipSource>,
+    /// When set, the source images given as `http://` URLs (see `prepare_files`) are fetched by
+    /// this http source. Otherwise, they are taken for paths.
+    pub http: Option<&'static HttpSource>,
+    /// The source images whose size (as read from their header) lies outside of this range are
+    /// skipped without being decoded
+    pub sizes: SizeRange,
+    /// When set, the time spent decoding, resizing and encoding is recorded in these timings
+    pub timings: Option<&'static Timings>,
+    /// When set, the sources whose thumbnails have been generated are recorded in this manifest
+    /// (which must be `save`d once the run is over)
+    pub manifest: Option<&'static Manifest>,
+}
+impl Default for Pipeline {
+    fn default() -> Self {
+        Self { asynchronous: None, limit: 10, threads: 0, fail_fast: false, interrupt: None, preserve_timestamps: false, retries: 0, retry_delay: Duration::from_millis(100), lqip: false, blurhash: None, archive: None, source: None, http: None, sizes: SizeRange::default(), timings: None, manifest: None }
+    }
+}
+
+impl Pipeline {
+    /// The file holding the source image `src`: the source archive if there is one, `src` itself
+    /// otherwise
+    pub(crate) fn origin<'a>(&'a self, src: &'a Path) -> &'a Path {
+        self.source.map(|archive| archive.path()).unwrap_or(src)
+    }
+}
+
+/// Tells whether the `interrupt` flag has been raised
+pub(crate) fn interrupted(interrupt: Option<&AtomicBool>) -> bool {
+    interrupt.map(|flag| flag.load(Ordering::Relaxed)).unwrap_or(false)
+}
+
+/// A file which could not be turned into a thumbnail
+#[derive(Debug)]
+pub struct Failure {
+    /// Path to the source image
+    pub src: PathBuf,
+    /// What went wrong
+    pub error: Error,
+}
+
+/// Creates the folders in which the thumbnails of `list` are to be written
+fn create_dirs(list: &[Task]) -> Result<(), Error> {
+    let dirs = list.iter()
+        .flat_map(|task| task.dst.iter().filter_map(|d| d.parent()))
+        .collect::<HashSet<_>>();
+    for dir in dirs {
+        fs::create_dir_all(dir).with_path(dir)?;
+    }
+    Ok(())
+}
+
+/// Number of source images from which the asynchronous pipeline is picked automatically
+pub const ASYNC_THRESHOLD: usize = 1000;
+
+/// Tells whether the asynchronous pipeline should be preferred to process the images of `list`,
+/// when the user has not chosen any. This is the case for large batches (of `ASYNC_THRESHOLD`
+/// images or more) and for the images lying on a network file system (nfs, smb, sshfs...) where
+/// the io latency dominates: the asynchronous pipeline keeps many reads in flight meanwhile the 
+/// images are resized. The synchronous pipeline has less overhead and wins on small batches 
+/// read from a local disk.
+pub fn prefers_async(list: &[Task]) -> bool {
+    list.len() >= ASYNC_THRESHOLD || list.first().map(|task| on_network_fs(&task.src)).unwrap_or(false)
+}
+
+/// Tells whether the file at `path` lies on a network file system
+#[cfg(target_os = "linux")]
+fn on_network_fs(path: &Path) -> bool {
+    use std::{ffi::CString, mem::MaybeUninit, os::unix::ffi::OsStrExt};
+    /// The magic numbers of nfs, smb, cifs, smb2, fuse (e.g. sshfs) and 9p (see statfs(2))
+    const NETWORK: [u32; 6] = [0x6969, 0x517b, 0xff53_4d42, 0xfe53_4d42, 0x6573_5546, 0x0102_1997];
+    let Ok(path) = CString::new(path.as_os_str().as_bytes()) else {
+        return false;
+    };
+    let mut stats = MaybeUninit::<libc::statfs>::uninit();
+    // SAFETY: path is nul-terminated and stats is only read once statfs has filled it
+    unsafe {
+        libc::statfs(path.as_ptr(), stats.as_mut_ptr()) == 0 && NETWORK.contains(&(stats.assume_init().f_type as u32))
+    }
+}
+
+/// Tells whether the file at `path` lies on a network file system (only detected on linux)
+#[cfg(not(target_os = "linux"))]
+fn on_network_fs(_path: &Path) -> bool {
+    false
+}
+
+/// Generates the thumbnails of each task of the `list` in parallel, either with the 
+/// synchronous (rayon) or with the asynchronous pipeline (chosen automatically unless the
+/// `pipeline` says otherwise). Each source image gets one thumbnail
+/// per job (resized with the filter of its task, if any): the `dst` of its task lists their 
+/// destinations in the same order as `jobs`. The `progress` bar is 
+/// incremented each time a source file has been processed, and the `stats` are updated
+/// accordingly. The destination folders are created beforehand as needed (unless the thumbnails
+/// go to an archive). In both cases, the 
+/// images are resized on `threads` threads: the synchronous version uses a dedicated rayon 
+/// thread pool rather than the global one.
+/// 
+/// The files which could not be processed are returned, unless the pipeline is set to fail fast
+/// in which case the first error aborts the run. A job asking for an output format whose encoder
+/// is not part of this build (or for a lossless encoding the format does not have) aborts the run
+/// before anything is done.
+pub fn run(jobs: &[ThumbnailJob], pipeline: &Pipeline, list: Vec<Task>, progress: &Progress, stats: &Stats) -> Result<Vec<Failure>, Error> {
+    check_jobs(jobs)?;
+    if pipeline.archive.is_none() {
+        create_dirs(&list)?;
+    }
+    let asynchronous = pipeline.asynchronous.unwrap_or_else(|| prefers_async(&list));
+    process(asynchronous, Queue::List(list), jobs, pipeline, progress, stats)
+}
+
+/// Makes sure the thumbnails of all the `jobs` can be encoded by this build
+pub(crate) fn check_jobs(jobs: &[ThumbnailJob]) -> Result<(), Error> {
+    if let Some((format, feature)) = jobs.iter().find_map(|j| j.format.missing_feature().map(|f| (j.format, f))) {
+        return Err(Error::UnsupportedOutputFormat(format.extension(), feature));
+    }
+    if let Some(job) = jobs.iter().find(|j| j.lossless && !j.format.has_lossless_mode()) {
+        return Err(Error::NoLosslessMode(job.format.extension()));
+    }
+    Ok(())
+}
+
+/// Same as `run`, but the images are those of the `src` folder (as listed by `prepare` with the
+/// `scan` options, their thumbnails going to `dst`) and they are processed while the folder is
+/// being scanned: the first thumbnails are created right away and the whole list is never held
+/// in memory. The `progress` bar grows as the images are found and the destination folders are
+/// created as they are needed.
+/// 
+/// The number of images found (and skipped) is logged once the scan is over.
+/// 
+/// Unless the `pipeline` says otherwise, the pipeline is chosen as `run` would, once 
+/// `ASYNC_THRESHOLD` images have been found (or the scan is over). A scan failing for good (e.g.
+/// because of a name collision) stops the run: its error is returned once the images found so far 
+/// are processed.
+pub fn run_streaming(src: &str, dst: &str, scan: &Scan, jobs: &[ThumbnailJob], pipeline: &Pipeline, progress: &Progress, stats: &Stats) -> Result<Vec<Failure>, Error> {
+    check_jobs(jobs)?;
+    let (found_sx, found_rx) = smol::channel::bounded(ASYNC_THRESHOLD);
+    std::thread::scope(|s| {
+        // the scan stops as soon as the pipeline does not take the images anymore
+        let scanner = s.spawn(move || {
+            let mut dirs = HashSet::new();
+            let mut created = Ok(());
+            let (mut found, mut stopped) = (0, false);
+            prepare_streaming(src, dst, scan, jobs, stats, |task| {
+                for dir in task.dst.iter().filter_map(|d| d.parent()).filter(|_| pipeline.archive.is_none()) {
+                    if !dirs.contains(dir) {
+                        if let Err(error) = fs::create_dir_all(dir).with_path(dir) {
+                            created = Err(error);
+                            return false;
+                        }
+                        dirs.insert(dir.to_path_buf());
+                    }
+                }
+                progress.grow(1);
+                found += 1;
+                stopped = interrupted(pipeline.interrupt) || found_sx.send_blocking(task).is_err();
+                !stopped
+            })?;
+            if !stopped && created.is_ok() {
+                log::info!("{found} image(s) selected, {} skipped", stats.skipped_count());
+            }
+            created
+        });
+
+        let mut head = vec![];
+        let mut complete = false;
+        let asynchronous = match pipeline.asynchronous {
+            Some(asynchronous) => asynchronous,
+            None => {
+                while head.len() < ASYNC_THRESHOLD {
+                    match found_rx.recv_blocking() {
+                        Ok(item) => head.push(item),
+                        Err(_)   => { complete = true; break },
+                    }
+                }
+                prefers_async(&head)
+            },
+        };
+        let queue = match () {
+            _ if complete        => Queue::List(head),
+            _ if head.is_empty() => Queue::Channel(found_rx),
+            _ => {
+                // the images found so far come first, then those which are still to be found
+                let (todo_sx, todo_rx) = smol::channel::unbounded();
+                for item in head {
+                    _ = todo_sx.try_send(item);
+                }
+                s.spawn(move || {
+                    while let Ok(item) = found_rx.recv_blocking() {
+                        if todo_sx.send_blocking(item).is_err() {
+                            break;
+                        }
+                    }
+                });
+                Queue::Channel(todo_rx)
+            },
+        };
+        let result  = process(asynchronous, queue, jobs, pipeline, progress, stats);
+        let scanned = scanner.join().map_err(|je| Error::JoinError(format!("{je:?}")))?;
+        let failures = result?;
+        scanned.map(|_| failures)
+    })
+}
+
+/// The images to process, along with the destinations of their thumbnails
+enum Queue {
+    /// All of them, known beforehand
+    List(Vec<Task>),
+    /// As they are found, until the channel is closed
+    Channel(Receiver<Task>),
+}
+
+/// Generates the thumbnails of the images of the `queue` with the `asynchronous` or with the 
+/// synchronous pipeline (see `run`)
+fn process(asynchronous: bool, queue: Queue, jobs: &[ThumbnailJob], pipeline: &Pipeline, progress: &Progress, stats: &Stats) -> Result<Vec<Failure>, Error> {
+    let threads  = if pipeline.threads == 0 { num_cpus::get() } else { pipeline.threads };
+    let pipeline = Pipeline { threads, ..*pipeline };
+    log::debug!("using the {} pipeline", if asynchronous { "asynchronous" } else { "synchronous" });
+    let result = if asynchronous {
+        let todo = match queue {
+            Queue::List(list) => {
+                let (todo_sx, todo_rx) = smol::channel::unbounded();
+                for item in list {
+                    _ = todo_sx.try_send(item);
+                }
+                todo_rx
+            },
+            Queue::Channel(todo) => todo,
+        };
+        async_version(todo, jobs, pipeline, progress, stats)
+    } else {
+        rayon::ThreadPoolBuilder::new()
+            .num_threads(threads)
+            .build()?
+            .install(|| match queue {
+                Queue::List(list)    => sync_pipeline(list.into_par_iter(), jobs, pipeline, progress, stats),
+                Queue::Channel(todo) => sync_pipeline(std::iter::from_fn(move || todo.recv_blocking().ok()).par_bridge(), jobs, pipeline, progress, stats),
+            })
+    };
+    progress.finish();
+    result
+}
+
+/// Processes all images of the list in parallel on the current rayon thread pool, with no more
+/// than `limit` images being processed at the same time. Once the `interrupt` flag is raised, 
+/// the remaining images are skipped.
+fn sync_pipeline(list: impl ParallelIterator<Item = Task>, jobs: &[ThumbnailJob], pipeline: Pipeline, progress: &Progress, stats: &Stats) -> Result<Vec<Failure>, Error> {
+    let Pipeline { fail_fast, interrupt, limit, .. } = pipeline;
+    // holds one token per image in flight, which caps them to `limit`
+    let (permit_sx, permit_rx) = smol::channel::bounded::<()>(limit.max(1));
+    let process = |task: &Task| {
+        _ = permit_sx.send_blocking(());
+        let outcome = sync_version(&task.src, &task.dst, &task.jobs(jobs), &pipeline, stats);
+        _ = permit_rx.try_recv();
+        outcome
+    };
+    if fail_fast {
+        list.try_for_each(|task| {
+                if interrupted(interrupt) {
+                    return Ok(());
+                }
+                let Task { src: s, dst: d, .. } = &task;
+                let outcome = process(&task).map_err(|error| {
+                    // this error ends the run, it must tell which image it happened on
+                    stats.failed(s, d, &error);
+                    error.failed(s)
+                });
+                match outcome? {
+                    Some((read, written)) => {
+                        stats.processed(s, d, read, written);
+                        if let Some(manifest) = pipeline.manifest {
+                            manifest.processed(s);
+                        }
+                    },
+                    None                  => stats.skipped(s, d),
+                }
+                progress.inc();
+                Ok(())
+            })
+            .map(|_| vec![])
+    } else {
+        Ok(list.filter_map(|task| {
+                if interrupted(interrupt) {
+                    return None;
+                }
+                let outcome = process(&task);
+                let Task { src, dst: d, .. } = task;
+                progress.inc();
+                match outcome {
+                    Ok(Some((read, written))) => { 
+                        stats.processed(&src, &d, read, written); 
+                        if let Some(manifest) = pipeline.manifest {
+                            manifest.processed(&src);
+                        }
+                        None 
+                    },
+                    Ok(None) => {
+                        stats.skipped(&src, &d);
+                        None
+                    },
+                    Err(error) => {
+                        stats.failed(&src, &d, &error);
+                        Some(Failure { src, error })
+                    },
+                }
+            })
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{TempDir, job, write_png};
+
+    #[test]
+    fn the_asynchronous_pipeline_makes_the_thumbnails_and_lists_the_failures() {
+        let dir = TempDir::new("async");
+        let (src, dst) = (dir.path().join("src"), dir.path().join("dst"));
+        let mut list = vec![];
+        for (i, (w, h)) in [(40, 30), (64, 64), (25, 90)].into_iter().enumerate() {
+            let path = src.join(format!("{i}.png"));
+            write_png(&path, w, h);
+            list.push(Task { src: path, dst: vec![dst.join(format!("{i}.jpg"))], filter: None });
+        }
+        fs::write(src.join("broken.png"), b"not an image").unwrap();
+        list.push(Task { src: src.join("broken.png"), dst: vec![dst.join("broken.jpg")], filter: None });
+
+        let pipeline = Pipeline { asynchronous: Some(true), threads: 2, limit: 2, ..Pipeline::default() };
+        let stats = Stats::new();
+        let failures = run(&[job(16, 12)], &pipeline, list, &Progress::hidden(), &stats).unwrap();
+
+        for i in 0..3 {
+            let thumbnail = image::open(dst.join(format!("{i}.jpg"))).unwrap();
+            assert_eq!((thumbnail.width(), thumbnail.height()), (16, 12));
+        }
+        assert_eq!(failures.len(), 1);
+        assert_eq!(failures[0].src, src.join("broken.png"));
+        assert!(!dst.join("broken.jpg").exists());
+    }
+
+    #[test]
+    fn the_asynchronous_pipeline_reads_one_file_at_a_time_when_the_limit_is_0() {
+        let dir = TempDir::new("limit");
+        let src = dir.path().join("a.png");
+        write_png(&src, 20, 20);
+        let list = vec![Task { src, dst: vec![dir.path().join("dst").join("a.jpg")], filter: None }];
+
+        let pipeline = Pipeline { asynchronous: Some(true), threads: 1, limit: 0, ..Pipeline::default() };
+        let failures = run(&[job(8, 8)], &pipeline, list, &Progress::hidden(), &Stats::new()).unwrap();
+        assert!(failures.is_empty());
+        assert!(dir.path().join("dst").join("a.jpg").exists());
+    }
+
+    #[test]
+    fn both_pipelines_tell_which_image_ended_a_run_which_fails_fast() {
+        let dir = TempDir::new("failfast");
+        let src = dir.path().join("broken.png");
+        fs::write(&src, b"not an image").unwrap();
+        for asynchronous in [false, true] {
+            let list = vec![Task { src: src.clone(), dst: vec![dir.path().join("broken.jpg")], filter: None }];
+            let pipeline = Pipeline { asynchronous: Some(asynchronous), threads: 1, fail_fast: true, ..Pipeline::default() };
+            let stats = Stats::new();
+            let error = run(&[job(8, 8)], &pipeline, list, &Progress::hidden(), &stats).unwrap_err();
+            assert!(matches!(&error, Error::Failed(path, _) if *path == src), "{error}");
+            assert!(stats.to_string().contains("1 failed"), "{stats}");
+        }
+    }
+
+    #[test]
+    fn the_empty_and_truncated_images_are_reported_with_their_path() {
+        let dir = TempDir::new("truncated");
+        let (empty, truncated) = (dir.path().join("empty.png"), dir.path().join("truncated.png"));
+        fs::write(&empty, b"").unwrap();
+        write_png(&truncated, 64, 64);
+        let content = fs::read(&truncated).unwrap();
+        fs::write(&truncated, &content[..content.len() / 2]).unwrap();
+
+        for asynchronous in [false, true] {
+            let list = [&empty, &truncated].map(|src| Task { src: src.clone(), dst: vec![src.with_extension("jpg")], filter: None });
+            let pipeline = Pipeline { asynchronous: Some(asynchronous), threads: 1, ..Pipeline::default() };
+            let mut failures = run(&[job(8, 8)], &pipeline, list.to_vec(), &Progress::hidden(), &Stats::new()).unwrap();
+            failures.sort_by(|a, b| a.src.cmp(&b.src));
+            assert_eq!(failures.len(), 2);
+            assert!(matches!(&failures[0].error, Error::EmptyFile(path) if *path == empty), "{}", failures[0].error);
+            assert!(matches!(&failures[1].error, Error::Truncated(path, _) if *path == truncated), "{}", failures[1].error);
+            // the summary names the source next to the error: the message does not repeat it
+            assert_eq!(failures[0].error.to_string(), "the file is empty");
+            assert!(!failures[1].error.to_string().contains("truncated.png"));
+            assert!(failures.pop().unwrap().error.failed(&truncated).to_string().starts_with(&format!("{} could not be processed: truncated image", truncated.display())));
+        }
+    }
+}
@@ -0,0 +1,127 @@
+use std::{f32::consts::PI, io::Cursor};
+
+use image::{imageops, DynamicImage};
+
+use crate::{BlurhashComponents, Error, OutputFormat, ResizeMode, ThumbnailJob, resize};
+
+/// The size of the box in which the low quality image placeholders fit
+pub const LQIP_SIZE: u32 = 16;
+
+/// The jpeg quality of the low quality image placeholders
+const LQIP_QUALITY: u8 = 40;
+
+/// Creates the low quality image placeholder (LQIP) of the image `im`: a blurred jpeg fitting in a
+/// `LQIP_SIZE` x `LQIP_SIZE` box, encoded as a base64 data URI (`data:image/jpeg;base64,...`)
+/// which can be inlined in a web page while the actual thumbnail loads. The placeholder is
+/// created as described by the `job` (orientation, colors, ...) except for its size, format,
+/// metadata and watermark.
+pub fn lqip(im: &DynamicImage, job: &ThumbnailJob) -> Result<String, Error> {
+    let tiny = ThumbnailJob {
+        width: LQIP_SIZE, height: LQIP_SIZE, scale: None, mode: ResizeMode::Fit, allow_upscale: false,
+        quality: LQIP_QUALITY, max_bytes: None, progressive: false, lossless: false, format: OutputFormat::Jpeg, preserve_metadata: false,
+        watermark: None, ..*job
+    };
+    let mut thumbnail = tiny.thumbnail(im);
+    resize::flatten(&mut thumbnail, job.background);
+    let blurred = DynamicImage::ImageRgba8(imageops::blur(&thumbnail, 1.0));
+    let mut encoded = Cursor::new(vec![]);
+    resize::write_image(&tiny, &blurred, &mut encoded)?;
+    Ok(format!("data:image/jpeg;base64,{}", base64(&encoded.into_inner())))
+}
+
+/// Encodes `data` in base64 (with padding)
+fn base64(data: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut encoded = String::with_capacity(data.len().div_ceil(3) * 4);
+    for chunk in data.chunks(3) {
+        let bytes = [chunk[0], *chunk.get(1).unwrap_or(&0), *chunk.get(2).unwrap_or(&0)];
+        let bits  = u32::from_be_bytes([0, bytes[0], bytes[1], bytes[2]]);
+        for i in 0..4 {
+            if i <= chunk.len() {
+                encoded.push(ALPHABET[(bits >> (18 - 6 * i) & 63) as usize] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+    encoded
+}
+
+/// The largest side of the image from which a blurhash is computed: the blurhash only holds a
+/// handful of components, computing them from the full size image would be a waste of time
+const BLURHASH_SIZE: u32 = 64;
+
+/// Computes the blurhash (see https://blurha.sh) of the image `im`, with the given number of 
+/// `components` along each axis. The image is oriented as described by the `job` (but not 
+/// resized) so that the blurhash looks like its thumbnails.
+pub fn blurhash(im: &DynamicImage, job: &ThumbnailJob, components: BlurhashComponents) -> String {
+    let im = job.transform(im);
+    let im = if im.width().max(im.height()) > BLURHASH_SIZE { im.thumbnail(BLURHASH_SIZE, BLURHASH_SIZE) } else { im.into_owned() };
+    let im = im.to_rgb8();
+    let (w, h) = im.dimensions();
+    let linear = im.pixels().map(|p| p.0.map(srgb_to_linear)).collect::<Vec<_>>();
+
+    let mut factors = vec![];
+    for j in 0..components.y {
+        for i in 0..components.x {
+            let normalisation = if i == 0 && j == 0 { 1.0 } else { 2.0 };
+            let mut factor = [0.0_f32; 3];
+            for y in 0..h {
+                let basis_y = (PI * (j * y) as f32 / h as f32).cos();
+                for x in 0..w {
+                    let basis = normalisation * basis_y * (PI * (i * x) as f32 / w as f32).cos();
+                    let pixel = linear[(y * w + x) as usize];
+                    for c in 0..3 {
+                        factor[c] += basis * pixel[c];
+                    }
+                }
+            }
+            factors.push(factor.map(|f| f / (w * h) as f32));
+        }
+    }
+
+    let mut hash = String::new();
+    base83(&mut hash, (components.x - 1) + (components.y - 1) * 9, 1);
+    let (dc, ac) = factors.split_first().unwrap();
+    let max_value = if ac.is_empty() {
+        base83(&mut hash, 0, 1);
+        1.0
+    } else {
+        let actual_max = ac.iter().flatten().fold(0.0_f32, |max, v| max.max(v.abs()));
+        let quantised = ((actual_max * 166.0 - 0.5).floor() as i32).clamp(0, 82) as u32;
+        base83(&mut hash, quantised, 1);
+        (quantised + 1) as f32 / 166.0
+    };
+    let [r, g, b] = dc.map(linear_to_srgb);
+    base83(&mut hash, (r << 16) + (g << 8) + b, 4);
+    for factor in ac {
+        let [r, g, b] = factor.map(|v| {
+            let v = v / max_value;
+            ((v.signum() * v.abs().sqrt() * 9.0 + 9.5).floor() as i32).clamp(0, 18) as u32
+        });
+        base83(&mut hash, r * 19 * 19 + g * 19 + b, 2);
+    }
+    hash
+}
+
+/// Converts an sRGB channel to linear light (in the range 0..=1)
+fn srgb_to_linear(c: u8) -> f32 {
+    let v = c as f32 / 255.0;
+    if v <= 0.04045 { v / 12.92 } else { ((v + 0.055) / 1.055).powf(2.4) }
+}
+
+/// Converts a linear light channel back to sRGB
+fn linear_to_srgb(v: f32) -> u32 {
+    let v = v.clamp(0.0, 1.0);
+    let c = if v <= 0.0031308 { v * 12.92 } else { 1.055 * v.powf(1.0 / 2.4) - 0.055 };
+    (c * 255.0 + 0.5) as u32
+}
+
+/// Appends the `length` base 83 digits of `value` to `hash`
+fn base83(hash: &mut String, value: u32, length: u32) {
+    const DIGITS: &[u8; 83] = b"0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz#$%*+,-.:;=?@[]^_{|}~";
+    for i in 1..=length {
+        let digit = (value / 83_u32.pow(length - i)) % 83;
+        hash.push(DIGITS[digit as usize] as char);
+    }
+}
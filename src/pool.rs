@@ -0,0 +1,32 @@
+use std::sync::Mutex;
+
+/// A pool of byte buffers which are reused from one image to the next rather than allocated 
+/// anew each time (see `resize_image_pooled`). This saves a lot of allocations when creating
+/// millions of small thumbnails. It can safely be shared between threads, but a pool owned by
+/// each thread avoids any contention.
+#[derive(Debug)]
+pub struct BufferPool {
+    /// The buffers which are currently not in use
+    buffers: Mutex<Vec<Vec<u8>>>,
+    /// The maximum number of buffers kept in the pool
+    capacity: usize,
+}
+impl BufferPool {
+    /// Creates an empty pool keeping at most `capacity` buffers
+    pub fn new(capacity: usize) -> Self {
+        Self { buffers: Mutex::new(vec![]), capacity }
+    }
+    /// Takes an empty buffer from the pool (or a new one when the pool is empty)
+    pub fn take(&self) -> Vec<u8> {
+        self.buffers.lock().unwrap().pop().unwrap_or_default()
+    }
+    /// Gives `buffer` back to the pool once it is no longer needed. It is dropped when the pool 
+    /// is full.
+    pub fn give(&self, mut buffer: Vec<u8>) {
+        buffer.clear();
+        let mut buffers = self.buffers.lock().unwrap();
+        if buffers.len() < self.capacity {
+            buffers.push(buffer);
+        }
+    }
+}
@@ -0,0 +1,723 @@
+use std::{borrow::Cow, collections::HashSet, ffi::{OsStr, OsString}, io, path::{PathBuf, Path}, fs, sync::{Arc, Condvar, Mutex, atomic::{AtomicBool, AtomicUsize, Ordering}}, time::SystemTime};
+
+use rayon::iter::{IntoParallelIterator, ParallelIterator};
+
+use crate::{Case, Error, Extensions, FilterType, Manifest, ZipSource, error::WithPath, Glob, http, manifest::{self, Hash}, NameTemplate, OnCollision, Overwrite, Stats, ThumbnailJob, naming, thumbignore::{self, IgnoreFile}};
+
+/// How the source folder is scanned and how the destination of each thumbnail is named
+#[derive(Debug, Clone)]
+pub struct Scan {
+    /// The extensions of the files to process
+    pub extension: Extensions,
+    /// When not empty, only the files whose name matches one of these patterns are processed
+    pub include: Vec<Glob>,
+    /// The files whose name matches one of these patterns are never processed
+    pub exclude: Vec<Glob>,
+    /// When the existing thumbnails are regenerated
+    pub overwrite: Overwrite,
+    /// The manifest of the destination folder, which tells which thumbnails are up to date when
+    /// they are only regenerated `IfChanged` (without it, they are all regenerated)
+    pub manifest: Option<&'static Manifest>,
+    /// The template used to name the thumbnails (if `None`, the default naming is used)
+    pub template: Option<NameTemplate>,
+    /// A suffix inserted before the extension of the default thumbnail names (e.g. '_thumb'). The
+    /// files whose name already ends with this suffix are never processed: this allows writing the
+    /// thumbnails next to their sources.
+    pub suffix: String,
+    /// A prefix inserted at the start of the default thumbnail names (e.g. 'thumb_'). Just like
+    /// with the `suffix`, the files whose name already starts with this prefix are never 
+    /// processed.
+    pub prefix: String,
+    /// Should the default thumbnail names keep the extension of their source (e.g. 'photo.tif.jpg'
+    /// rather than 'photo.jpg') ? This keeps 'a.tif' and 'a.png' from colliding.
+    pub keep_original_ext: bool,
+    /// Should the characters which are illegal in a file name on some file system be replaced
+    /// in the thumbnail names ? (see `--sanitize`)
+    pub sanitize: bool,
+    /// How the letters of the thumbnail names are cased
+    pub case: Case,
+    /// What to do when two images would produce a thumbnail with the same destination
+    pub on_collision: OnCollision,
+    /// How deep the subfolders are scanned (0 means only the source folder itself, `None` means 
+    /// no limit)
+    pub max_depth: Option<usize>,
+    /// Should the symbolic links to folders be followed ? (Otherwise they are ignored)
+    pub follow_symlinks: bool,
+    /// Should the folders be scanned in the order of the file names ? (Otherwise, the order is
+    /// the one in which the file system lists them)
+    pub sorted: bool,
+    /// Should all the thumbnails be written in the destination folder itself ? (Otherwise, the
+    /// subfolders of the source folder are mirrored in the destination folder.) The images of
+    /// different folders bearing the same name then collide (see `on_collision`).
+    pub flatten: bool,
+}
+impl Default for Scan {
+    fn default() -> Self {
+        Self { extension: Extensions::All, include: vec![], exclude: vec![], overwrite: Overwrite::Always, manifest: None, template: None, suffix: String::new(), prefix: String::new(), keep_original_ext: false, sanitize: false, case: Case::Keep, on_collision: OnCollision::Error, max_depth: None, follow_symlinks: false, sorted: false, flatten: false }
+    }
+}
+
+/// The name of the files naming the filter to use for the images of a folder
+pub(crate) const FILTER_FILE: &str = ".thumbfilter";
+
+/// An image to process, as listed by `prepare`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Task {
+    /// The source image
+    pub src: PathBuf,
+    /// The destination of its thumbnail for each job
+    pub dst: Vec<PathBuf>,
+    /// The filter to use instead of the filter of the jobs (as named by a `.thumbfilter` file)
+    pub filter: Option<FilterType>,
+}
+impl Task {
+    /// The `jobs` as they apply to this image, i.e. with the filter of this task (if any)
+    pub fn jobs<'a>(&self, jobs: &'a [ThumbnailJob]) -> Cow<'a, [ThumbnailJob]> {
+        match self.filter {
+            None         => Cow::Borrowed(jobs),
+            Some(filter) => Cow::Owned(jobs.iter().map(|job| ThumbnailJob { filter, ..*job }).collect()),
+        }
+    }
+}
+
+/// The state accumulated while walking the source folder
+struct Walk<'a> {
+    /// The images to process along with their destinations
+    list: Vec<Task>,
+    /// The destinations generated so far
+    generated: HashSet<PathBuf>,
+    /// The number of source images encountered so far
+    n: usize,
+    /// Counts the images which are skipped
+    stats: &'a Stats,
+    /// The modification time of all the source images, when they are not files of their own
+    /// (e.g. the entries of an archive)
+    modified: Option<SystemTime>,
+    /// The archive holding the source images, when they are not files of their own
+    source: Option<&'a ZipSource>,
+}
+
+/// The folder of `dst` where the thumbnails of the images of its `relative` subfolder go: that
+/// subfolder, unless the `scan` flattens the destination
+fn subfolder(dst: &Path, relative: impl AsRef<Path>, scan: &Scan) -> PathBuf {
+    if scan.flatten { dst.to_path_buf() } else { dst.join(relative) }
+}
+
+/// Tells whether the file at `path` is to be processed, as per its extension and the include/
+/// exclude patterns. The excludes win over the includes. A file bearing the thumbnail suffix (or
+/// prefix) is a thumbnail generated by a previous run and is never processed. The reason why a
+/// file is not selected is logged.
+fn selected(path: &Path, scan: &Scan) -> bool {
+    let extension = path.extension().map(|ext| scan.extension.matches(ext)).unwrap_or(false);
+    let name      = path.file_name().unwrap_or_default().to_string_lossy();
+    let stem      = path.file_stem().unwrap_or_default().to_string_lossy();
+    let included  = scan.include.is_empty() || scan.include.iter().any(|g| g.matches(&name));
+    let excluded  = scan.exclude.iter().any(|g| g.matches(&name));
+    let thumbnail = !scan.suffix.is_empty() && stem.ends_with(&scan.suffix)
+        || !scan.prefix.is_empty() && stem.starts_with(&scan.prefix);
+    let reason = match () {
+        _ if !extension => "its extension is not selected",
+        _ if !included  => "it matches no include pattern",
+        _ if excluded   => "it matches an exclude pattern",
+        _ if thumbnail  => "it bears the thumbnail suffix or prefix",
+        _               => return true,
+    };
+    log::debug!("ignoring {}: {reason}", path.display());
+    false
+}
+
+/// Tells whether the thumbnail `dst` needs to be (re)generated from the image `src`, which was 
+/// last `modified` at the given time (when it is not a file of its own) and whose content has
+/// the given `hash` (when the thumbnails are only regenerated if it has changed)
+fn needs_work(src: &Path, dst: &Path, overwrite: Overwrite, modified: Option<SystemTime>, manifest: Option<&Manifest>, hash: Option<&Hash>) -> Result<bool, Error> {
+    // checked first: a montage (or an archive) names its destinations below a file
+    if overwrite == Overwrite::Always {
+        return Ok(true);
+    }
+    if !dst.try_exists().with_path(dst)? {
+        return Ok(true);
+    }
+    match overwrite {
+        Overwrite::Always  => Ok(true),
+        Overwrite::Never   => Ok(false),
+        // a URL has no modification time: its existing thumbnails are deemed up to date
+        Overwrite::IfNewer if http::is_url(src) => Ok(false),
+        Overwrite::IfNewer => {
+            let src_time = match modified {
+                Some(time) => time,
+                None       => fs::metadata(src).and_then(|m| m.modified()).with_path(src)?,
+            };
+            let dst_time = fs::metadata(dst).and_then(|m| m.modified()).with_path(dst)?;
+            Ok(dst_time < src_time)
+        },
+        Overwrite::IfChanged => match (manifest, hash) {
+            (Some(manifest), Some(hash)) => Ok(!manifest.is_current(dst, hash)),
+            _                            => Ok(true),
+        },
+    }
+}
+
+/// Walks the `src` folder recursively and returns the source of every image needing a thumbnail
+/// together with its destinations (one per job) and the filter named by the nearest 
+/// `.thumbfilter` file, if any. This does not touch the disk: the
+/// destination folders only get created by `run`. The subfolders are scanned in parallel on the
+/// current rayon thread pool, but the images are listed in the same order as a sequential scan.
+/// 
+/// When a name template is used, it takes precedence over the default naming (including the
+/// size suffix appended when there are several jobs, the thumbnail `suffix` and `prefix`). The destination
+/// folder may be the source folder itself: a thumbnail which would overwrite its own source is
+/// reported as an `OverwritesSource` error. A destination folder lying inside the source folder
+/// is not scanned, so that the thumbnails of the previous runs are not processed again.
+/// 
+/// A folder holding a `.thumbfilter` file gets its images (and those of its subfolders) resized
+/// with the filter named in that file rather than with the filter of the jobs, e.g. 'nearest'
+/// for a folder of line art scans. The file holds the name of the filter on a line of its own
+/// (blank lines and lines starting with '#' are ignored); the file of an inner folder wins over
+/// the files of its parents.
+/// 
+/// All generated destinations are tracked so as to detect when two thumbnails would be written 
+/// to the same path (e.g. from 'a.tif' and 'a.png', or because the template lacks `{width}` 
+/// while there are several sizes). Such a collision is handled according to `on_collision`: it
+/// is either reported as a `NameCollision` error, or the image is skipped (with a warning), or
+/// the thumbnail is renamed by appending '_1', '_2', ... to its name.
+/// 
+/// The images which need no work (or which are skipped because of a collision) are counted in
+/// `stats`.
+pub fn prepare(src: &str, dst: &str, scan: &Scan, jobs: &[ThumbnailJob], stats: &Stats) -> Result<Vec<Task>, Error>{
+    let mut walk = Walk { list: vec![], generated: HashSet::new(), n: 0, stats, modified: None, source: None };
+    let output = fs::canonicalize(dst).ok();
+    for (path, folder, filter) in prepare_dir(Path::new(src), Path::new(dst), 0, scan, &Context { ancestors: &[], ignores: &[], filter: None, output: output.as_deref() })? {
+        add_image(path, &folder, filter, scan, jobs, &mut walk)?;
+    }
+    Ok(walk.list)
+}
+
+/// Same as `prepare`, but each image needing a thumbnail is handed to `sink` (along with its
+/// destinations) as soon as it is found, rather than once the whole folder has been scanned: 
+/// the images come in the same order as in the list of `prepare` (the subfolders are still
+/// scanned in parallel, see `stream_dir`). The scan stops as soon as `sink` returns false.
+/// 
+/// This allows the thumbnails to be created while the scan goes on (see `run_streaming`), which
+/// matters for the huge folders whose scan takes minutes.
+pub fn prepare_streaming(src: &str, dst: &str, scan: &Scan, jobs: &[ThumbnailJob], stats: &Stats, mut sink: impl FnMut(Task) -> bool) -> Result<(), Error>{
+    let mut walk = Walk { list: vec![], generated: HashSet::new(), n: 0, stats, modified: None, source: None };
+    let output = fs::canonicalize(dst).ok();
+    stream_dir(Path::new(src), Path::new(dst), scan, output, &mut |(path, folder, filter)| {
+        add_image(path, &folder, filter, scan, jobs, &mut walk)?;
+        Ok(walk.list.drain(..).all(&mut sink))
+    })
+}
+
+/// Same as `prepare` but for an explicit list of source `files` instead of the content of a 
+/// folder. The files are taken as they are: they are not filtered by extension nor by the 
+/// include/exclude patterns. The thumbnails of a file lying inside the `src` folder are written
+/// to the matching subfolder of `dst` (as `prepare` would do), those of any other file are 
+/// written in `dst` directly. The `.thumbfilter` files are not read.
+///
+/// The files may also be `http://` URLs (to be fetched by the `HttpSource` of the pipeline), whose
+/// thumbnails are written in `dst`. Having no modification time, a URL is deemed older than its
+/// existing thumbnails (see `Overwrite::IfNewer`), while its content is only hashed once fetched:
+/// its thumbnails are regenerated on every run with `Overwrite::IfChanged`.
+pub fn prepare_files(files: impl IntoIterator<Item = PathBuf>, src: &str, dst: &str, scan: &Scan, jobs: &[ThumbnailJob], stats: &Stats) -> Result<Vec<Task>, Error>{
+    let mut walk = Walk { list: vec![], generated: HashSet::new(), n: 0, stats, modified: None, source: None };
+    for path in files {
+        let folder = path.parent()
+            .and_then(|parent| parent.strip_prefix(src).ok())
+            .map(|relative| subfolder(Path::new(dst), relative, scan))
+            .unwrap_or_else(|| PathBuf::from(dst));
+        add_image(path, &folder, None, scan, jobs, &mut walk)?;
+    }
+    Ok(walk.list)
+}
+
+/// Same as `prepare` but the source images are the entries of the zip `archive` rather than the
+/// files of a folder. The entries are filtered like the files of a folder would be (by extension,
+/// include/exclude patterns and depth, but there are no `.thumbignore` nor `.thumbfilter` files) and their 
+/// thumbnails are written to the subfolders of `dst` matching their location in the archive. The
+/// entries are all deemed to be as recent as the archive itself.
+pub fn prepare_archive(archive: &ZipSource, dst: &str, scan: &Scan, jobs: &[ThumbnailJob], stats: &Stats) -> Result<Vec<Task>, Error>{
+    let modified = fs::metadata(archive.path()).and_then(|m| m.modified()).with_path(archive.path())?;
+    let mut walk = Walk { list: vec![], generated: HashSet::new(), n: 0, stats, modified: Some(modified), source: Some(archive) };
+    let mut files = archive.files().collect::<Vec<_>>();
+    if scan.sorted {
+        files.sort();
+    }
+    for path in files {
+        let inner  = path.strip_prefix(archive.path()).unwrap_or(&path);
+        let folder = inner.parent().unwrap_or(Path::new(""));
+        let depth  = folder.iter().count();
+        if scan.max_depth.map(|max| depth > max).unwrap_or(false) {
+            log::debug!("ignoring {}: too deep", path.display());
+            continue;
+        }
+        if selected(&path, scan) {
+            let folder = subfolder(Path::new(dst), folder, scan);
+            add_image(path, &folder, None, scan, jobs, &mut walk)?;
+        }
+    }
+    Ok(walk.list)
+}
+
+/// What the scan of a folder inherits from the scan of its parents
+struct Context<'a> {
+    /// The canonical paths of the folders leading to the folder
+    ancestors: &'a [PathBuf],
+    /// The `.thumbignore` files of these folders (outermost first)
+    ignores: &'a [Arc<IgnoreFile>],
+    /// The filter named by the innermost `.thumbfilter` file of these folders
+    filter: Option<FilterType>,
+    /// The canonical path of the destination folder, when it exists
+    output: Option<&'a Path>,
+}
+
+/// A file selected while scanning, along with the folder where its thumbnails go and the filter
+/// named by the `.thumbfilter` file of its folder (or of the nearest parent having one)
+type Candidate = (PathBuf, PathBuf, Option<FilterType>);
+
+/// An entry of a folder which is to be scanned further
+enum Entry {
+    /// A selected file
+    File(PathBuf),
+    /// A subfolder, along with its name
+    Folder(PathBuf, OsString),
+}
+
+/// A folder being scanned: the entries to scan and what its subfolders inherit from it
+struct Folder {
+    /// The canonical paths of the folders leading to the folder, the folder included
+    ancestors: Vec<PathBuf>,
+    /// The `.thumbignore` files of these folders (outermost first)
+    ignores: Vec<Arc<IgnoreFile>>,
+    /// The filter named by the nearest `.thumbfilter` file
+    filter: Option<FilterType>,
+    /// The files selected in the folder and the subfolders to scan, in order
+    entries: Vec<Entry>,
+}
+
+/// Opens the folder `src` (which lies `depth` levels below the source folder) and lists the files
+/// it selects and the subfolders to scan. The entries are checked in parallel (on the current
+/// rayon thread pool) but they always come in the order of the folder. When the scan is
+/// `sorted`, the entries of the folder are visited by order of their names: this way the images
+/// are listed (and numbered) the same way on every run.
+///
+/// A folder which is one of its own `ancestors` (because a symlink leads back to it) is not 
+/// scanned again, neither is the `output` folder (unless it is the source folder itself): `None`
+/// is returned for them.
+///
+/// When the folder holds a `.thumbignore` file, the files and subfolders matching its patterns
+/// are not scanned (see `IgnoreFile` for the syntax). Its patterns are relative to the folder and
+/// apply to all its subfolders, the patterns of the innermost files taking precedence. Likewise,
+/// the filter named by a `.thumbfilter` file applies to the folder and all its subfolders.
+fn open_dir(src: &Path, depth: usize, scan: &Scan, context: &Context) -> Result<Option<Folder>, Error> {
+    let canonical = fs::canonicalize(src).with_path(src)?;
+    if context.ancestors.contains(&canonical) {
+        log::debug!("ignoring {}: this folder is one of its own parents", src.display());
+        return Ok(None);
+    }
+    if depth > 0 && context.output == Some(canonical.as_path()) {
+        log::debug!("ignoring {}: this is the destination folder", src.display());
+        return Ok(None);
+    }
+    log::debug!("scanning {}", src.display());
+    let ancestors = context.ancestors.iter().cloned().chain(Some(canonical)).collect::<Vec<_>>();
+    let ignores   = context.ignores.iter().cloned().chain(IgnoreFile::read(src)?.map(Arc::new)).collect::<Vec<_>>();
+    let filter    = read_filter(src)?.or(context.filter);
+
+    let mut entries = std::fs::read_dir(src).and_then(|entries| entries.collect::<Result<Vec<_>, _>>()).with_path(src)?;
+    if scan.sorted {
+        entries.sort_by_key(|entry| entry.file_name());
+    }
+    let entries = entries.into_par_iter().map(|entry| {
+        let path = entry.path();
+        let is_dir = path.is_dir();
+        if thumbignore::ignored(&ignores, &path, is_dir) {
+            log::debug!("ignoring {}: it matches a .thumbignore pattern", path.display());
+            return Ok(None);
+        }
+        if is_dir {
+            let too_deep = scan.max_depth.map(|max| depth >= max).unwrap_or(false);
+            let ignored  = entry.file_type().with_path(&path)?.is_symlink() && !scan.follow_symlinks;
+            if too_deep || ignored {
+                log::debug!("ignoring {}: {}", path.display(), if too_deep { "too deep" } else { "symbolic link" });
+                return Ok(None);
+            }
+            Ok(Some(Entry::Folder(path, entry.file_name())))
+        } else if selected(&path, scan) {
+            Ok(Some(Entry::File(path)))
+        } else {
+            Ok(None)
+        }
+    }).collect::<Result<Vec<_>, Error>>()?;
+
+    Ok(Some(Folder { ancestors, ignores, filter, entries: entries.into_iter().flatten().collect() }))
+}
+
+/// Reads the `.thumbfilter` file of `folder`, if it has one, and returns the filter it names
+fn read_filter(folder: &Path) -> Result<Option<FilterType>, Error> {
+    let path = folder.join(FILTER_FILE);
+    let text = match fs::read_to_string(&path) {
+        Ok(text) => text,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e).with_path(&path),
+    };
+    let Some(name) = text.lines().map(str::trim).find(|l| !l.is_empty() && !l.starts_with('#')) else {
+        return Ok(None);
+    };
+    let filter = name.parse().map_err(|_| Error::InvalidFilterFile(path.clone(), name.to_string()))?;
+    log::debug!("resizing the images of {} with the '{name}' filter", folder.display());
+    Ok(Some(filter))
+}
+
+/// Scans the folder `src` (which lies `depth` levels below the source folder) and returns the
+/// files it selects, its subfolders included (see `open_dir`). The subfolders are scanned in
+/// parallel (on the current rayon thread pool) but the files always come in the order of a
+/// sequential scan.
+fn prepare_dir(src: &Path, dst: &Path, depth: usize, scan: &Scan, context: &Context) -> Result<Vec<Candidate>, Error>{
+    let Some(Folder { ancestors, ignores, filter, entries }) = open_dir(src, depth, scan, context)? else {
+        return Ok(vec![]);
+    };
+    let context = Context { ancestors: &ancestors, ignores: &ignores, filter, output: context.output };
+    let found = entries.into_par_iter().map(|entry| match entry {
+        Entry::File(path)         => Ok(vec![(path, dst.to_path_buf(), filter)]),
+        Entry::Folder(path, name) => prepare_dir(&path, &subfolder(dst, name, scan), depth + 1, scan, &context),
+    }).collect::<Result<Vec<_>, Error>>()?;
+
+    Ok(found.into_iter().flatten().collect())
+}
+
+/// Same as `prepare_dir` for the source folder `src` (whose canonical destination is `output`),
+/// but the files are handed to `found` one at a time, in the same order, as soon as they are 
+/// found. The scan stops as soon as `found` returns false.
+/// 
+/// The subfolders are scanned in parallel on the global rayon thread pool while the current 
+/// thread waits for them in order (see `Pending`). The scan is held back when `STREAM_AHEAD`
+/// files are waiting for `found`: the folders which are not scanned yet are then only scanned 
+/// once `found` reaches them.
+fn stream_dir(src: &Path, dst: &Path, scan: &Scan, output: Option<PathBuf>, found: &mut dyn FnMut(Candidate) -> Result<bool, Error>) -> Result<(), Error>{
+    let stream = Arc::new(Stream { scan: scan.clone(), output, stopped: AtomicBool::new(false), ahead: AtomicUsize::new(0) });
+    let root   = Pending::new(Subfolder { src: src.to_path_buf(), dst: dst.to_path_buf(), depth: 0, ancestors: vec![], ignores: vec![], filter: None });
+    root.start(&stream);
+    let drained = drain(&root, &stream, found);
+    stream.stopped.store(true, Ordering::Relaxed);
+    drained.map(|_| ())
+}
+
+/// Hands the files of the `pending` folder (its subfolders included) to `found`, in order, as 
+/// soon as they are scanned. False is returned as soon as `found` returns false.
+fn drain(pending: &Pending, stream: &Arc<Stream>, found: &mut dyn FnMut(Candidate) -> Result<bool, Error>) -> Result<bool, Error> {
+    for entry in pending.wait()? {
+        let more = match entry {
+            Streamed::File(candidate) => {
+                stream.ahead.fetch_sub(1, Ordering::Relaxed);
+                found(candidate)?
+            },
+            Streamed::Folder(pending) => {
+                pending.start(stream);
+                drain(&pending, stream, found)?
+            },
+        };
+        if !more {
+            return Ok(false);
+        }
+    }
+    Ok(true)
+}
+
+/// How many files found by `stream_dir` may be waiting to be handed over before the scan of the
+/// other folders is held back
+const STREAM_AHEAD: usize = 4096;
+
+/// What the folder scans of `stream_dir` share
+struct Stream {
+    /// How the folders are scanned
+    scan: Scan,
+    /// The canonical path of the destination folder, when it exists
+    output: Option<PathBuf>,
+    /// Set once the files are not needed anymore: the folders which are left are not scanned
+    stopped: AtomicBool,
+    /// The number of files which are found but not handed over yet
+    ahead: AtomicUsize,
+}
+
+/// Where to find a folder to scan and what it inherits from its parents (see `Context`)
+struct Subfolder {
+    src: PathBuf,
+    dst: PathBuf,
+    depth: usize,
+    ancestors: Vec<PathBuf>,
+    ignores: Vec<Arc<IgnoreFile>>,
+    filter: Option<FilterType>,
+}
+
+/// An entry of a folder scanned by `stream_dir`
+enum Streamed {
+    /// A selected file
+    File(Candidate),
+    /// A subfolder, whose scan may still be under way
+    Folder(Arc<Pending>),
+}
+
+/// A folder scanned by `stream_dir`. Its scan runs as a job of the rayon thread pool which 
+/// publishes the entries of the folder, once it is opened, for `drain` to wait on. Since the 
+/// jobs never wait for anything, the scan cannot deadlock, even on a single thread.
+struct Pending {
+    /// The folder to scan, until its scan is started
+    folder: Mutex<Option<Subfolder>>,
+    /// The entries of the folder, once it is opened
+    entries: Mutex<Option<Result<Vec<Streamed>, Error>>>,
+    /// Notified when the entries are published
+    opened: Condvar,
+}
+impl Pending {
+    fn new(folder: Subfolder) -> Arc<Self> {
+        Arc::new(Self { folder: Mutex::new(Some(folder)), entries: Mutex::new(None), opened: Condvar::new() })
+    }
+    /// Starts the scan of the folder, unless it is already started
+    fn start(self: &Arc<Self>, stream: &Arc<Stream>) {
+        if let Some(folder) = self.folder.lock().unwrap().take() {
+            let (pending, stream) = (self.clone(), stream.clone());
+            rayon::spawn(move || {
+                let entries = if stream.stopped.load(Ordering::Relaxed) { Ok(vec![]) } else { Self::open(folder, &stream) };
+                *pending.entries.lock().unwrap() = Some(entries);
+                pending.opened.notify_all();
+            });
+        }
+    }
+    /// Opens the `folder` and lists its entries. The scans of its subfolders are started right
+    /// away, unless the scan is held back.
+    fn open(folder: Subfolder, stream: &Arc<Stream>) -> Result<Vec<Streamed>, Error> {
+        let Subfolder { src, dst, depth, ancestors, ignores, filter } = folder;
+        let context = Context { ancestors: &ancestors, ignores: &ignores, filter, output: stream.output.as_deref() };
+        let Some(Folder { ancestors, ignores, filter, entries }) = open_dir(&src, depth, &stream.scan, &context)? else {
+            return Ok(vec![]);
+        };
+        let files = entries.iter().filter(|entry| matches!(entry, Entry::File(_))).count();
+        let eager = stream.ahead.fetch_add(files, Ordering::Relaxed) + files < STREAM_AHEAD;
+        Ok(entries.into_iter().map(|entry| match entry {
+            Entry::File(path)         => Streamed::File((path, dst.clone(), filter)),
+            Entry::Folder(path, name) => {
+                let dst = subfolder(&dst, name, &stream.scan);
+                let pending = Self::new(Subfolder { src: path, dst, depth: depth + 1, ancestors: ancestors.clone(), ignores: ignores.clone(), filter });
+                if eager {
+                    pending.start(stream);
+                }
+                Streamed::Folder(pending)
+            },
+        }).collect())
+    }
+    /// Waits for the folder to be opened and takes its entries
+    fn wait(&self) -> Result<Vec<Streamed>, Error> {
+        let mut entries = self.entries.lock().unwrap();
+        loop {
+            if let Some(entries) = entries.take() {
+                return entries;
+            }
+            entries = self.opened.wait(entries).unwrap();
+        }
+    }
+}
+
+/// Tells whether `dst` and `src` are the same file, however their paths are spelled (e.g. 
+/// '/photos/a.jpg' and './a.jpg' when '/photos' is the current folder)
+fn same_file(dst: &Path, src: &Path) -> bool {
+    let folder = |path: &Path| path.parent().map(|p| if p.as_os_str().is_empty() { Path::new(".") } else { p }).and_then(|p| fs::canonicalize(p).ok());
+    dst == src || (dst.file_name() == src.file_name() && folder(dst).is_some() && folder(dst) == folder(src))
+}
+
+/// Computes the destinations (one per job) of the image at `path` and adds it to the list of 
+/// images to process if needed, along with the `filter` overriding the one of the jobs
+fn add_image(path: PathBuf, dst: &Path, filter: Option<FilterType>, scan: &Scan, jobs: &[ThumbnailJob], walk: &mut Walk) -> Result<(), Error> {
+    walk.n += 1;
+    let fstem = path.file_stem().unwrap_or(OsStr::new("unk"));
+    let fstem = if scan.keep_original_ext { path.file_name().unwrap_or(fstem) } else { fstem };
+    let mut dstnames = vec![];
+    for job in jobs {
+        let name = match &scan.template {
+            None           => naming::default_name(fstem, &scan.prefix, &scan.suffix, job, jobs),
+            Some(template) => template.render(&path, job, walk.n),
+        };
+        let normalized = naming::normalize(&name, scan.sanitize, scan.case);
+        if normalized != name {
+            log::debug!("naming the thumbnail of {} '{}' rather than '{}'", path.display(), normalized.to_string_lossy(), name.to_string_lossy());
+        }
+        let dstname = dst.join(normalized);
+        if same_file(&dstname, &path) {
+            return Err(Error::OverwritesSource(path));
+        }
+        let dstname = match scan.on_collision {
+            _ if !walk.generated.contains(&dstname) => dstname,
+            OnCollision::Error  => return Err(Error::NameCollision(dstname)),
+            OnCollision::Skip   => {
+                log::warn!("skipping {}: {} is already generated from another image", path.display(), dstname.display());
+                dstnames.clear();
+                break;
+            },
+            OnCollision::Rename => (1..)
+                .map(|k| naming::with_suffix(&dstname, &format!("_{k}")))
+                .find(|renamed| !walk.generated.contains(renamed))
+                .unwrap(),
+        };
+        dstnames.push(dstname);
+    }
+    if dstnames.is_empty() {
+        walk.stats.skipped(&path, &dstnames);
+        return Ok(());
+    }
+    walk.generated.extend(dstnames.iter().cloned());
+
+    // the hash is needed to record the thumbnails in the manifest, even when none of them exists
+    let hash = match (scan.overwrite, scan.manifest) {
+        (Overwrite::IfChanged, Some(_)) if !http::is_url(&path) => Some(manifest::content_hash(&path, walk.source)?),
+        _                               => None,
+    };
+    let thumbnails = dstnames.iter().zip(jobs).map(|(dstname, job)| naming::frame_name(dstname, job, 0)).collect::<Vec<_>>();
+    let mut needed = false;
+    for thumbnail in thumbnails.iter() {
+        needed |= needs_work(&path, thumbnail, scan.overwrite, walk.modified, scan.manifest, hash.as_ref())?;
+    }
+    if needed {
+        log::debug!("selecting {}", path.display());
+        if let (Some(manifest), Some(hash)) = (scan.manifest, hash) {
+            manifest.expect(&path, hash, thumbnails);
+        }
+        walk.list.push(Task { src: path, dst: dstnames, filter });
+    } else {
+        log::debug!("skipping {}: its thumbnails are up to date", path.display());
+        walk.stats.skipped(&path, &dstnames);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{TempDir, job, write_png};
+
+    /// The source images listed by a scan of `src`, relative to it (sorted)
+    fn sources(list: &[Task], src: &Path) -> Vec<PathBuf> {
+        let mut sources = list.iter().map(|task| task.src.strip_prefix(src).unwrap().to_path_buf()).collect::<Vec<_>>();
+        sources.sort();
+        sources
+    }
+
+    #[test]
+    fn the_images_are_numbered_the_same_way_by_every_scan() {
+        let dir = TempDir::new("numbers");
+        let src = dir.path().join("src");
+        for folder in ["", "a", "b", "b/c"] {
+            for name in ["x.png", "y.png", "z.png"] {
+                write_png(&src.join(folder).join(name), 4, 4);
+            }
+        }
+        let (s, d) = (src.to_str().unwrap(), dir.path().join("dst"));
+        let d = d.to_str().unwrap();
+        let names = |sorted: bool| {
+            let scan = Scan { template: Some("{n}_{stem}.{ext}".parse().unwrap()), sorted, ..Scan::default() };
+            let list = prepare(s, d, &scan, &[job(2, 2)], &Stats::new()).unwrap();
+            let mut streamed = vec![];
+            prepare_streaming(s, d, &scan, &[job(2, 2)], &Stats::new(), |task| { streamed.push(task); true }).unwrap();
+            let names = list.into_iter().map(|task| (task.src, task.dst)).collect::<Vec<_>>();
+            assert_eq!(names, streamed.into_iter().map(|task| (task.src, task.dst)).collect::<Vec<_>>());
+            names
+        };
+        for sorted in [false, true] {
+            let first = names(sorted);
+            assert_eq!(first.len(), 12);
+            assert_eq!(first, names(sorted));
+            // the images are numbered in the order of the scan
+            let numbers = first.iter()
+                .map(|(_, dst)| dst[0].file_name().unwrap().to_str().unwrap().split('_').next().unwrap().parse::<usize>().unwrap())
+                .collect::<Vec<_>>();
+            assert_eq!(numbers, (1..=12).collect::<Vec<_>>());
+        }
+    }
+
+    #[test]
+    fn the_thumbnails_written_inside_the_source_folder_are_not_scanned_again() {
+        let dir = TempDir::new("nested");
+        let src = dir.path().join("src");
+        let dst = src.join("out");
+        write_png(&src.join("a.png"), 8, 8);
+        write_png(&src.join("sub").join("b.png"), 8, 8);
+        let (s, d) = (src.to_str().unwrap(), dst.to_str().unwrap());
+        let jobs = [ThumbnailJob { format: crate::OutputFormat::Png, ..job(4, 4) }];
+
+        // the first run creates the destination folder and fills it with images
+        let list = prepare(s, d, &Scan::default(), &jobs, &Stats::new()).unwrap();
+        crate::run(&jobs, &crate::Pipeline::default(), list, &crate::Progress::hidden(), &Stats::new()).unwrap();
+        assert!(dst.join("a.png").exists() && dst.join("sub").join("b.png").exists());
+
+        let listed = [Path::new("a.png"), &Path::new("sub").join("b.png")];
+        let list = prepare(s, d, &Scan::default(), &jobs, &Stats::new()).unwrap();
+        assert_eq!(sources(&list, &src), listed);
+        let mut streamed = vec![];
+        prepare_streaming(s, d, &Scan::default(), &jobs, &Stats::new(), |task| { streamed.push(task); true }).unwrap();
+        assert_eq!(sources(&streamed, &src), listed);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn a_symlink_to_its_own_folder_is_scanned_once() {
+        let dir = TempDir::new("loop");
+        let src = dir.path().join("src");
+        write_png(&src.join("a.png"), 8, 8);
+        write_png(&src.join("sub").join("b.png"), 8, 8);
+        std::os::unix::fs::symlink(&src, src.join("loop")).unwrap();
+        std::os::unix::fs::symlink(&src, src.join("sub").join("up")).unwrap();
+
+        let scan = Scan { follow_symlinks: true, ..Scan::default() };
+        let dst = dir.path().join("dst");
+        let (s, d) = (src.to_str().unwrap(), dst.to_str().unwrap());
+        let listed = [Path::new("a.png"), &Path::new("sub").join("b.png")];
+        let list = prepare(s, d, &scan, &[job(4, 4)], &Stats::new()).unwrap();
+        assert_eq!(sources(&list, &src), listed);
+        let mut streamed = vec![];
+        prepare_streaming(s, d, &scan, &[job(4, 4)], &Stats::new(), |task| { streamed.push(task); true }).unwrap();
+        assert_eq!(sources(&streamed, &src), listed);
+    }
+
+    #[test]
+    fn a_thumbnail_never_overwrites_its_source_whatever_the_spelling_of_the_paths() {
+        let dir = TempDir::new("spelling");
+        let src = dir.path().join("src");
+        write_png(&src.join("a.png"), 8, 8);
+        let jobs = [ThumbnailJob { format: crate::OutputFormat::Png, ..job(4, 4) }];
+        for dst in [src.clone(), src.join("."), src.join("..").join("src")] {
+            let scan = Scan { extension: "png".parse().unwrap(), ..Scan::default() };
+            let result = prepare(src.to_str().unwrap(), dst.to_str().unwrap(), &scan, &jobs, &Stats::new());
+            assert!(matches!(result, Err(Error::OverwritesSource(ref path)) if *path == src.join("a.png")), "{}", dst.display());
+        }
+    }
+
+    #[test]
+    fn the_streamed_scan_lists_the_images_in_order() {
+        let dir = TempDir::new("stream");
+        let src = dir.path().join("src");
+        // enough images for the scan of the subfolders to be held back
+        fs::create_dir_all(&src).unwrap();
+        for i in 0..=STREAM_AHEAD {
+            fs::write(src.join(format!("{i:05}.png")), b"").unwrap();
+        }
+        for folder in 0..20 {
+            for sub in ["", "a", "a/b"] {
+                let folder = src.join(format!("f{folder:02}")).join(sub);
+                fs::create_dir_all(&folder).unwrap();
+                fs::write(folder.join("x.png"), b"").unwrap();
+            }
+        }
+        let dst = dir.path().join("dst");
+        let (s, d) = (src.to_str().unwrap(), dst.to_str().unwrap());
+        let scan = Scan { sorted: true, ..Scan::default() };
+        let list = prepare(s, d, &scan, &[job(4, 4)], &Stats::new()).unwrap().into_iter().map(|task| task.src).collect::<Vec<_>>();
+        assert_eq!(list.len(), STREAM_AHEAD + 61);
+        assert_eq!(list.last(), Some(&src.join("f19").join("x.png")));
+
+        let mut streamed = vec![];
+        prepare_streaming(s, d, &scan, &[job(4, 4)], &Stats::new(), |task| { streamed.push(task.src); true }).unwrap();
+        assert_eq!(streamed, list);
+        // the scan stops as soon as the images are not needed anymore
+        let mut streamed = vec![];
+        prepare_streaming(s, d, &scan, &[job(4, 4)], &Stats::new(), |task| { streamed.push(task.src); streamed.len() < 10 }).unwrap();
+        assert_eq!(streamed, list[..10]);
+    }
+}
@@ -0,0 +1,83 @@
+use std::{io::{IsTerminal, Write}, sync::{atomic::{AtomicUsize, AtomicU64, Ordering}, Mutex}, time::{Duration, Instant}};
+
+/// Minimum delay between two redraws of the progress bar
+const REDRAW_DELAY: Duration = Duration::from_millis(100);
+/// Number of characters of the bar itself
+const BAR_WIDTH: usize = 30;
+
+/// A progress bar showing the number of processed images and an estimation of the remaining 
+/// time. It is drawn on stderr (only when stderr is a terminal) and can safely be incremented 
+/// from any thread.
+#[derive(Debug)]
+pub struct Progress {
+    /// Total number of images to process
+    total: AtomicUsize,
+    /// Number of images processed so far
+    done: AtomicUsize,
+    /// Is the bar drawn at all ?
+    visible: bool,
+    /// The moment when the processing started
+    start: Instant,
+    /// Time of the last redraw (in milliseconds since start)
+    last_draw: AtomicU64,
+    /// Serializes the redraws so that the lines don't get garbled
+    lock: Mutex<()>,
+}
+impl Progress {
+    /// Creates a progress bar for `total` images. The bar is only drawn when `visible` is true
+    /// and stderr is a terminal.
+    pub fn new(total: usize, visible: bool) -> Self {
+        Self {
+            total: AtomicUsize::new(total),
+            done: AtomicUsize::new(0),
+            visible: visible && std::io::stderr().is_terminal(),
+            start: Instant::now(),
+            last_draw: AtomicU64::new(0),
+            lock: Mutex::new(()),
+        }
+    }
+    /// Creates a progress bar which is never drawn
+    pub fn hidden() -> Self {
+        Self::new(0, false)
+    }
+    /// Records that there are `n` more images to process than expected (e.g. when the images are
+    /// processed while the folder is being scanned)
+    pub fn grow(&self, n: usize) {
+        self.total.fetch_add(n, Ordering::Relaxed);
+    }
+    /// Records that one more image has been processed
+    pub fn inc(&self) {
+        self.done.fetch_add(1, Ordering::Relaxed);
+        if self.visible {
+            let now  = self.start.elapsed().as_millis() as u64;
+            let last = self.last_draw.load(Ordering::Relaxed);
+            if now >= last + REDRAW_DELAY.as_millis() as u64 
+                && self.last_draw.compare_exchange(last, now, Ordering::Relaxed, Ordering::Relaxed).is_ok() {
+                self.draw();
+            }
+        }
+    }
+    /// Draws the final state of the bar and moves to the next line
+    pub fn finish(&self) {
+        if self.visible {
+            self.draw();
+            eprintln!();
+        }
+    }
+    /// Draws the bar: processed/total counts, a percentage and the ETA
+    fn draw(&self) {
+        let _guard = self.lock.lock();
+        let total   = self.total.load(Ordering::Relaxed);
+        let done    = self.done.load(Ordering::Relaxed).min(total);
+        let ratio   = if total == 0 { 1.0 } else { done as f64 / total as f64 };
+        let filled  = (ratio * BAR_WIDTH as f64) as usize;
+        let elapsed = self.start.elapsed().as_secs_f64();
+        let eta     = if done == 0 { 0 } else { (elapsed / done as f64 * (total - done) as f64) as u64 };
+        
+        let mut err = std::io::stderr().lock();
+        _ = write!(err, "\r[{}{}] {done}/{} ({:>3}%) ETA {:02}:{:02}:{:02}", 
+            "#".repeat(filled), " ".repeat(BAR_WIDTH - filled), total, (ratio * 100.0) as u32,
+            eta / 3600, (eta / 60) % 60, eta % 60);
+        _ = err.flush();
+    }
+}
@@ -0,0 +1,623 @@
+use std::{borrow::Cow, io::{Cursor, Seek, Write}};
+
+use image::{codecs::jpeg::{JpegEncoder, PixelDensity}, DynamicImage, ImageOutputFormat, RgbaImage};
+
+use crate::{BufferPool, Chroma, Color, Error, FilterType, Frame, ResizeMode, OutputFormat, Shape, Stage, Timings, Watermark, decode, color::ToSrgb, exif, jpeg, metadata::{self, Metadata}, stats::timed};
+
+/// The standard deviation (in pixels) of the blur of the unsharp mask sharpening the thumbnails:
+/// thumbnails are small, only their finest details need to be enhanced
+pub const SHARPEN_SIGMA: f32 = 0.8;
+
+/// The smallest difference (out of 255) between a pixel and its blurred version for the pixel to
+/// be sharpened: this keeps the noise of the flat areas from being enhanced
+pub const SHARPEN_THRESHOLD: i32 = 2;
+
+/// The lowest quality to which the thumbnails are degraded to fit in their size budget (see
+/// `ThumbnailJob::max_bytes`)
+pub const MIN_QUALITY: u8 = 10;
+
+/// The parameters telling how each thumbnail must be generated
+#[derive(Debug, Clone, Copy)]
+pub struct ThumbnailJob {
+    /// Width of the generated thumbnails (or of the bounding box in 'fit' mode). When it is 0, the
+    /// width of each thumbnail follows from its `height` and the aspect ratio of its image.
+    pub width: u32,
+    /// Height of the generated thumbnails (or of the bounding box in 'fit' mode). When it is 0, the
+    /// height of each thumbnail follows from its `width` and the aspect ratio of its image.
+    pub height: u32,
+    /// When set, each image is resized by this factor (e.g. 0.5 halves its sides) regardless of
+    /// the `width`, `height` and `mode` of the job
+    pub scale: Option<f32>,
+    /// The filter used to interpolate pixels
+    pub filter: FilterType,
+    /// The jpeg quality
+    pub quality: u8,
+    /// The largest size (in bytes) of the encoded thumbnails: the thumbnails of the lossy formats
+    /// which would be larger are encoded again with a lower quality (no lower than `MIN_QUALITY`)
+    /// until they fit. The other formats and the lossless thumbnails are left as they are. There
+    /// is no budget when `None`.
+    pub max_bytes: Option<u64>,
+    /// Should the jpeg thumbnails be progressive rather than baseline ? (Ignored by the other
+    /// formats.)
+    pub progressive: bool,
+    /// How the chroma of the jpeg thumbnails is subsampled (ignored by the other formats)
+    pub chroma: Chroma,
+    /// Should the thumbnails be encoded losslessly, ignoring the `quality` ? This selects the 
+    /// lossless encoder of webp, leaves the formats which are always lossless (png, bmp) 
+    /// untouched and is an error for those which have no lossless mode (jpeg, avif, gif).
+    pub lossless: bool,
+    /// The encoding of the generated thumbnails
+    pub format: OutputFormat,
+    /// How the image is fit into the width x height box
+    pub mode: ResizeMode,
+    /// May images smaller than the bounding box be scaled up (in 'fit' mode) ?
+    pub allow_upscale: bool,
+    /// Should the image be rotated/flipped upright according to its EXIF orientation ?
+    pub auto_orient: bool,
+    /// A rotation (clockwise, in degrees: 0, 90, 180 or 270) applied after the auto-orientation
+    pub rotate: u16,
+    /// Should the image be flipped horizontally (after the rotation) ?
+    pub flip_h: bool,
+    /// Should the image be flipped vertically (after the horizontal flip) ?
+    pub flip_v: bool,
+    /// Should the thumbnail be converted to shades of gray ?
+    pub grayscale: bool,
+    /// Added to each channel of the thumbnail (-255..=255, 0 leaves the thumbnail untouched)
+    pub brightness: i32,
+    /// Contrast adjustment of the thumbnail, in percents (-100..=100, 0 leaves the thumbnail
+    /// untouched, negative values decrease the contrast)
+    pub contrast: f32,
+    /// The amount of unsharp masking applied to the thumbnail right after the resize (0 leaves 
+    /// the thumbnail untouched, 1 adds the whole difference between the thumbnail and its blurred
+    /// version, see `SHARPEN_SIGMA` and `SHARPEN_THRESHOLD`)
+    pub sharpen: f32,
+    /// Should the ICC profile and the orientation of the source be copied to the thumbnail ?
+    /// (Only for JPEG and PNG outputs. By default, thumbnails carry no metadata at all.)
+    pub preserve_metadata: bool,
+    /// Should the thumbnail be converted to sRGB from the color space of the ICC profile embedded
+    /// in the source ? (Only the matrix/TRC profiles are understood, the others are ignored.)
+    pub to_srgb: bool,
+    /// The shape of the thumbnails: the pixels lying out of it are made transparent
+    pub shape: Shape,
+    /// The overlay (e.g. a logo) composited onto each thumbnail, if any
+    pub watermark: Option<&'static Watermark>,
+    /// The color over which transparent images are flattened when the output format has no alpha
+    /// channel (e.g. jpeg)
+    pub background: Color,
+    /// The frame(s) of animated (or multi-page) images to turn into thumbnails
+    pub frame: Frame,
+    /// The resolution (in dots per inch) recorded in the JPEG and PNG thumbnails, which tells the
+    /// applications their physical size (no resolution is recorded when `None`)
+    pub dpi: Option<u16>,
+    /// The largest number of pixels of the source images: the images having more pixels are not
+    /// decoded at all (no limit when `None`)
+    pub max_pixels: Option<u64>,
+    /// How many pixels (in each dimension) the size of a source image may differ from the size
+    /// of its thumbnail for the source to be copied as it is (see `is_copy`)
+    pub passthrough: u32,
+}
+impl ThumbnailJob {
+    /// Computes the dimensions to which an image of size `iw` x `ih` must be resized.
+    /// 
+    /// * In 'exact' mode, this is always `width` x `height`.
+    /// * In 'fit' mode, the image is scaled by the largest factor that makes it fit inside the box 
+    ///   (never more than 1 unless upscaling is allowed).
+    /// * In 'fill' mode, the image is scaled by the smallest factor that makes it cover the whole 
+    ///   box. The overflow is cropped afterwards (see `resize`).
+    /// 
+    /// Sizes are rounded to the nearest pixel and no side ever drops below 1px (nor below the box
+    /// size in 'fill' mode). When the job has a `scale`, both sides are simply multiplied by it.
+    /// When only one of `width` and `height` is given (the other one being 0), the image is scaled
+    /// to that side whatever the mode, keeping its aspect ratio (in 'fit' mode, it is still never
+    /// scaled up unless upscaling is allowed). When neither is given, the image keeps its size.
+    pub fn target_size(&self, iw: u32, ih: u32) -> (u32, u32) {
+        let scaled = |ratio: f64| (((iw as f64 * ratio).round() as u32).max(1), ((ih as f64 * ratio).round() as u32).max(1));
+        if let Some(scale) = self.scale {
+            return scaled(scale as f64);
+        }
+        let rw = self.width  as f64 / iw as f64;
+        let rh = self.height as f64 / ih as f64;
+        let single = match (self.width, self.height) {
+            (0, 0) => return (iw, ih),
+            (0, _) => Some(rh),
+            (_, 0) => Some(rw),
+            _      => None,
+        };
+        if let Some(ratio) = single {
+            let upscale = self.allow_upscale || !matches!(self.mode, ResizeMode::Fit);
+            return scaled(if upscale { ratio } else { ratio.min(1.0) });
+        }
+        let ratio = match self.mode {
+            ResizeMode::Exact => return (self.width, self.height),
+            ResizeMode::Fit  if self.allow_upscale => f64::min(rw, rh),
+            ResizeMode::Fit  => f64::min(rw, rh).min(1.0),
+            ResizeMode::Fill => f64::max(rw, rh),
+        };
+        let (w, h) = scaled(ratio);
+        match self.mode {
+            ResizeMode::Fill => (w.max(self.width), h.max(self.height)),
+            _                => (w, h)
+        }
+    }
+    /// Applies the explicit rotation and flips of this job to the (already auto-oriented) image, 
+    /// in this order: rotation, horizontal flip, vertical flip.
+    pub fn transform<'a>(&self, im: &'a DynamicImage) -> Cow<'a, DynamicImage> {
+        let mut im = match self.rotate {
+            90  => Cow::Owned(im.rotate90()),
+            180 => Cow::Owned(im.rotate180()),
+            270 => Cow::Owned(im.rotate270()),
+            _   => Cow::Borrowed(im),
+        };
+        if self.flip_h {
+            im = Cow::Owned(im.fliph());
+        }
+        if self.flip_v {
+            im = Cow::Owned(im.flipv());
+        }
+        im
+    }
+    /// Applies the color adjustments of this job to the thumbnail `im`: grayscale conversion, then
+    /// brightness, then contrast. This happens after the resize, where there are less pixels to 
+    /// adjust.
+    pub fn adjust(&self, im: RgbaImage) -> RgbaImage {
+        let mut im = im;
+        if self.grayscale {
+            im = DynamicImage::ImageLumaA8(image::imageops::grayscale_alpha(&im)).to_rgba8();
+        }
+        if self.brightness != 0 {
+            im = image::imageops::brighten(&im, self.brightness);
+        }
+        if self.contrast != 0.0 {
+            im = image::imageops::contrast(&im, self.contrast);
+        }
+        im
+    }
+    /// Sharpens the thumbnail `im` with an unsharp mask, as per the `sharpen` amount of this job:
+    /// the thumbnail is moved by that fraction of the way towards its fully sharpened version 
+    /// (amounts above 1 go past it).
+    pub fn sharpen(&self, im: RgbaImage) -> RgbaImage {
+        if self.sharpen == 0.0 {
+            return im;
+        }
+        let sharpened = image::imageops::unsharpen(&im, SHARPEN_SIGMA, SHARPEN_THRESHOLD);
+        let mut im = im;
+        for (p, s) in im.pixels_mut().zip(sharpened.pixels()) {
+            for c in 0..3 {
+                let v = p.0[c] as f32 + self.sharpen * (s.0[c] as f32 - p.0[c] as f32);
+                p.0[c] = v.round().clamp(0.0, 255.0) as u8;
+            }
+        }
+        im
+    }
+    /// Creates the thumbnail of the given (auto-oriented) image according to this job: it is
+    /// transformed, resized, sharpened, adjusted, watermarked and masked, in this order.
+    pub fn thumbnail(&self, im: &DynamicImage) -> RgbaImage {
+        self.thumbnail_in(im, None)
+    }
+    /// Same as `thumbnail`, but when the job converts the thumbnails to sRGB, the resized image
+    /// is converted from the color space of the source with `srgb` (before it is sharpened and
+    /// adjusted, so that e.g. its gray stays gray)
+    pub(crate) fn thumbnail_in(&self, im: &DynamicImage, srgb: Option<&ToSrgb>) -> RgbaImage {
+        let mut thumbnail = self.resize(&self.transform(im));
+        if let Some(srgb) = srgb.filter(|_| self.to_srgb) {
+            srgb.apply(&mut thumbnail);
+        }
+        let mut thumbnail = self.adjust(self.sharpen(thumbnail));
+        if let Some(watermark) = self.watermark {
+            watermark.apply(&mut thumbnail);
+        }
+        self.mask(thumbnail)
+    }
+    /// Makes the pixels of the thumbnail `im` which lie out of the shape of this job transparent.
+    /// The edge of the shape is antialiased: the alpha of each pixel is multiplied by the 
+    /// fraction of the pixel which lies inside the shape.
+    pub fn mask(&self, im: RgbaImage) -> RgbaImage {
+        if self.shape == Shape::Rectangle {
+            return im;
+        }
+        let (w, h) = (im.width() as f32, im.height() as f32);
+        // the (signed) distance from the point (x, y) to the edge of the shape, negative inside
+        let distance = |x: f32, y: f32| -> f32 {
+            let (dx, dy) = ((x - w / 2.0).abs(), (y - h / 2.0).abs());
+            match self.shape {
+                Shape::Rectangle => f32::NEG_INFINITY,
+                Shape::Circle    => dx.hypot(dy) - w.min(h) / 2.0,
+                Shape::Rounded(radius) => {
+                    let r  = radius.min(w.min(h) / 2.0);
+                    let qx = dx - (w / 2.0 - r);
+                    let qy = dy - (h / 2.0 - r);
+                    qx.max(0.0).hypot(qy.max(0.0)) + qx.max(qy).min(0.0) - r
+                },
+            }
+        };
+        let mut im = im;
+        for (x, y, pixel) in im.enumerate_pixels_mut() {
+            let inside = (0.5 - distance(x as f32 + 0.5, y as f32 + 0.5)).clamp(0.0, 1.0);
+            pixel[3] = (pixel[3] as f32 * inside).round() as u8;
+        }
+        im
+    }
+    /// Resizes the given image according to this job. 
+    /// 
+    /// In 'fill' mode, the scaled image is center-cropped to the box. When the overflow is odd,
+    /// the crop offset is rounded down which means the extra pixel is cut from the right (or bottom)
+    /// side of the image.
+    /// 
+    /// The box filter is only used to downscale images: it is replaced by the triangle filter when
+    /// the image is upscaled along any of its sides.
+    pub fn resize(&self, im: &DynamicImage) -> RgbaImage {
+        let (w, h) = self.target_size(im.width(), im.height());
+        let im = match self.filter {
+            FilterType::Box if w <= im.width() && h <= im.height() => area_resize(im, w, h),
+            filter => image::imageops::resize(im, w, h, filter.into()),
+        };
+        match self.mode {
+            ResizeMode::Fill if self.scale.is_none() && self.width > 0 && self.height > 0 => {
+                let x = (w - self.width)  / 2;
+                let y = (h - self.height) / 2;
+                image::imageops::crop_imm(&im, x, y, self.width, self.height).to_image()
+            },
+            _ => im
+        }
+    }
+    /// Tells whether the thumbnail of the image `input` would merely be a copy of it, in which
+    /// case the image need neither be decoded nor encoded: its bytes are written as they are. 
+    /// This happens when the image is already written in the output format (jpeg or png), when it
+    /// keeps its size (e.g. it fits in the box in 'fit' mode while upscaling is not allowed, or it
+    /// has the size of the box in 'fill' mode, so that nothing gets cropped), or nearly so as per
+    /// the `passthrough` tolerance of the job, and when the job transforms nothing (no rotation,
+    /// flip, color adjustment, sharpening, sRGB conversion, resolution, progressive encoding,
+    /// chroma subsampling, shape, watermark, nor orientation to apply), when the image fits in the
+    /// size budget of the job and when it carries no metadata (EXIF, XMP nor ICC profile) unless
+    /// the job preserves them. The quality of the job is then ignored and the copy keeps all the
+    /// metadata of the source (not only its ICC profile and orientation).
+    pub fn is_copy(&self, input: &[u8]) -> bool {
+        let format = match image::guess_format(input) {
+            Ok(image::ImageFormat::Jpeg) => OutputFormat::Jpeg,
+            Ok(image::ImageFormat::Png)  => OutputFormat::Png,
+            _                            => return false,
+        };
+        let untouched = self.rotate == 0 && !self.flip_h && !self.flip_v && !self.grayscale 
+            && self.brightness == 0 && self.contrast == 0.0 && self.sharpen == 0.0 && !self.to_srgb
+            && self.dpi.is_none() && !self.progressive && self.chroma == Chroma::Full && self.frame == Frame::Index(0)
+            && self.shape == Shape::Rectangle && self.watermark.is_none()
+            && self.max_bytes.is_none_or(|max| input.len() as u64 <= max)
+            // a copy would carry all the metadata of the source over to the thumbnail
+            && (self.preserve_metadata || !metadata::has_metadata(input));
+        let upright = !self.auto_orient || matches!(exif::orientation(input), None | Some(1));
+        untouched && upright && format == self.format 
+            && decode::dimensions(input).is_some_and(|(w, h)| {
+                let (tw, th) = self.target_size(w, h);
+                // in 'fill' mode, the thumbnail is the box itself: an image which would have to be
+                // cropped to fit it is never copied
+                let crops = matches!(self.mode, ResizeMode::Fill) && self.scale.is_none() && self.width > 0 && self.height > 0;
+                let cropped = crops && (tw, th) != (self.width, self.height);
+                !cropped && w.abs_diff(tw) <= self.passthrough && h.abs_diff(th) <= self.passthrough
+            })
+    }
+}
+
+/// Resizes `im` to `w` x `h` (which must not be larger) by averaging, for each pixel of the 
+/// result, all the source pixels it covers (weighted by the fraction of each pixel it covers).
+/// This is a separable filter: the rows are averaged first, then the columns. The pixels are
+/// read straight from the buffer of an RGB or RGBA image (the other ones are converted to RGBA
+/// first).
+fn area_resize(im: &DynamicImage, w: u32, h: u32) -> RgbaImage {
+    if w == 0 || h == 0 {
+        return RgbaImage::new(w, h);
+    }
+    let (iw, ih) = (im.width(), im.height());
+    let converted;
+    let (raw, channels) = match im {
+        DynamicImage::ImageRgba8(im) => (im.as_raw(), 4),
+        DynamicImage::ImageRgb8(im)  => (im.as_raw(), 3),
+        im => {
+            converted = im.to_rgba8();
+            (converted.as_raw(), 4)
+        },
+    };
+    // the pixels of an RGB image are opaque
+    let alpha = if channels == 3 { 255.0 } else { 0.0 };
+    let columns = coverage(iw, w);
+    let mut rows = vec![[0.0, 0.0, 0.0, alpha]; w as usize * ih as usize];
+    for (row, acc) in raw.chunks_exact(iw as usize * channels).zip(rows.chunks_exact_mut(w as usize)) {
+        for (acc, weights) in acc.iter_mut().zip(&columns) {
+            // the source pixels covered by a resized pixel are contiguous
+            let start = weights.first().map_or(0, |&(sx, _)| sx as usize * channels);
+            for (pixel, &(_, weight)) in row[start..].chunks_exact(channels).zip(weights) {
+                for (a, &c) in acc.iter_mut().zip(pixel) {
+                    *a += c as f32 * weight;
+                }
+            }
+        }
+    }
+    let mut out = vec![0; w as usize * h as usize * 4];
+    let mut acc = vec![[0.0_f32; 4]; w as usize];
+    for (out, weights) in out.chunks_exact_mut(w as usize * 4).zip(coverage(ih, h)) {
+        acc.fill([0.0; 4]);
+        for &(sy, weight) in &weights {
+            for (acc, row) in acc.iter_mut().zip(rows.chunks_exact(w as usize).nth(sy as usize).unwrap_or_default()) {
+                for (a, c) in acc.iter_mut().zip(row) {
+                    *a += c * weight;
+                }
+            }
+        }
+        for (pixel, acc) in out.chunks_exact_mut(4).zip(&acc) {
+            for (p, a) in pixel.iter_mut().zip(acc) {
+                *p = a.round().clamp(0.0, 255.0) as u8;
+            }
+        }
+    }
+    RgbaImage::from_raw(w, h, out).expect("the buffer holds w x h pixels")
+}
+
+/// For each of the `n` pixels of a resized side of `size` pixels, the source pixels it covers 
+/// along with their weights (which sum up to 1)
+fn coverage(size: u32, n: u32) -> Vec<Vec<(u32, f32)>> {
+    let scale = size as f64 / n as f64;
+    (0..n)
+        .map(|i| {
+            let (start, end) = (i as f64 * scale, (i + 1) as f64 * scale);
+            (start.floor() as u32..(end.ceil() as u32).min(size))
+                .map(|s| (s, (end.min(s as f64 + 1.0) - start.max(s as f64)) / scale))
+                .filter(|&(_, weight)| weight > 0.0)
+                .map(|(s, weight)| (s, weight as f32))
+                .collect()
+        })
+        .collect()
+}
+
+/// Encodes `im` into `output` in the format of the `job`, along with the resolution of the job
+/// (only for the JPEG and PNG formats)
+pub(crate) fn write_image<W: Write + Seek>(job: &ThumbnailJob, im: &DynamicImage, output: &mut W) -> Result<(), Error> {
+    if job.lossless && !job.format.has_lossless_mode() {
+        return Err(Error::NoLosslessMode(job.format.extension()));
+    }
+    match job.max_bytes {
+        Some(max) if job.format.is_lossy() && !job.lossless => write_within(job, im, max, output),
+        _ => write_with_quality(job, im, output),
+    }
+}
+
+/// Encodes `im` with the highest quality (no higher than that of the `job`) at which it takes no
+/// more than `max` bytes, which is found by a binary search. When even `MIN_QUALITY` takes more
+/// than that, the thumbnail is encoded at `MIN_QUALITY` anyway.
+fn write_within<W: Write + Seek>(job: &ThumbnailJob, im: &DynamicImage, max: u64, output: &mut W) -> Result<(), Error> {
+    let encode = |quality: u8| -> Result<Vec<u8>, Error> {
+        let mut encoded = Cursor::new(vec![]);
+        write_with_quality(&ThumbnailJob { quality, ..*job }, im, &mut encoded)?;
+        Ok(encoded.into_inner())
+    };
+    let mut best = encode(job.quality)?;
+    let (mut lo, mut hi) = (MIN_QUALITY.min(job.quality), job.quality);
+    if best.len() as u64 > max {
+        best = encode(lo)?;
+        // invariant: `lo` fits in the budget (or is the floor) and `hi` does not
+        while best.len() as u64 <= max && hi - lo > 1 {
+            let mid = lo + (hi - lo) / 2;
+            let encoded = encode(mid)?;
+            if encoded.len() as u64 <= max {
+                (lo, best) = (mid, encoded);
+            } else {
+                hi = mid;
+            }
+        }
+        if best.len() as u64 > max {
+            log::warn!("the thumbnail takes {} bytes at the lowest quality, more than the {max} bytes allowed", best.len());
+        }
+        log::trace!("encoded with quality {lo} to fit in {max} bytes");
+    }
+    output.write_all(&best)?;
+    Ok(())
+}
+
+/// Encodes `im` as described by the `job`, regardless of its size budget
+fn write_with_quality<W: Write + Seek>(job: &ThumbnailJob, im: &DynamicImage, output: &mut W) -> Result<(), Error> {
+    match (job.format, job.dpi) {
+        (OutputFormat::Jpeg, dpi) if job.progressive || job.chroma != Chroma::Full => Ok(jpeg::encode(&im.to_rgb8(), job.quality, dpi, job.chroma, job.progressive, output)?),
+        (OutputFormat::Jpeg, Some(dpi)) => {
+            let mut encoder = JpegEncoder::new_with_quality(output, job.quality);
+            encoder.set_pixel_density(PixelDensity::dpi(dpi));
+            Ok(encoder.encode(im.as_bytes(), im.width(), im.height(), im.color())?)
+        },
+        (OutputFormat::Png, Some(dpi)) => {
+            // the density chunk is inserted in the encoded thumbnail, which must thus be buffered
+            let mut encoded = Cursor::new(vec![]);
+            im.write_to(&mut encoded, ImageOutputFormat::Png)?;
+            output.write_all(&metadata::with_png_density(encoded.into_inner(), dpi))?;
+            Ok(())
+        },
+        (format, _) => Ok(im.write_to(output, format.image_output_format(job.quality))?),
+    }
+}
+
+/// Composites the (possibly transparent) image `im` over the given `background` color, which 
+/// leaves it fully opaque
+pub(crate) fn flatten(im: &mut RgbaImage, Color(background): Color) {
+    for pixel in im.pixels_mut() {
+        let alpha = pixel[3] as u32;
+        for (c, bg) in pixel.0.iter_mut().zip(background) {
+            *c = ((*c as u32 * alpha + bg as u32 * (255 - alpha) + 127) / 255) as u8;
+        }
+        pixel[3] = 255;
+    }
+}
+
+/// The `thumbnail` ready to be encoded as per the `job`: flattened over the background color of
+/// the job when the output format has no alpha channel
+fn flattened(job: &ThumbnailJob, mut thumbnail: RgbaImage) -> DynamicImage {
+    if !job.format.keeps_alpha() {
+        flatten(&mut thumbnail, job.background);
+    }
+    DynamicImage::ImageRgba8(thumbnail)
+}
+
+/// Creates the thumbnail of an image which is already decoded and encodes it into `output`. 
+/// This is the resize step of `resize_image` without the decoding: the image is taken as it is,
+/// which means it is neither oriented as per its EXIF data nor converted to sRGB, and the
+/// thumbnail carries no metadata (the image has none).
+pub fn resize_decoded<W: Write + Seek>(im: &DynamicImage, output: &mut W, job: &ThumbnailJob) -> Result<(), Error>
+{
+    write_image(job, &flattened(job, job.thumbnail(im)), output)
+}
+
+/// Resizes *one* image and encodes the thumbnail into `output`
+pub fn resize_image(input: &[u8], output: &mut Cursor<Vec<u8>>, job: &ThumbnailJob) -> Result<(), Error>
+{  
+    resize_image_into(input, output, job)
+}
+
+/// Resizes *one* image and encodes the thumbnail straight into `output` (e.g. the destination
+/// file) rather than into an intermediate buffer. The jobs asking for all the frames of the image
+/// only get the thumbnail of its first frame.
+pub fn resize_image_into<W: Write + Seek>(input: &[u8], output: &mut W, job: &ThumbnailJob) -> Result<(), Error>
+{
+    if job.is_copy(input) {
+        output.write_all(input)?;
+        return Ok(());
+    }
+    let jobs = std::slice::from_ref(job);
+    let sources = Sources::new(input, jobs, None)?;
+    match sources.frames(job).first() {
+        Some(im) => sources.encode_into(job, im, output),
+        None     => Err(Error::Decode("the image has no frame".to_string())),
+    }
+}
+
+/// Same as `resize_image` but the thumbnail is encoded into a buffer taken from the `pool`. That
+/// buffer should be given back to the pool once the thumbnail has been written.
+pub fn resize_image_pooled(input: &[u8], job: &ThumbnailJob, pool: &BufferPool) -> Result<Vec<u8>, Error>
+{
+    let mut output = Cursor::new(pool.take());
+    resize_image_into(input, &mut output, job)?;
+    Ok(output.into_inner())
+}
+
+/// Decodes *one* image and creates one encoded thumbnail per job. This is much faster than calling
+/// `resize_image` once per job since the (costly) decoding only happens once. The jobs asking 
+/// for all the frames of the image only get the thumbnail of its first frame 
+/// (see `resize_image_frames`).
+pub fn resize_image_all(input: &[u8], jobs: &[ThumbnailJob]) -> Result<Vec<Vec<u8>>, Error>
+{
+    let thumbnails = resize_image_frames(input, jobs)?;
+    Ok(thumbnails.into_iter().map(|frames| frames.into_iter().next().unwrap_or_default()).collect())
+}
+
+/// Decodes *one* image and creates the encoded thumbnails of the frames selected by each job: 
+/// the result holds the thumbnails of each job in the order of the frames. Unless a job asks
+/// for all the frames, it only gets one thumbnail. The frames are decoded only once for all the
+/// jobs asking for the same ones. An image having more pixels than allowed by any of the jobs 
+/// is rejected with a `TooManyPixels` error before it is decoded.
+pub fn resize_image_frames(input: &[u8], jobs: &[ThumbnailJob]) -> Result<Vec<Vec<Vec<u8>>>, Error>
+{
+    let decoded = jobs.iter().filter(|job| !job.is_copy(input)).copied().collect::<Vec<_>>();
+    let sources = Sources::new(input, &decoded, None)?;
+    jobs.iter()
+        .map(|job| {
+            if job.is_copy(input) {
+                return Ok(vec![input.to_vec()]);
+            }
+            sources.frames(job).iter()
+                .map(|im| {
+                    let mut output = Cursor::new(vec![]);
+                    sources.encode_into(job, im, &mut output)?;
+                    Ok(output.into_inner())
+                })
+                .collect()
+        })
+        .collect()
+}
+
+/// The decoded frames of *one* source image, ready to be turned into thumbnails by a set of jobs 
+pub(crate) struct Sources {
+    /// The EXIF orientation of the source image
+    orientation: Option<u16>,
+    /// The metadata of the source image (when some job preserves them or converts to sRGB)
+    metadata: Metadata,
+    /// The conversion from the color space of the source to sRGB (when some job asks for it and
+    /// the profile of the source is understood)
+    srgb: Option<ToSrgb>,
+    /// The decoded frames, along with their upright version when some job needs them
+    frames: Vec<(Frame, Vec<DynamicImage>, Vec<DynamicImage>)>,
+    /// Where the time spent decoding, resizing and encoding is recorded (if anywhere)
+    timings: Option<&'static Timings>,
+}
+impl Sources {
+    /// Decodes the frames of the image `input` needed by the `jobs`. An image having more pixels
+    /// than allowed by any of the jobs is rejected with a `TooManyPixels` error before it is 
+    /// decoded. The time spent in each stage is recorded in the `timings`, if any.
+    pub(crate) fn new(input: &[u8], jobs: &[ThumbnailJob], timings: Option<&'static Timings>) -> Result<Self, Error> {
+        timed(timings, Stage::Decode, || Self::load(input, jobs, timings))
+    }
+    /// Does the job of `new`
+    fn load(input: &[u8], jobs: &[ThumbnailJob], timings: Option<&'static Timings>) -> Result<Self, Error> {
+        if let Some(max_pixels) = jobs.iter().filter_map(|j| j.max_pixels).min() {
+            decode::check_size(input, max_pixels)?;
+        }
+        let orientation = exif::orientation(input);
+        let metadata = if jobs.iter().any(|j| j.preserve_metadata || j.to_srgb) { Metadata::read(input) } else { Metadata::default() };
+        let srgb = metadata.icc.as_deref().filter(|_| jobs.iter().any(|j| j.to_srgb)).and_then(ToSrgb::new);
+
+        let mut frames: Vec<(Frame, Vec<DynamicImage>, Vec<DynamicImage>)> = vec![];
+        for job in jobs {
+            if frames.iter().any(|(frame, _, _)| *frame == job.frame) {
+                continue;
+            }
+            let decoded = decode::decode_frames(input, job.frame)?;
+            let upright = orientation
+                .filter(|_| jobs.iter().any(|j| j.auto_orient && j.frame == job.frame))
+                .map(|orientation| decoded.iter().map(|im| exif::apply_orientation(im, orientation)).collect())
+                .unwrap_or_default();
+            frames.push((job.frame, decoded, upright));
+        }
+        Ok(Self { orientation, metadata, srgb, frames, timings })
+    }
+    /// The frames `job` is to turn into thumbnails (upright if the job auto-orients the images)
+    pub(crate) fn frames(&self, job: &ThumbnailJob) -> &[DynamicImage] {
+        let (_, frames, upright) = self.frames.iter().find(|(frame, _, _)| *frame == job.frame).unwrap();
+        if job.auto_orient && !upright.is_empty() { upright } else { frames }
+    }
+    /// Creates the thumbnail of `im` (one of the frames of this source) as described by `job`
+    pub(crate) fn thumbnail(&self, job: &ThumbnailJob, im: &DynamicImage) -> RgbaImage {
+        timed(self.timings, Stage::Resize, || job.thumbnail_in(im, self.srgb.as_ref()))
+    }
+    /// Creates the thumbnail of `im` as described by `job` and encodes it into `output`, along 
+    /// with the source metadata if the job preserves them. A thumbnail converted to sRGB does not
+    /// carry the profile of its source.
+    pub(crate) fn encode_into<W: Write + Seek>(&self, job: &ThumbnailJob, im: &DynamicImage, output: &mut W) -> Result<(), Error> {
+        let thumbnail = flattened(job, self.thumbnail(job, im));
+        timed(self.timings, Stage::Encode, || self.encode(job, &thumbnail, output))
+    }
+    /// Encodes the `thumbnail` into `output` (see `encode_into`)
+    fn encode<W: Write + Seek>(&self, job: &ThumbnailJob, thumbnail: &DynamicImage, output: &mut W) -> Result<(), Error> {
+        if !job.preserve_metadata {
+            return write_image(job, thumbnail, output);
+        }
+        // the metadata are inserted in the encoded thumbnail, which must thus be buffered
+        let mut encoded = Cursor::new(vec![]);
+        write_image(job, thumbnail, &mut encoded)?;
+        // an upright thumbnail must not be rotated a second time by the viewers
+        let orientation = if job.auto_orient { self.orientation.map(|_| 1) } else { self.orientation };
+        let icc = if job.to_srgb && self.srgb.is_some() { None } else { self.metadata.icc.clone() };
+        let metadata = Metadata { orientation, icc };
+        output.write_all(&metadata.embed(encoded.into_inner(), job.format))?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn the_box_filter_averages_the_pixels_it_covers() {
+        let rgb = image::RgbImage::from_fn(6, 4, |x, y| image::Rgb([(x * 40) as u8, (y * 60) as u8, if (x + y) % 2 == 0 { 255 } else { 0 }]));
+        let im = area_resize(&DynamicImage::ImageRgb8(rgb.clone()), 3, 2);
+        assert_eq!(im.get_pixel(0, 0).0, [20, 30, 128, 255]);
+        assert_eq!(im.get_pixel(2, 1).0, [180, 150, 128, 255]);
+        // the rgb, rgba and other buffers give the same result
+        assert_eq!(im, area_resize(&DynamicImage::ImageRgba8(DynamicImage::ImageRgb8(rgb.clone()).to_rgba8()), 3, 2));
+        let gray = DynamicImage::ImageLuma8(DynamicImage::ImageRgb8(rgb).to_luma8());
+        assert_eq!(area_resize(&gray, 4, 3), area_resize(&DynamicImage::ImageRgba8(gray.to_rgba8()), 4, 3));
+    }
+}
@@ -0,0 +1,285 @@
+use std::{collections::HashMap, fmt::Display, io::{self, Write}, path::{Path, PathBuf}, sync::{atomic::{AtomicU64, AtomicUsize, Ordering}, Mutex}, time::{Duration, Instant}};
+
+use crate::{Error, OutputInfo, stream::Sink};
+
+/// Counters summarizing a whole run. They can safely be updated from any thread.
+///
+/// When created `with_report`, the outcome of each source image is recorded as well, so that a
+/// detailed report can be written at the end of the run.
+#[derive(Debug)]
+pub struct Stats {
+    /// The moment when the run started
+    start: Instant,
+    /// Number of source images whose thumbnails have been generated
+    processed: AtomicUsize,
+    /// Number of source images which did not need to be processed (e.g. up to date thumbnails)
+    skipped: AtomicUsize,
+    /// Number of source images which could not be processed
+    failed: AtomicUsize,
+    /// Total size of the source images which have been read (in bytes)
+    read: AtomicU64,
+    /// Total size of the thumbnails which have been written (in bytes)
+    written: AtomicU64,
+    /// The outcome of each source image (only when a report is wanted)
+    entries: Option<Mutex<Vec<Entry>>>,
+    /// The placeholders of the source images (only when a report is wanted)
+    placeholders: Option<Mutex<Vec<Placeholder>>>,
+    /// Where the outcome of each source image is sent as it is recorded (see `run_stream`)
+    sink: Option<Sink>,
+}
+
+/// The outcome of one source image
+#[derive(Debug)]
+struct Entry {
+    /// Path to the source image
+    src: PathBuf,
+    /// Paths to its thumbnails
+    dst: Vec<PathBuf>,
+    /// Either of 'ok', 'skipped' or 'failed'
+    status: &'static str,
+    /// What went wrong (for the failed images only)
+    error: Option<String>,
+    /// Size of the source image (in bytes, 0 when it has not been read)
+    read: u64,
+    /// Total size of the thumbnails (in bytes)
+    written: u64,
+}
+
+/// The placeholder of one source image
+#[derive(Debug)]
+struct Placeholder {
+    /// Path to the source image
+    src: PathBuf,
+    /// The kind of placeholder (e.g. 'lqip'), which is its key in the report
+    kind: &'static str,
+    /// The placeholder itself
+    value: String,
+}
+
+impl Default for Stats {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+impl Stats {
+    /// Creates empty counters. The wall-clock time is measured from this moment on.
+    pub fn new() -> Self {
+        Self {
+            start: Instant::now(),
+            processed: AtomicUsize::new(0),
+            skipped: AtomicUsize::new(0),
+            failed: AtomicUsize::new(0),
+            read: AtomicU64::new(0),
+            written: AtomicU64::new(0),
+            entries: None,
+            placeholders: None,
+            sink: None,
+        }
+    }
+    /// Creates empty counters which also record the outcome of each source image (see
+    /// `write_report`)
+    pub fn with_report() -> Self {
+        Self { entries: Some(Mutex::new(vec![])), placeholders: Some(Mutex::new(vec![])), ..Self::new() }
+    }
+    /// Creates empty counters which also send the outcome of each source image to the `sink`.
+    /// The failures are sent as `Failed` errors carrying the message of the original error.
+    pub(crate) fn with_sink(sink: Sink) -> Self {
+        Self { sink: Some(sink), ..Self::new() }
+    }
+    /// Records that the thumbnails `dst` of the source image `src` have been generated. The
+    /// source image was `read` bytes long and its thumbnails amount to `written` bytes.
+    pub fn processed(&self, src: &Path, dst: &[PathBuf], read: u64, written: u64) {
+        self.processed.fetch_add(1, Ordering::Relaxed);
+        self.read.fetch_add(read, Ordering::Relaxed);
+        self.written.fetch_add(written, Ordering::Relaxed);
+        self.record(src, dst, "ok", None, read, written);
+        self.send(|| Ok(OutputInfo { src: src.to_path_buf(), dst: dst.to_vec(), skipped: false, read, written }));
+    }
+    /// Records that the source image `src` did not need to be processed
+    pub fn skipped(&self, src: &Path, dst: &[PathBuf]) {
+        self.skipped.fetch_add(1, Ordering::Relaxed);
+        self.record(src, dst, "skipped", None, 0, 0);
+        self.send(|| Ok(OutputInfo { src: src.to_path_buf(), dst: dst.to_vec(), skipped: true, read: 0, written: 0 }));
+    }
+    /// Records that the source image `src` could not be processed because of `error`
+    pub fn failed(&self, src: &Path, dst: &[PathBuf], error: &Error) {
+        self.failed.fetch_add(1, Ordering::Relaxed);
+        self.record(src, dst, "failed", Some(error.to_string()), 0, 0);
+        self.send(|| Err(Error::Failed(src.to_path_buf(), error.to_string())));
+    }
+    /// The number of source images which did not need to be processed so far
+    pub fn skipped_count(&self) -> usize {
+        self.skipped.load(Ordering::Relaxed)
+    }
+    /// Records the placeholder of the source image `src` (e.g. its 'lqip'), which is added to its
+    /// entry in the report. This is a no-op unless the stats were created `with_report`.
+    pub fn placeholder(&self, src: &Path, kind: &'static str, value: String) {
+        if let Some(placeholders) = &self.placeholders {
+            placeholders.lock().unwrap().push(Placeholder { src: src.to_path_buf(), kind, value });
+        }
+    }
+    /// Sends the outcome of one source image to the sink, if any. The outcomes are dropped once 
+    /// the receiving end of the sink is gone.
+    fn send(&self, outcome: impl FnOnce() -> Result<OutputInfo, Error>) {
+        if let Some(sink) = &self.sink {
+            _ = sink.try_send(outcome());
+        }
+    }
+    /// Records the outcome of one source image when a report is wanted
+    fn record(&self, src: &Path, dst: &[PathBuf], status: &'static str, error: Option<String>, read: u64, written: u64) {
+        if let Some(entries) = &self.entries {
+            let entry = Entry { src: src.to_path_buf(), dst: dst.to_vec(), status, error, read, written };
+            entries.lock().unwrap().push(entry);
+        }
+    }
+    /// Writes a JSON report of the run to `out`. The report is an object holding the counters
+    /// ('processed', 'skipped', 'failed', 'read' and 'written' bytes, 'seconds') and a 'files'
+    /// array giving the outcome of each source image, sorted by path:
+    ///
+    /// ```json
+    /// {"src": "a/b.tif", "dst": ["out/a/b.jpg"], "status": "ok", "error": null, "read": 1234, "written": 56}
+    /// ```
+    ///
+    /// The status is either of 'ok', 'skipped' or 'failed'; the error is only given for the
+    /// failed images. The placeholders recorded for an image (see `placeholder`) are added to its
+    /// entry, keyed by their kind (e.g. `"lqip": "data:image/jpeg;base64,..."`). The 'files' 
+    /// array is empty unless the stats were created `with_report`.
+    pub fn write_report(&self, out: &mut impl Write) -> io::Result<()> {
+        writeln!(out, "{{")?;
+        writeln!(out, "  \"processed\": {},", self.processed.load(Ordering::Relaxed))?;
+        writeln!(out, "  \"skipped\": {},", self.skipped.load(Ordering::Relaxed))?;
+        writeln!(out, "  \"failed\": {},", self.failed.load(Ordering::Relaxed))?;
+        writeln!(out, "  \"read\": {},", self.read.load(Ordering::Relaxed))?;
+        writeln!(out, "  \"written\": {},", self.written.load(Ordering::Relaxed))?;
+        writeln!(out, "  \"seconds\": {:.3},", self.start.elapsed().as_secs_f64())?;
+        write!(out, "  \"files\": [")?;
+        if let (Some(entries), Some(placeholders)) = (&self.entries, &self.placeholders) {
+            let placeholders = placeholders.lock().unwrap();
+            let mut by_src = HashMap::<&Path, Vec<_>>::new();
+            for p in placeholders.iter() {
+                by_src.entry(&p.src).or_default().push(format!(", \"{}\": {}", p.kind, json_string(&p.value)));
+            }
+            let mut entries = entries.lock().unwrap();
+            entries.sort_by(|a, b| a.src.cmp(&b.src));
+            for (i, e) in entries.iter().enumerate() {
+                let dst   = e.dst.iter().map(|d| json_string(&d.to_string_lossy())).collect::<Vec<_>>().join(", ");
+                let error = e.error.as_deref().map(json_string).unwrap_or_else(|| "null".to_string());
+                let extra = by_src.get(e.src.as_path()).map(|p| p.concat()).unwrap_or_default();
+                write!(out, "{}\n    {{\"src\": {}, \"dst\": [{dst}], \"status\": \"{}\", \"error\": {error}, \"read\": {}, \"written\": {}{extra}}}",
+                    if i == 0 { "" } else { "," }, json_string(&e.src.to_string_lossy()), e.status, e.read, e.written)?;
+            }
+            if !entries.is_empty() {
+                write!(out, "\n  ")?;
+            }
+        }
+        writeln!(out, "]")?;
+        writeln!(out, "}}")
+    }
+}
+
+/// The stages of the creation of a thumbnail whose duration is measured by the `Timings`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stage {
+    /// Decoding the source image (and reading its metadata)
+    Decode,
+    /// Resizing the decoded image, along with the other transformations of the job (rotation,
+    /// color adjustments, sharpening, ...)
+    Resize,
+    /// Encoding the thumbnail (and writing it, when it is encoded straight into its file)
+    Encode,
+}
+
+/// The time spent in each stage of the creation of the thumbnails, cumulated over all the 
+/// threads. They can safely be updated from any thread.
+#[derive(Debug, Default)]
+pub struct Timings {
+    /// The time spent decoding the source images (in nanoseconds)
+    decode: AtomicU64,
+    /// The time spent resizing them (in nanoseconds)
+    resize: AtomicU64,
+    /// The time spent encoding the thumbnails (in nanoseconds)
+    encode: AtomicU64,
+}
+impl Timings {
+    /// Creates empty timings
+    pub const fn new() -> Self {
+        Self { decode: AtomicU64::new(0), resize: AtomicU64::new(0), encode: AtomicU64::new(0) }
+    }
+    /// Adds `elapsed` to the time spent in `stage`
+    pub fn record(&self, stage: Stage, elapsed: Duration) {
+        let counter = match stage {
+            Stage::Decode => &self.decode,
+            Stage::Resize => &self.resize,
+            Stage::Encode => &self.encode,
+        };
+        counter.fetch_add(elapsed.as_nanos() as u64, Ordering::Relaxed);
+    }
+    /// The total time spent in `stage`
+    pub fn total(&self, stage: Stage) -> Duration {
+        let counter = match stage {
+            Stage::Decode => &self.decode,
+            Stage::Resize => &self.resize,
+            Stage::Encode => &self.encode,
+        };
+        Duration::from_nanos(counter.load(Ordering::Relaxed))
+    }
+}
+
+/// Performs `f` and records the time it took in the `stage` of the `timings` (if any)
+pub(crate) fn timed<T>(timings: Option<&Timings>, stage: Stage, f: impl FnOnce() -> T) -> T {
+    let Some(timings) = timings else {
+        return f();
+    };
+    let start = Instant::now();
+    let outcome = f();
+    timings.record(stage, start.elapsed());
+    outcome
+}
+
+/// Quotes `s` as a JSON string
+fn json_string(s: &str) -> String {
+    let mut quoted = String::with_capacity(s.len() + 2);
+    quoted.push('"');
+    for c in s.chars() {
+        match c {
+            '"'  => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            c if (c as u32) < 0x20 => quoted.push_str(&format!("\\u{:04x}", c as u32)),
+            c    => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+impl Display for Stats {
+    /// A one-line summary, e.g. '12 processed, 3 skipped, 1 failed, 10.2 MiB read, 0.3 MiB written in 1.52s'
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        const MIB: f64 = 1024.0 * 1024.0;
+        write!(f, "{} processed, {} skipped, {} failed, {:.1} MiB read, {:.1} MiB written in {:.2}s",
+            self.processed.load(Ordering::Relaxed),
+            self.skipped.load(Ordering::Relaxed),
+            self.failed.load(Ordering::Relaxed),
+            self.read.load(Ordering::Relaxed) as f64 / MIB,
+            self.written.load(Ordering::Relaxed) as f64 / MIB,
+            self.start.elapsed().as_secs_f64())
+    }
+}
+
+impl Display for Timings {
+    /// A one-line breakdown, e.g. 'decode 12.31s (61%), resize 4.02s (20%), encode 3.85s (19%)'
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let stages = [("decode", Stage::Decode), ("resize", Stage::Resize), ("encode", Stage::Encode)];
+        let all = stages.iter().map(|(_, stage)| self.total(*stage).as_secs_f64()).sum::<f64>().max(f64::MIN_POSITIVE);
+        let stages = stages.iter()
+            .map(|(name, stage)| {
+                let total = self.total(*stage).as_secs_f64();
+                format!("{name} {total:.2}s ({:.0}%)", 100.0 * total / all)
+            })
+            .collect::<Vec<_>>();
+        write!(f, "{}", stages.join(", "))
+    }
+}
@@ -0,0 +1,47 @@
+//! Running the pipeline in the background and reporting the outcome of each image as a stream,
+//! for the async programs (e.g. a web backend showing the progress of a run live).
+
+use std::{path::PathBuf, thread};
+
+use smol::{channel::Sender, stream::Stream};
+
+use crate::{Error, Pipeline, Progress, Stats, Task, ThumbnailJob, run};
+
+/// The outcome of one source image which has been dealt with, as reported by `run_stream`
+#[derive(Debug, Clone)]
+pub struct OutputInfo {
+    /// Path to the source image
+    pub src: PathBuf,
+    /// Paths to its thumbnails (one per job)
+    pub dst: Vec<PathBuf>,
+    /// Did the image not need to be processed (e.g. its size is out of the range of the pipeline)?
+    pub skipped: bool,
+    /// Size of the source image (in bytes, 0 when it has been skipped)
+    pub read: u64,
+    /// Total size of the thumbnails (in bytes, 0 when the image has been skipped)
+    pub written: u64,
+}
+
+/// Where the outcome of each image is sent (see `Stats::with_sink`)
+pub(crate) type Sink = Sender<Result<OutputInfo, Error>>;
+
+/// Same as `run`, but the thumbnails are created on a thread of its own and the outcome of each
+/// image is yielded by the returned stream as soon as the image has been dealt with. The stream
+/// is a plain channel: it can be polled from any executor (tokio, async-std, smol, ...) and the
+/// caller need not run one of smol since the pipeline drives its own tasks.
+///
+/// The images which cannot be processed are yielded as `Failed` errors, which name the image
+/// (and tell what went wrong). An error aborting the whole run (e.g. an output format whose
+/// encoder is missing from this build, or the first failure when the pipeline fails fast) is the
+/// last item of the stream. The stream ends once the run is over, dropping it does not stop the
+/// run (raise the `interrupt` flag of the pipeline for that).
+pub fn run_stream(jobs: Vec<ThumbnailJob>, pipeline: Pipeline, list: Vec<Task>) -> impl Stream<Item = Result<OutputInfo, Error>> + Send + Unpin {
+    let (sink, outcomes) = smol::channel::unbounded();
+    thread::spawn(move || {
+        let stats = Stats::with_sink(sink.clone());
+        if let Err(error) = run(&jobs, &pipeline, list, &Progress::hidden(), &stats) {
+            _ = sink.try_send(Err(error));
+        }
+    });
+    outcomes
+}
@@ -0,0 +1,55 @@
+//! Helpers shared by the unit tests of the crate
+
+use std::{fs, path::{Path, PathBuf}, sync::atomic::{AtomicUsize, Ordering}};
+
+use image::{ImageOutputFormat, Rgb, RgbImage};
+
+use crate::{Color, FilterType, Frame, OutputFormat, ResizeMode, Shape, ThumbnailJob};
+
+/// A folder of the system temporary directory which is removed (with its content) when dropped
+pub(crate) struct TempDir(PathBuf);
+impl TempDir {
+    /// Creates an empty folder whose name is unique to this run of the tests
+    pub fn new(name: &str) -> Self {
+        static COUNT: AtomicUsize = AtomicUsize::new(0);
+        let n = COUNT.fetch_add(1, Ordering::Relaxed);
+        let path = std::env::temp_dir().join(format!("thumbnails-{name}-{}-{n}", std::process::id()));
+        _ = fs::remove_dir_all(&path);
+        fs::create_dir_all(&path).unwrap();
+        Self(path)
+    }
+    /// The path of the folder
+    pub fn path(&self) -> &Path {
+        &self.0
+    }
+}
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+/// A job making `width` x `height` jpeg thumbnails with no other transformation
+pub(crate) fn job(width: u32, height: u32) -> ThumbnailJob {
+    ThumbnailJob {
+        width, height, scale: None,
+        filter: FilterType::Triangle,
+        quality: 80, max_bytes: None, progressive: false, chroma: Default::default(), lossless: false, format: OutputFormat::Jpeg,
+        mode: ResizeMode::Exact, allow_upscale: false,
+        auto_orient: false, rotate: 0, flip_h: false, flip_v: false,
+        grayscale: false, brightness: 0, contrast: 0.0, sharpen: 0.0,
+        preserve_metadata: false, to_srgb: false,
+        shape: Shape::default(), watermark: None, background: Color::default(), frame: Frame::default(), dpi: None,
+        max_pixels: None, passthrough: 0,
+    }
+}
+
+/// Writes a `width` x `height` png image (a color gradient) to `path`, creating its folder
+pub(crate) fn write_png(path: &Path, width: u32, height: u32) {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).unwrap();
+    }
+    let im = RgbImage::from_fn(width, height, |x, y| Rgb([(x * 255 / width) as u8, (y * 255 / height) as u8, 128]));
+    let mut out = fs::File::create(path).unwrap();
+    im.write_to(&mut out, ImageOutputFormat::Png).unwrap();
+}
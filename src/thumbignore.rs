@@ -0,0 +1,105 @@
+//! The `.thumbignore` files, which list the files and folders that must not be processed (in the
+//! spirit of `.gitignore`).
+
+use std::{fs, io, path::{Path, PathBuf}, sync::Arc};
+
+use crate::{Error, Glob, error::WithPath};
+
+/// The name of the files listing the patterns to ignore
+pub(crate) const IGNORE_FILE: &str = ".thumbignore";
+
+/// The patterns of the `.thumbignore` file of one folder. Each line of the file holds one glob
+/// pattern (blank lines and lines starting with '#' are ignored):
+///
+/// * a pattern without any '/' matches the names of the files and folders at any depth below
+///   the folder (e.g. `*.raw`),
+/// * a pattern holding a '/' matches their path relative to the folder, one glob per component;
+///   a `**` component matches any number of components (e.g. `/drafts/*.png`, `a/**/old`),
+/// * a pattern ending with '/' only matches folders (e.g. `tmp/`),
+/// * a pattern starting with '!' re-includes what an earlier pattern has ignored.
+///
+/// When several patterns match, the last one wins; the patterns of the innermost folders come last.
+#[derive(Debug, Clone)]
+pub(crate) struct IgnoreFile {
+    /// The folder holding the file
+    folder: PathBuf,
+    /// The patterns, in the order of the file
+    rules: Vec<Rule>,
+}
+
+/// One line of an ignore file
+#[derive(Debug, Clone)]
+struct Rule {
+    /// The globs matching each component of the path (only one for unanchored patterns)
+    globs: Vec<Option<Glob>>,
+    /// Is the pattern matched against the path relative to the folder (rather than the name) ?
+    anchored: bool,
+    /// Does the pattern only match folders ?
+    folders_only: bool,
+    /// Does the pattern re-include what it matches ?
+    negated: bool,
+}
+
+impl IgnoreFile {
+    /// Reads the ignore file of `folder`, if it has one
+    pub(crate) fn read(folder: &Path) -> Result<Option<Self>, Error> {
+        let path = folder.join(IGNORE_FILE);
+        let text = match fs::read_to_string(&path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e).with_path(&path),
+        };
+        let mut rules = vec![];
+        for line in text.lines().map(str::trim).filter(|l| !l.is_empty() && !l.starts_with('#')) {
+            let (negated, line) = match line.strip_prefix('!') {
+                Some(line) => (true, line),
+                None       => (false, line),
+            };
+            let (folders_only, line) = match line.strip_suffix('/') {
+                Some(line) => (true, line),
+                None       => (false, line),
+            };
+            let anchored = line.contains('/');
+            let globs = line.trim_start_matches('/').split('/')
+                .map(|component| if component == "**" { Ok(None) } else { component.parse().map(Some) })
+                .collect::<Result<Vec<_>, _>>()?;
+            rules.push(Rule { globs, anchored, folders_only, negated });
+        }
+        log::debug!("read {} pattern(s) from {}", rules.len(), path.display());
+        Ok(Some(Self { folder: folder.to_path_buf(), rules }))
+    }
+}
+
+/// Tells whether `path` (a folder when `is_folder` is set) is ignored by any of the `files`,
+/// the outermost coming first
+pub(crate) fn ignored(files: &[Arc<IgnoreFile>], path: &Path, is_folder: bool) -> bool {
+    let mut ignored = false;
+    for file in files {
+        let Ok(relative) = path.strip_prefix(&file.folder) else {
+            continue;
+        };
+        let components = relative.iter().map(|c| c.to_string_lossy()).collect::<Vec<_>>();
+        let components = components.iter().map(|c| c.as_ref()).collect::<Vec<_>>();
+        for rule in file.rules.iter().filter(|r| is_folder || !r.folders_only) {
+            let matched = if rule.anchored {
+                matches(&rule.globs, &components)
+            } else {
+                components.last().map(|name| matches(&rule.globs, &[name])).unwrap_or(false)
+            };
+            if matched {
+                ignored = !rule.negated;
+            }
+        }
+    }
+    ignored
+}
+
+/// Tells whether the path `components` match the `globs` (`None` standing for `**`)
+fn matches(globs: &[Option<Glob>], components: &[&str]) -> bool {
+    match (globs.split_first(), components.split_first()) {
+        (None, None)                            => true,
+        (Some((None, globs)), _)                => (0..=components.len()).any(|skip| matches(globs, &components[skip..])),
+        (Some((Some(glob), globs)), Some((c, components))) => glob.matches(c) && matches(globs, components),
+        _                                       => false,
+    }
+}
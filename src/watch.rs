@@ -0,0 +1,73 @@
+//! Watching the source folder and thumbnailing the images as they land in it.
+
+use std::{collections::HashMap, fs, path::PathBuf, thread, time::{Duration, Instant, SystemTime}};
+
+use crate::{Error, Failure, Pipeline, Progress, Scan, Stats, ThumbnailJob, pipeline::interrupted, prepare, run};
+
+/// How often the interrupt flag is checked while waiting for the next scan
+const TICK: Duration = Duration::from_millis(100);
+
+/// The state of a source file when it was seen: its size and modification time
+type Seen = (u64, Option<SystemTime>);
+
+/// Processes the images of the `src` folder (as `prepare` and `run` would do), then keeps
+/// watching the folder and processes the images which are created or modified afterwards, until
+/// the `pipeline` is interrupted. The images are selected by the same `scan` options (extensions,
+/// patterns, overwrite policy, ...) as the first run.
+///
+/// The folder is scanned again every `interval`. This debounces the changes: an image is only
+/// processed once its size and modification time did not change between two scans, so that a
+/// file which is still being written (or copied) is not processed until it is complete. An image
+/// is processed again when it changes after it has been processed, as long as the overwrite policy
+/// allows its thumbnails to be regenerated. The images which cannot be processed are only retried
+/// when they change.
+///
+/// Only the first scan counts the images which need no work in the `stats`, the later scans
+/// would count them over and over again.
+pub fn watch(src: &str, dst: &str, scan: &Scan, jobs: &[ThumbnailJob], interval: Duration, pipeline: &Pipeline, stats: &Stats) -> Result<Vec<Failure>, Error> {
+    let state = |path: &PathBuf| -> Seen {
+        let metadata = fs::metadata(path).ok();
+        (metadata.as_ref().map(|m| m.len()).unwrap_or_default(), metadata.and_then(|m| m.modified().ok()))
+    };
+    let mut processed = HashMap::<PathBuf, Seen>::new();
+    let mut pending   = HashMap::<PathBuf, Seen>::new();
+    let mut failures  = vec![];
+
+    let mut list = prepare(src, dst, scan, jobs, stats)?;
+    loop {
+        if !list.is_empty() {
+            log::debug!("processing {} image(s)", list.len());
+            let seen = list.iter().map(|task| (task.src.clone(), state(&task.src))).collect::<Vec<_>>();
+            failures.extend(run(jobs, pipeline, list, &Progress::hidden(), stats)?);
+            processed.extend(seen);
+        }
+        if interrupted(pipeline.interrupt) {
+            return Ok(failures);
+        }
+
+        let next = Instant::now() + interval;
+        while Instant::now() < next {
+            thread::sleep(TICK.min(next.saturating_duration_since(Instant::now())));
+            if interrupted(pipeline.interrupt) {
+                return Ok(failures);
+            }
+        }
+
+        let mut settled = vec![];
+        let mut changing = HashMap::new();
+        for task in prepare(src, dst, scan, jobs, &Stats::new())? {
+            let now = state(&task.src);
+            if processed.get(&task.src) == Some(&now) {
+                continue;
+            }
+            if pending.get(&task.src) == Some(&now) {
+                settled.push(task);
+            } else {
+                log::trace!("waiting for {} to settle", task.src.display());
+                changing.insert(task.src, now);
+            }
+        }
+        pending = changing;
+        list = settled;
+    }
+}
@@ -0,0 +1,69 @@
+//! The watermarks: a small overlay (e.g. a logo) composited onto each thumbnail.
+
+use std::{fs, path::Path};
+
+use image::{imageops, RgbaImage};
+
+use crate::{Error, WatermarkPosition, decode, error::WithPath};
+
+/// The space left between the watermark and the edges of the thumbnail, as a fraction of the 
+/// smallest side of the thumbnail
+const MARGIN: f32 = 0.02;
+
+/// An image composited onto each thumbnail, scaled relative to the size of the thumbnail
+#[derive(Debug, Clone)]
+pub struct Watermark {
+    /// The overlay itself, at its own size
+    image: RgbaImage,
+    /// Where the overlay is placed on the thumbnails
+    position: WatermarkPosition,
+    /// The opacity of the overlay (0 leaves the thumbnails untouched, 1 keeps the overlay as is)
+    opacity: f32,
+    /// The largest fraction of the width and height of the thumbnail the overlay may cover
+    size: f32,
+}
+impl Watermark {
+    /// Reads the overlay from the image at `path` (in any of the formats that can be decoded,
+    /// a png with an alpha channel being the most likely)
+    pub fn open(path: &Path, position: WatermarkPosition, opacity: f32, size: f32) -> Result<Self, Error> {
+        let input = fs::read(path).with_path(path)?;
+        let image = decode::decode(&input)
+            .map_err(|error| Error::InvalidWatermark(path.to_path_buf(), error.to_string()))?
+            .to_rgba8();
+        Ok(Self::new(image, position, opacity, size))
+    }
+    /// Creates a watermark from the overlay `image`
+    pub fn new(image: RgbaImage, position: WatermarkPosition, opacity: f32, size: f32) -> Self {
+        Self { image, position, opacity, size }
+    }
+    /// Composites the overlay onto the thumbnail `im`. The overlay is scaled (preserving its
+    /// aspect ratio) to fit in `size` times the width and height of the thumbnail, then blended
+    /// with the `opacity` of the watermark.
+    pub fn apply(&self, im: &mut RgbaImage) {
+        let (w, h) = im.dimensions();
+        let (ow, oh) = self.image.dimensions();
+        let ratio = f32::min(w as f32 * self.size / ow as f32, h as f32 * self.size / oh as f32);
+        let ow = ((ow as f32 * ratio).round() as u32).clamp(1, w);
+        let oh = ((oh as f32 * ratio).round() as u32).clamp(1, h);
+        let overlay = imageops::resize(&self.image, ow, oh, imageops::FilterType::CatmullRom);
+
+        let margin = (w.min(h) as f32 * MARGIN).round() as u32;
+        let (left, right)  = (margin.min(w - ow), w.saturating_sub(ow + margin));
+        let (top,  bottom) = (margin.min(h - oh), h.saturating_sub(oh + margin));
+        let (x, y) = match self.position {
+            WatermarkPosition::TopLeft     => (left, top),
+            WatermarkPosition::TopRight    => (right, top),
+            WatermarkPosition::BottomLeft  => (left, bottom),
+            WatermarkPosition::BottomRight => (right, bottom),
+            WatermarkPosition::Center      => ((w - ow) / 2, (h - oh) / 2),
+        };
+        for (ox, oy, o) in overlay.enumerate_pixels() {
+            let pixel = im.get_pixel_mut(x + ox, y + oy);
+            let alpha = o[3] as f32 / 255.0 * self.opacity;
+            for c in 0..3 {
+                pixel[c] = (pixel[c] as f32 * (1.0 - alpha) + o[c] as f32 * alpha).round() as u8;
+            }
+            pixel[3] = (pixel[3] as f32 * (1.0 - alpha) + 255.0 * alpha).round() as u8;
+        }
+    }
+}